@@ -0,0 +1,79 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(1) from_center: vec2<f32>,
+    @location(2) to_center: vec2<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) square_size: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // in squares, x along the arrow from its start, y across it
+    @location(0) local_position: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) length: f32,
+};
+
+// half widths of the shaft and the head, and the head's length, in squares
+const SHAFT: f32 = 0.1;
+const HEAD: f32 = 0.3;
+const HEAD_LENGTH: f32 = 0.5;
+// radius and half width of a circle's ring
+const RADIUS: f32 = 0.45;
+const RING: f32 = 0.05;
+
+@vertex
+fn vs_main(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
+    var out: VertexOutput;
+
+    let delta = instance.to_center - instance.from_center;
+    let length = length(delta) / instance.square_size;
+    // an arrow from a square to itself is a circle around it
+    let circle = length < 0.01;
+    let along = select(normalize(delta), vec2<f32>(1.0, 0.0), circle);
+    let across = vec2<f32>(-along.y, along.x);
+
+    // the unit quad stretched over the arrow, with a margin for antialiasing
+    var local = vec2<f32>(
+        mix(-0.1, length + 0.1, vertex.position.x),
+        mix(-HEAD - 0.1, HEAD + 0.1, vertex.position.y),
+    );
+    if circle {
+        local = mix(vec2<f32>(-0.6), vec2<f32>(0.6), vertex.position.xy);
+    }
+    let position = instance.from_center + (along * local.x + across * local.y) * instance.square_size;
+    out.clip_position = vec4<f32>(position, 0.0, 1.0);
+    out.local_position = local;
+    out.color = instance.color;
+    out.length = length;
+    return out;
+}
+
+// signed distance to the arrow, negative inside
+fn arrow(p: vec2<f32>, length: f32) -> f32 {
+    let head_length = min(HEAD_LENGTH, length * 0.5);
+    let base = length - head_length;
+
+    let shaft = max(abs(p.y) - SHAFT, max(-p.x, p.x - base));
+    // the head's slanted sides, and its base
+    let side = (abs(p.y) * head_length - HEAD * (length - p.x)) / sqrt(head_length * head_length + HEAD * HEAD);
+    let head = max(side, base - p.x);
+    return min(shaft, head);
+}
+
+fn ring(p: vec2<f32>) -> f32 {
+    return abs(length(p) - RADIUS) - RING;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = select(arrow(in.local_position, in.length), ring(in.local_position), in.length < 0.01);
+    let coverage = clamp(0.5 - distance / fwidth(distance), 0.0, 1.0);
+    if coverage <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
@@ -20,8 +20,10 @@ fn vs_main(
 struct GameInfo {
     time: f32,
     state: u32,
-    legal_moves_low: u32,
-    legal_moves_high: u32,
+    board_size: vec2<u32>,
+    legal_moves: vec4<u32>,
+    visible: vec4<u32>,
+    eval_bar: f32,
 };
 
 @group(0) @binding(0)
@@ -29,31 +31,46 @@ var<uniform> game_info: GameInfo;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // the evaluation bar's quad is left of the board's, white's share filled from the bottom
+    if in.local_position.x < -0.5 {
+        if game_info.eval_bar < 0.0 {
+            discard;
+        }
+        if in.local_position.y + 0.5 < game_info.eval_bar {
+            return vec4<f32>(0.93, 0.93, 0.93, 1.0);
+        }
+        return vec4<f32>(0.15, 0.15, 0.15, 1.0);
+    }
+
     let uv = in.local_position.xy * 0.5 + 0.5;
 
-    let board_size = vec2<f32>(0.5, 0.5);
+    let squares = vec2<f32>(game_info.board_size);
+    let board_size = squares * 0.5 / max(squares.x, squares.y);
     let board_start = vec2<f32>(0.5, 0.5) - board_size * 0.5;
     let board_end = board_start + board_size;
 
     if all(uv >= board_start) && all(uv <= board_end) {
         let local = (uv - board_start) / board_size;
 
-        let squares = 8.0;
-        let x = i32(floor(local.x * squares));
-        let y = i32(floor(local.y * squares));
-        let square = x + y;
+        let x = min(u32(floor(local.x * squares.x)), game_info.board_size.x - 1u);
+        let y = min(u32(floor(local.y * squares.y)), game_info.board_size.y - 1u);
+        let square = y * game_info.board_size.x + x;
 
         var color = vec4<f32>(0.40, 0.40, 0.40, 1.0);
 
-        if square % 2 == 0 {
+        if (x + y) % 2u == 0u {
             color = vec4<f32>(0.93, 0.93, 0.93, 1.0);
         }
 
-        if is_legal_move(u32(y * 8 + x)) {
+        if !is_visible(square) {
+            return mix(color, vec4<f32>(0.0, 0.0, 0.0, 1.0), 0.8);
+        }
+
+        if is_legal_move(square) {
             return mix(color, vec4<f32>(0.20, 0.80, 0.20, 1.0), 0.7);
         }
 
-        if is_selected(u32(y * 8 + x) + 1u) {
+        if is_selected(square + 1u) {
             return mix(color, vec4<f32>(0.80, 0.20, 0.80, 1.0), 0.7);
         }
 
@@ -64,13 +81,13 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 }
 
 fn is_legal_move(idx: u32) -> bool {
-    if idx < 32u {
-        return (game_info.legal_moves_low & (1u << idx)) != 0u;
-    } else {
-        return (game_info.legal_moves_high & (1u << (idx - 32u))) != 0u;
-    }
+    return (game_info.legal_moves[idx / 32u] & (1u << (idx % 32u))) != 0u;
+}
+
+fn is_visible(idx: u32) -> bool {
+    return (game_info.visible[idx / 32u] & (1u << (idx % 32u))) != 0u;
 }
 
 fn is_selected(idx: u32) -> bool {
-    return ((game_info.state >> 8u) & 0x7Fu) == idx;
+    return ((game_info.state >> 9u) & 0xFFu) == idx;
 }
\ No newline at end of file
@@ -0,0 +1,65 @@
+// Material and piece-square evaluation of a batch of positions, one invocation each, the same
+// as `Evaluation::placement` on the CPU.
+
+const MAX_PHASE: i32 = 24;
+
+struct Batch {
+    width: u32,
+    height: u32,
+    count: u32,
+    padding: u32,
+    // per piece code & 15: middlegame value, endgame value, phase weight, table
+    kinds: array<vec4<i32>, 16>,
+}
+
+@group(0) @binding(0)
+var<storage, read> batch: Batch;
+
+// middlegame then endgame table of every piece, 64 squares each starting at a8
+@group(0) @binding(1)
+var<storage, read> tables: array<i32>;
+
+// a byte per square, 0 when empty
+@group(0) @binding(2)
+var<storage, read> positions: array<array<u32, 32>>;
+
+// centipawns from white's point of view
+@group(0) @binding(3)
+var<storage, read_write> scores: array<i32>;
+
+fn taper(score: vec2<i32>, phase: i32) -> i32 {
+    return (score.x * phase + score.y * (MAX_PHASE - phase)) / MAX_PHASE;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= batch.count {
+        return;
+    }
+    var material = vec2<i32>(0);
+    var placement = vec2<i32>(0);
+    var phase = 0;
+    for (var square = 0u; square < batch.width * batch.height; square++) {
+        let piece = (positions[index][square / 4u] >> (square % 4u * 8u)) & 0xFFu;
+        if piece == 0u {
+            continue;
+        }
+        let kind = batch.kinds[piece & 15u];
+        let white = (piece & 16u) != 0u;
+        let sign = select(-1, 1, white);
+        phase += kind.z;
+        material += sign * kind.xy;
+
+        // tables are 8x8, other boards are stretched onto them
+        let file = square % batch.width * 8u / batch.width;
+        var rank = square / batch.width * 8u / batch.height;
+        if !white {
+            rank = 7u - rank;
+        }
+        let entry = u32(kind.w) * 128u + (7u - rank) * 8u + file;
+        placement += sign * vec2<i32>(tables[entry], tables[entry + 64u]);
+    }
+    phase = min(phase, MAX_PHASE);
+    scores[index] = taper(material, phase) + taper(placement, phase);
+}
@@ -0,0 +1,24 @@
+// Legal move masks for the renderer, one invocation per square.
+
+@group(0) @binding(1)
+var<storage, read_write> masks: array<vec4<u32>, MAX_SQUARES>;
+
+var<private> mask: vec4<u32>;
+
+fn found(origin: u32, to: u32) {
+    mask[to / 32u] |= 1u << (to % 32u);
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let square = id.x;
+    if square >= board.width * board.height {
+        return;
+    }
+    squares = board.squares;
+    flags = board.flags;
+    en_passant = board.en_passant;
+    mask = vec4<u32>(0u);
+    generate(square);
+    masks[square] = mask;
+}
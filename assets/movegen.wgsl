@@ -0,0 +1,296 @@
+// Move generation shared by the compute shaders, which are this file followed by their entry
+// points and a `found(origin, to)` that is handed every move. Mirrors `BoardState::legal_moves`:
+// the pieces of the side to play get their legal moves, the other side's ignore check.
+
+const MAX_SQUARES: u32 = 128u;
+const NO_SQUARE: u32 = 0xFFFFFFFFu;
+
+// Kind roles, everything else only moves by its directions.
+const PAWN: u32 = 1u;
+const KING: u32 = 2u;
+const ROOK: u32 = 3u;
+const FAIRY: u32 = 4u;
+
+struct Board {
+    width: u32,
+    height: u32,
+    // bit 0 white to play, bits 1-4 castling (white O-O, white O-O-O, black O-O, black O-O-O),
+    // bit 5 fog of war
+    flags: u32,
+    en_passant: u32,
+    // kinds of the queen, rook, bishop and knight, which pawns promote to
+    promotions: vec4<u32>,
+    // 0 = empty, otherwise bits 0-3 index into `kinds` and bit 4 is set for white
+    squares: array<u32, MAX_SQUARES>,
+    // first direction, direction count and role of every kind on the board
+    kinds: array<vec4<u32>, 16>,
+    // rank step, file step and whether the piece keeps sliding
+    directions: array<vec4<i32>, 128>,
+};
+
+@group(0) @binding(0)
+var<storage, read> board: Board;
+
+// the position moves are generated for, laid out like in `Board`; moves are tried on it and
+// put back after each one
+var<private> squares: array<u32, MAX_SQUARES>;
+var<private> flags: u32;
+var<private> en_passant: u32;
+// squares the piece being generated for could go to, before checking for check
+var<private> targets: vec4<u32>;
+
+fn is_white(piece: u32) -> bool {
+    return (piece & 16u) != 0u;
+}
+
+fn kind_of(piece: u32) -> vec4<u32> {
+    return board.kinds[piece & 15u];
+}
+
+fn role_of(piece: u32) -> u32 {
+    return kind_of(piece).z;
+}
+
+fn rank_of(square: u32) -> i32 {
+    return i32(square / board.width);
+}
+
+fn file_of(square: u32) -> i32 {
+    return i32(square % board.width);
+}
+
+fn square_at(rank: i32, file: i32) -> u32 {
+    if rank < 0 || rank >= i32(board.height) || file < 0 || file >= i32(board.width) {
+        return NO_SQUARE;
+    }
+    return u32(rank) * board.width + u32(file);
+}
+
+// every direction set is symmetric, so looking outward from `square` along a kind's directions
+// finds the pieces of that kind that attack it
+fn is_attacked(square: u32, by_white: bool) -> bool {
+    let rank = rank_of(square);
+    let file = file_of(square);
+    let pawn_rank = rank - select(-1, 1, by_white);
+    for (var df = -1; df <= 1; df += 2) {
+        let origin = square_at(pawn_rank, file + df);
+        if origin != NO_SQUARE {
+            let piece = squares[origin];
+            if piece != 0u && is_white(piece) == by_white && role_of(piece) == PAWN {
+                return true;
+            }
+        }
+    }
+    for (var kind = 1u; kind < 16u; kind++) {
+        let entry = board.kinds[kind];
+        for (var i = entry.x; i < entry.x + entry.y; i++) {
+            let direction = board.directions[i];
+            var r = rank;
+            var f = file;
+            loop {
+                r += direction.x;
+                f += direction.y;
+                let origin = square_at(r, f);
+                if origin == NO_SQUARE {
+                    break;
+                }
+                let piece = squares[origin];
+                if piece != 0u {
+                    if is_white(piece) == by_white && (piece & 15u) == kind {
+                        return true;
+                    }
+                    break;
+                }
+                if direction.z == 0 {
+                    break;
+                }
+            }
+        }
+    }
+    return false;
+}
+
+fn king_square(white: bool) -> u32 {
+    for (var square = 0u; square < board.width * board.height; square++) {
+        let piece = squares[square];
+        if piece != 0u && is_white(piece) == white && role_of(piece) == KING {
+            return square;
+        }
+    }
+    return NO_SQUARE;
+}
+
+// squares `is_legal` changed, with what was there before
+var<private> undo: array<vec2<u32>, 4>;
+var<private> undo_count: u32;
+
+fn put(square: u32, piece: u32) {
+    undo[undo_count] = vec2<u32>(square, squares[square]);
+    undo_count++;
+    squares[square] = piece;
+}
+
+// plays the move like `BoardState::make` and checks the mover's king is safe afterwards
+fn is_legal(origin: u32, to: u32) -> bool {
+    undo_count = 0u;
+    let piece = squares[origin];
+    let white = is_white(piece);
+    let width = board.width;
+    put(origin, 0u);
+    if role_of(piece) == PAWN && to == en_passant && file_of(origin) != file_of(to) {
+        put(select(to + width, to - width, white), 0u);
+    }
+    if role_of(piece) == KING && rank_of(origin) == rank_of(to) && abs(file_of(origin) - file_of(to)) >= 2 {
+        let back_rank = u32(rank_of(origin)) * width;
+        var rook_from = back_rank;
+        var rook_to = to + 1u;
+        if to > origin {
+            rook_from = back_rank + width - 1u;
+            rook_to = to - 1u;
+        }
+        let rook = squares[rook_from];
+        put(rook_from, 0u);
+        put(rook_to, rook);
+    }
+    put(to, piece);
+
+    let king = king_square(white);
+    let legal = king != NO_SQUARE && !is_attacked(king, !white);
+    for (var i = i32(undo_count) - 1; i >= 0; i--) {
+        squares[undo[i].x] = undo[i].y;
+    }
+    return legal;
+}
+
+fn mark(to: u32) {
+    targets[to / 32u] |= 1u << (to % 32u);
+}
+
+fn castling_target(square: u32, white: bool, king_file: u32, rook_file: u32) -> u32 {
+    let width = board.width;
+    let back_rank = select(board.height - 1u, 0u, white);
+    if u32(rank_of(square)) != back_rank {
+        return NO_SQUARE;
+    }
+    let rook_square = back_rank * width + rook_file;
+    let rook = squares[rook_square];
+    if rook == 0u || role_of(rook) != ROOK || is_white(rook) != white {
+        return NO_SQUARE;
+    }
+    let king_target = back_rank * width + king_file;
+    var rook_target = king_target + 1u;
+    if rook_file > king_file {
+        rook_target = king_target - 1u;
+    }
+
+    // everything between king and rook, and both landing squares, must be empty
+    let lowest = min(min(square, rook_square), min(king_target, rook_target));
+    let highest = max(max(square, rook_square), max(king_target, rook_target));
+    for (var between = lowest; between <= highest; between++) {
+        if between != square && between != rook_square && squares[between] != 0u {
+            return NO_SQUARE;
+        }
+    }
+
+    // and the king may not pass through an attacked square
+    if (flags & 32u) == 0u {
+        for (var passed = min(square, king_target); passed <= max(square, king_target); passed++) {
+            if is_attacked(passed, !white) {
+                return NO_SQUARE;
+            }
+        }
+    }
+    return king_target;
+}
+
+// hands every move of the piece on `square` to `found`
+fn generate(square: u32) {
+    let piece = squares[square];
+    let white_to_play = (flags & 1u) != 0u;
+    let fog_of_war = (flags & 32u) != 0u;
+    if piece == 0u || (is_white(piece) == white_to_play && king_square(white_to_play) == NO_SQUARE) {
+        return;
+    }
+    let white = is_white(piece);
+    let check = white == white_to_play && !fog_of_war;
+    let rank = rank_of(square);
+    let file = file_of(square);
+    let kind = kind_of(piece);
+    targets = vec4<u32>(0u);
+
+    if kind.z == PAWN {
+        let direction = select(-1, 1, white);
+        let start_rank = select(i32(board.height) - 2, 1, white);
+        let forward = square_at(rank + direction, file);
+        if forward != NO_SQUARE && squares[forward] == 0u {
+            mark(forward);
+            let double_forward = square_at(rank + 2 * direction, file);
+            if rank == start_rank && double_forward != NO_SQUARE && squares[double_forward] == 0u {
+                mark(double_forward);
+            }
+        }
+        for (var df = -1; df <= 1; df += 2) {
+            let capture = square_at(rank + direction, file + df);
+            if capture == NO_SQUARE {
+                continue;
+            }
+            let occupant = squares[capture];
+            if (occupant != 0u && is_white(occupant) != white) || (occupant == 0u && capture == en_passant) {
+                mark(capture);
+            }
+        }
+    }
+
+    for (var i = kind.x; i < kind.x + kind.y; i++) {
+        let direction = board.directions[i];
+        var r = rank;
+        var f = file;
+        loop {
+            r += direction.x;
+            f += direction.y;
+            let target_square = square_at(r, f);
+            if target_square == NO_SQUARE {
+                break;
+            }
+            let occupant = squares[target_square];
+            if occupant == 0u || is_white(occupant) != white {
+                mark(target_square);
+            }
+            if occupant != 0u || direction.z == 0 {
+                break;
+            }
+        }
+    }
+
+    if kind.z == KING && (fog_of_war || !is_attacked(square, !white)) {
+        let castling = flags >> select(3u, 1u, white);
+        // the king lands on the c and second-to-last files, the rook right beside it
+        if (castling & 2u) != 0u {
+            let king_target = castling_target(square, white, 2u, 0u);
+            if king_target != NO_SQUARE {
+                mark(king_target);
+            }
+        }
+        if (castling & 1u) != 0u {
+            let king_target = castling_target(square, white, board.width - 2u, board.width - 1u);
+            if king_target != NO_SQUARE {
+                mark(king_target);
+            }
+        }
+    }
+
+    // checked in this one place, the shader compiler inlines every call
+    for (var to = 0u; to < board.width * board.height; to++) {
+        if (targets[to / 32u] & (1u << (to % 32u))) == 0u {
+            continue;
+        }
+        // not folded into the condition above, the GL backend gets calls inside || wrong
+        var legal = true;
+        if check {
+            legal = is_legal(square, to);
+        }
+        if legal {
+            found(square, to);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+// Breadth-first perft, one invocation per position: `count_moves` counts the moves of every
+// position of a level, `expand` writes their children to where `offsets` says.
+
+// squares packed four to a word, a byte each
+struct Position {
+    squares: array<u32, 32>,
+    flags: u32,
+    en_passant: u32,
+};
+
+// the range of `positions` this dispatch works on
+struct Params {
+    first: u32,
+    count: u32,
+};
+
+@group(0) @binding(1)
+var<uniform> params: Params;
+
+@group(0) @binding(2)
+var<storage, read> positions: array<Position>;
+
+@group(0) @binding(3)
+var<storage, read_write> counts: array<u32>;
+
+@group(0) @binding(4)
+var<storage, read> offsets: array<u32>;
+
+@group(0) @binding(5)
+var<storage, read_write> children: array<Position>;
+
+var<private> expanding: bool;
+var<private> moves: u32;
+var<private> next: u32;
+
+fn load(index: u32) {
+    let position = positions[index];
+    for (var square = 0u; square < MAX_SQUARES; square++) {
+        squares[square] = (position.squares[square / 4u] >> (square % 4u * 8u)) & 0xFFu;
+    }
+    flags = position.flags;
+    en_passant = position.en_passant;
+}
+
+fn generate_all() {
+    let white_to_play = (flags & 1u) != 0u;
+    for (var square = 0u; square < board.width * board.height; square++) {
+        let piece = squares[square];
+        if piece != 0u && is_white(piece) == white_to_play {
+            generate(square);
+        }
+    }
+}
+
+fn found(origin: u32, to: u32) {
+    let piece = squares[origin];
+    let last_rank = select(0u, board.height - 1u, is_white(piece));
+    if role_of(piece) != PAWN || u32(rank_of(to)) != last_rank {
+        emit(origin, to, 0u);
+        return;
+    }
+    // like `BoardState::promotion_kinds`, the standard pieces and any fairy piece in play
+    for (var i = 0u; i < 4u; i++) {
+        emit(origin, to, board.promotions[i]);
+    }
+    for (var kind = 1u; kind < 16u; kind++) {
+        if board.kinds[kind].z == FAIRY && in_play(kind) {
+            emit(origin, to, kind);
+        }
+    }
+}
+
+fn in_play(kind: u32) -> bool {
+    for (var square = 0u; square < board.width * board.height; square++) {
+        if squares[square] != 0u && (squares[square] & 15u) == kind {
+            return true;
+        }
+    }
+    return false;
+}
+
+fn revoke_castling(castling: u32, corner: u32) -> u32 {
+    let width = board.width;
+    let last_rank = board.height - 1u;
+    if corner == 0u {
+        return castling & ~4u;
+    } else if corner == width - 1u {
+        return castling & ~2u;
+    } else if corner == last_rank * width {
+        return castling & ~16u;
+    } else if corner == last_rank * width + width - 1u {
+        return castling & ~8u;
+    }
+    return castling;
+}
+
+// plays the move like `BoardState::make` into the next free child
+fn emit(origin: u32, to: u32, promotion: u32) {
+    if !expanding {
+        moves++;
+        return;
+    }
+    var child = squares;
+    var child_flags = flags ^ 1u;
+    var child_en_passant = NO_SQUARE;
+    let width = board.width;
+    let piece = child[origin];
+    let white = is_white(piece);
+    var moved = piece;
+    child[origin] = 0u;
+    switch role_of(piece) {
+        case PAWN: {
+            if to == en_passant && file_of(origin) != file_of(to) {
+                child[select(to + width, to - width, white)] = 0u;
+            }
+            if abs(rank_of(origin) - rank_of(to)) == 2 {
+                child_en_passant = (origin + to) / 2u;
+            }
+            if promotion != 0u {
+                moved = promotion | (piece & 16u);
+            }
+        }
+        case KING: {
+            child_flags &= ~select(24u, 6u, white);
+            if rank_of(origin) == rank_of(to) && abs(file_of(origin) - file_of(to)) >= 2 {
+                let back_rank = u32(rank_of(origin)) * width;
+                var rook_from = back_rank;
+                var rook_to = to + 1u;
+                if to > origin {
+                    rook_from = back_rank + width - 1u;
+                    rook_to = to - 1u;
+                }
+                let rook = child[rook_from];
+                child[rook_from] = 0u;
+                child[rook_to] = rook;
+            }
+        }
+        case ROOK: {
+            child_flags = revoke_castling(child_flags, origin);
+        }
+        default: {}
+    }
+    // a rook captured in its corner takes the castling right with it
+    child_flags = revoke_castling(child_flags, to);
+    child[to] = moved;
+
+    let index = next + moves;
+    for (var word = 0u; word < 32u; word++) {
+        children[index].squares[word] = child[word * 4u]
+            | (child[word * 4u + 1u] << 8u)
+            | (child[word * 4u + 2u] << 16u)
+            | (child[word * 4u + 3u] << 24u);
+    }
+    children[index].flags = child_flags;
+    children[index].en_passant = child_en_passant;
+    moves++;
+}
+
+@compute @workgroup_size(64)
+fn count_moves(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.count {
+        return;
+    }
+    let index = params.first + id.x;
+    load(index);
+    expanding = false;
+    moves = 0u;
+    generate_all();
+    counts[index] = moves;
+}
+
+@compute @workgroup_size(64)
+fn expand(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.count {
+        return;
+    }
+    let index = params.first + id.x;
+    load(index);
+    expanding = true;
+    moves = 0u;
+    next = offsets[index];
+    generate_all();
+}
@@ -15,9 +15,11 @@ struct VertexOutput {
     @location(3) white: u32,
 };
 
-const tile_size: vec2<f32> = vec2<f32>(6.0, 2.0);
+// set from the number of sprites in the atlas, 6 per row
+override atlas_rows: f32 = 2.0;
 
 fn get_uv(index: u32, position: vec2<f32>) -> vec2<f32> {
+    let tile_size = vec2<f32>(6.0, atlas_rows);
     let x = f32(index % u32(tile_size.x));
     let y = f32(index / u32(tile_size.x));
 
@@ -28,7 +30,9 @@ fn get_uv(index: u32, position: vec2<f32>) -> vec2<f32> {
 fn vs_main(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
 
-    out.clip_position = vec4<f32>(vertex.position + instance.position, 1.0);
+    // the quad is sized for 8 squares across
+    let scale = 8.0 / f32(max(game_info.board_size.x, game_info.board_size.y));
+    out.clip_position = vec4<f32>(vertex.position * scale + instance.position, 1.0);
     out.local_position = instance.position;
     out.uv = get_uv(instance_piece(instance.data), vertex.position.xy * 8.2);
     out.idx = instance_index(instance.data) + 1u;
@@ -46,8 +50,10 @@ var pieces_sampler: sampler;
 struct GameInfo {
     time: f32,
     state: u32,
-    legal_moves_low: u32,
-    legal_moves_high: u32,
+    board_size: vec2<u32>,
+    legal_moves: vec4<u32>,
+    visible: vec4<u32>,
+    eval_bar: f32,
 };
 
 @group(1) @binding(0)
@@ -70,6 +76,9 @@ fn hsl_to_rgb(hsl: vec3<f32>) -> vec3<f32> {
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if !is_visible(in.idx - 1u) {
+        discard;
+    }
     let texture = textureSample(pieces_texture, pieces_sampler, in.uv);
     if is_selected(in.idx) && is_hovered(in.idx) {
         if length(texture - textureSample(pieces_texture, pieces_sampler, in.uv - 0.002)) > 0.1 {
@@ -98,12 +107,16 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     }
 }
 
+fn is_visible(idx: u32) -> bool {
+    return (game_info.visible[idx / 32u] & (1u << (idx % 32u))) != 0u;
+}
+
 fn is_selected(idx: u32) -> bool {
-    return ((game_info.state >> 8u) & 0x7Fu) == idx;
+    return ((game_info.state >> 9u) & 0xFFu) == idx;
 }
 
 fn is_hovered(idx: u32) -> bool {
-    return ((game_info.state >> 1u) & 0x7Fu) == idx;
+    return ((game_info.state >> 1u) & 0xFFu) == idx;
 }
 
 fn white_to_play() -> u32 {
@@ -115,9 +128,9 @@ fn instance_white(data: u32) -> u32 {
 }
 
 fn instance_piece(data: u32) -> u32 {
-    return (data >> 1u) & 0xFu;
+    return (data >> 1u) & 0x7Fu;
 }
 
 fn instance_index(data: u32) -> u32 {
-    return (data >> 5u) & 0x3Fu;
+    return (data >> 8u) & 0xFFu;
 }
\ No newline at end of file
@@ -14,6 +14,8 @@ struct VertexOutput {
     @location(0) local_position: vec3<f32>,
     @location(1) uv: vec2<f32>,
     @location(2) idx: u32,
+    // 0 for labels, 1 for the move list and 2 for its current move
+    @location(3) style: u32,
 };
 
 const font_size: vec2<f32> = vec2<f32>(6.0, 10.0);
@@ -35,11 +37,17 @@ fn get_uv(index: u32, position: vec2<f32>) -> vec2<f32> {
 fn vs_main(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
 
+    // the character is in the low half of idx, the style in the high half
+    let style = instance.idx >> 16u;
+    // the move list's characters are smaller, in the font's proportions
+    let scale = select(vec3<f32>(1.0), vec3<f32>(0.24, 0.4, 1.0), style != 0u);
+
     let local_uv = offsets[vertex.vertex_index % 4u];
-    out.clip_position = vec4<f32>(instance.position + vertex.position, 1.0);
+    out.clip_position = vec4<f32>(instance.position + vertex.position * scale, 1.0);
     out.local_position = instance.position;
-    out.uv = get_uv(instance.idx, vec2<f32>(local_uv.x, 1.0 - local_uv.y));
+    out.uv = get_uv(instance.idx & 0xFFFFu, vec2<f32>(local_uv.x, 1.0 - local_uv.y));
     out.idx = instance.instance_index;
+    out.style = style;
 
     return out;
 }
@@ -52,8 +60,10 @@ var text_sampler: sampler;
 struct GameInfo {
     time: f32,
     state: u32,
-    legal_moves_low: u32,
-    legal_moves_high: u32,
+    board_size: vec2<u32>,
+    legal_moves: vec4<u32>,
+    visible: vec4<u32>,
+    eval_bar: f32,
 };
 
 @group(1) @binding(0)
@@ -74,8 +84,21 @@ fn hsl_to_rgb(hsl: vec3<f32>) -> vec3<f32> {
     return rgb + vec3<f32>(m);
 }
 
+const MOVE_COLOR: vec3<f32> = vec3<f32>(0.9, 0.9, 0.9);
+const CURRENT_MOVE_BACKGROUND: vec3<f32> = vec3<f32>(0.9, 0.75, 0.2);
+const CURRENT_MOVE_COLOR: vec3<f32> = vec3<f32>(0.1, 0.1, 0.1);
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let glyph = textureSample(text_texture, text_sampler, in.uv);
+    if in.style == 1u {
+        return glyph * vec4<f32>(MOVE_COLOR, 1.0);
+    }
+    // the current move sits on a box of its own
+    if in.style == 2u {
+        return vec4<f32>(mix(CURRENT_MOVE_BACKGROUND, CURRENT_MOVE_COLOR, glyph.a), 1.0);
+    }
+
     var rgb = hsl_to_rgb(vec3<f32>(sin(game_info.time + length(in.local_position.xy)) * 0.5 + 0.5, 1.0, 0.5));
 
     if white_to_play() == in.idx / 5u { // tacky solution
@@ -86,7 +109,7 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         rgb = vec3<f32>(0.0, 1.0, 0.0);
     }
 
-    return textureSample(text_texture, text_sampler, in.uv) * vec4<f32>(rgb, 1.0);
+    return glyph * vec4<f32>(rgb, 1.0);
 }
 
 fn white_to_play() -> u32 {
@@ -94,5 +117,5 @@ fn white_to_play() -> u32 {
 }
 
 fn game_over() -> bool {
-    return ((game_info.state >> 15u) & 0x3u) != 0;
+    return ((game_info.state >> 17u) & 0x3u) != 0;
 }
\ No newline at end of file
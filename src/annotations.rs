@@ -0,0 +1,264 @@
+use winit::keyboard::ModifiersState;
+
+use crate::logic::BoardState;
+
+/// Colours of drawn marks, with the letters PGN's `[%csl]` and `[%cal]` commands write them as.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MarkColor {
+    Green,
+    Red,
+    Yellow,
+    Blue,
+}
+
+impl MarkColor {
+    /// The colour other GUIs give the held modifiers: Shift or Ctrl red, Alt blue, Shift with
+    /// Alt yellow and green without any.
+    pub fn from_modifiers(modifiers: ModifiersState) -> Self {
+        let shift = modifiers.shift_key() || modifiers.control_key();
+        match (shift, modifiers.alt_key()) {
+            (true, true) => Self::Yellow,
+            (true, false) => Self::Red,
+            (false, true) => Self::Blue,
+            (false, false) => Self::Green,
+        }
+    }
+
+    pub fn letter(self) -> char {
+        match self {
+            Self::Green => 'G',
+            Self::Red => 'R',
+            Self::Yellow => 'Y',
+            Self::Blue => 'B',
+        }
+    }
+
+    pub fn from_letter(letter: char) -> Option<Self> {
+        match letter {
+            'G' => Some(Self::Green),
+            'R' => Some(Self::Red),
+            'Y' => Some(Self::Yellow),
+            'B' => Some(Self::Blue),
+            _ => None,
+        }
+    }
+
+    pub fn rgba(self) -> [f32; 4] {
+        match self {
+            Self::Green => [0.1, 0.6, 0.1, 0.75],
+            Self::Red => [0.85, 0.1, 0.1, 0.75],
+            Self::Yellow => [0.9, 0.7, 0.05, 0.75],
+            Self::Blue => [0.1, 0.3, 0.85, 0.75],
+        }
+    }
+}
+
+/// Circles and arrows drawn on a position by hand.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Annotations {
+    pub circles: Vec<(u32, MarkColor)>,
+    pub arrows: Vec<(u32, u32, MarkColor)>,
+}
+
+impl Annotations {
+    /// Draws a circle when `from` is `to`, an arrow otherwise. Drawing a mark again takes it
+    /// away, drawing it in another colour recolours it.
+    pub fn toggle(&mut self, from: u32, to: u32, color: MarkColor) {
+        if from == to {
+            match self.circles.iter().position(|&(square, _)| square == from) {
+                Some(index) if self.circles[index].1 == color => {
+                    self.circles.remove(index);
+                }
+                Some(index) => self.circles[index].1 = color,
+                None => self.circles.push((from, color)),
+            }
+        } else {
+            match self
+                .arrows
+                .iter()
+                .position(|&(start, end, _)| (start, end) == (from, to))
+            {
+                Some(index) if self.arrows[index].2 == color => {
+                    self.arrows.remove(index);
+                }
+                Some(index) => self.arrows[index].2 = color,
+                None => self.arrows.push((from, to, color)),
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.circles.is_empty() && self.arrows.is_empty()
+    }
+
+    /// Everything as arrows for `arrows::Arrows`, circles being arrows from a square to itself.
+    pub fn shapes(&self) -> impl Iterator<Item = (u32, u32, [f32; 4])> + '_ {
+        self.circles
+            .iter()
+            .map(|&(square, color)| (square, square, color.rgba()))
+            .chain(
+                self.arrows
+                    .iter()
+                    .map(|&(from, to, color)| (from, to, color.rgba())),
+            )
+    }
+
+    /// Reads the `[%csl]` and `[%cal]` commands out of a PGN comment, returning the marks and
+    /// the rest of the comment. Marks on squares `board` doesn't have are dropped.
+    pub fn from_comment(comment: &str, board: &BoardState) -> (Self, String) {
+        let mut annotations = Self::default();
+        let mut rest = String::new();
+        let mut text = comment;
+        while let Some(start) = text.find("[%") {
+            rest += &text[..start];
+            let Some(end) = text[start..].find(']') else {
+                text = &text[start..];
+                break;
+            };
+            let command = &text[start + 2..start + end];
+            text = &text[start + end + 1..];
+            let (name, marks) = command.split_once(' ').unwrap_or((command, ""));
+            if name != "csl" && name != "cal" {
+                // other commands, e.g. clocks, stay in the comment
+                rest += &format!("[%{command}]");
+                continue;
+            }
+            for mark in marks.split(',').map(str::trim) {
+                let mut chars = mark.chars();
+                let Some(color) = chars.next().and_then(MarkColor::from_letter) else {
+                    continue;
+                };
+                let squares = chars.as_str();
+                let Some(tail) = squares.get(1..) else {
+                    continue;
+                };
+                // the second square starts at the second letter
+                let split = tail
+                    .find(|c: char| c.is_ascii_alphabetic())
+                    .map_or(squares.len(), |index| index + 1);
+                let from = board.parse_square(&squares[..split]);
+                let to = board.parse_square(&squares[split..]);
+                match (name, from, to) {
+                    ("csl", Some(square), None) => annotations.circles.push((square, color)),
+                    ("cal", Some(from), Some(to)) => annotations.arrows.push((from, to, color)),
+                    _ => {}
+                }
+            }
+        }
+        rest += text;
+        (
+            annotations,
+            rest.split_whitespace().collect::<Vec<_>>().join(" "),
+        )
+    }
+
+    /// The marks as PGN comment commands, e.g. `[%csl Ge4,Rd5][%cal Ge2e4]`. Empty when
+    /// nothing is drawn.
+    pub fn commands(&self, board: &BoardState) -> String {
+        let mut commands = String::new();
+        if !self.circles.is_empty() {
+            let circles = self
+                .circles
+                .iter()
+                .map(|&(square, color)| format!("{}{}", color.letter(), board.square_name(square)))
+                .collect::<Vec<_>>();
+            commands += &format!("[%csl {}]", circles.join(","));
+        }
+        if !self.arrows.is_empty() {
+            let arrows = self
+                .arrows
+                .iter()
+                .map(|&(from, to, color)| {
+                    format!(
+                        "{}{}{}",
+                        color.letter(),
+                        board.square_name(from),
+                        board.square_name(to)
+                    )
+                })
+                .collect::<Vec<_>>();
+            commands += &format!("[%cal {}]", arrows.join(","));
+        }
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::STARTING_FEN;
+
+    #[test]
+    fn round_trips_comments() {
+        let board = BoardState::from_fen(STARTING_FEN).unwrap();
+        let square = |name| board.parse_square(name).unwrap();
+        let comment = "[%csl Ge4,Rd5][%cal Ge2e4]";
+        let (annotations, rest) = Annotations::from_comment(comment, &board);
+        assert_eq!(
+            annotations.circles,
+            [
+                (square("e4"), MarkColor::Green),
+                (square("d5"), MarkColor::Red)
+            ]
+        );
+        assert_eq!(
+            annotations.arrows,
+            [(square("e2"), square("e4"), MarkColor::Green)]
+        );
+        assert_eq!(rest, "");
+        assert_eq!(annotations.commands(&board), comment);
+        assert_eq!(Annotations::default().commands(&board), "");
+    }
+
+    #[test]
+    fn keeps_other_commands_in_the_text() {
+        let board = BoardState::from_fen(STARTING_FEN).unwrap();
+        let (annotations, rest) =
+            Annotations::from_comment("Good move [%clk 0:04:58] [%cal Bg1f3] here", &board);
+        assert_eq!(annotations.arrows.len(), 1);
+        assert_eq!(rest, "Good move [%clk 0:04:58] here");
+    }
+
+    #[test]
+    fn reads_squares_the_board_has() {
+        let board = BoardState::from_fen(STARTING_FEN).unwrap();
+        let (annotations, _) =
+            Annotations::from_comment("[%csl Gi9,Ye4,Xe5][%cal Ga1i9,Ra1]", &board);
+        let e4 = board.parse_square("e4").unwrap();
+        assert_eq!(annotations.circles, [(e4, MarkColor::Yellow)]);
+        assert!(annotations.arrows.is_empty());
+
+        // ten files and ten ranks
+        let board = BoardState::from_fen("4k5/10/10/10/10/10/10/10/10/4K5 w - - 0 1").unwrap();
+        let square = |name| board.parse_square(name).unwrap();
+        let comment = "[%csl Bj10][%cal Ga1j10,Rj10a1]";
+        let (annotations, _) = Annotations::from_comment(comment, &board);
+        assert_eq!(annotations.circles, [(square("j10"), MarkColor::Blue)]);
+        assert_eq!(
+            annotations.arrows,
+            [
+                (square("a1"), square("j10"), MarkColor::Green),
+                (square("j10"), square("a1"), MarkColor::Red),
+            ]
+        );
+        assert_eq!(annotations.commands(&board), comment);
+    }
+
+    #[test]
+    fn toggles_marks() {
+        let mut annotations = Annotations::default();
+        annotations.toggle(4, 4, MarkColor::Green);
+        annotations.toggle(12, 28, MarkColor::Green);
+        assert_eq!(annotations.circles, [(4, MarkColor::Green)]);
+        assert_eq!(annotations.arrows, [(12, 28, MarkColor::Green)]);
+
+        // another colour recolours, the same colour takes it away
+        annotations.toggle(4, 4, MarkColor::Red);
+        annotations.toggle(12, 28, MarkColor::Blue);
+        assert_eq!(annotations.circles, [(4, MarkColor::Red)]);
+        assert_eq!(annotations.arrows, [(12, 28, MarkColor::Blue)]);
+        annotations.toggle(4, 4, MarkColor::Red);
+        annotations.toggle(12, 28, MarkColor::Blue);
+        assert!(annotations.is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+/// What an arrow shows, each source's arrows are replaced together.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Source {
+    /// First moves of the analysis' lines.
+    Analysis,
+    Book,
+    /// What the opponent would play if it were its turn.
+    Threat,
+    /// Circles and arrows drawn with the right mouse button.
+    User,
+}
+
+pub const ANALYSIS_COLOR: [f32; 4] = [0.15, 0.55, 0.15, 0.8];
+pub const BOOK_COLOR: [f32; 4] = [0.15, 0.35, 0.8, 0.8];
+pub const THREAT_COLOR: [f32; 4] = [0.8, 0.15, 0.15, 0.8];
+
+/// One arrow as `assets/arrows.wgsl` draws it, between two square centres in clip space.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Arrow {
+    from: [f32; 2],
+    to: [f32; 2],
+    color: [f32; 4],
+    square_size: f32,
+}
+
+impl Arrow {
+    pub fn new(from: [f32; 2], to: [f32; 2], color: [f32; 4], square_size: f32) -> Self {
+        Self {
+            from,
+            to,
+            color,
+            square_size,
+        }
+    }
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<Arrow>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: 8,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: 16,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: 32,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
+        }
+    }
+}
+
+/// Arrows between squares, by source.
+#[derive(Default)]
+pub struct Arrows {
+    arrows: Vec<(Source, u32, u32, [f32; 4])>,
+}
+
+impl Arrows {
+    /// Replaces the arrows of `source` with `arrows`, given as from, to and colour. An arrow
+    /// from a square to itself is drawn as a circle around it.
+    pub fn set(&mut self, source: Source, arrows: impl IntoIterator<Item = (u32, u32, [f32; 4])>) {
+        self.arrows.retain(|arrow| arrow.0 != source);
+        self.arrows.extend(
+            arrows
+                .into_iter()
+                .map(|(from, to, color)| (source, from, to, color)),
+        );
+    }
+
+    pub fn clear(&mut self, source: Source) {
+        self.set(source, []);
+    }
+
+    /// Instances to draw, `center` being a square's centre in clip space.
+    pub fn instances(&self, center: impl Fn(u32) -> [f32; 2], square_size: f32) -> Vec<Arrow> {
+        self.arrows
+            .iter()
+            .map(|&(_, from, to, color)| Arrow::new(center(from), center(to), color, square_size))
+            .collect()
+    }
+}
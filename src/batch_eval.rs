@@ -0,0 +1,273 @@
+use std::sync::Mutex;
+
+use wgpu::util::DeviceExt;
+
+use crate::eval;
+use crate::gpu;
+use crate::logic::BoardState;
+
+// the low four bits of a piece code pick the kind, 0 is an empty square
+const MAX_KINDS: usize = 16;
+const MAX_SQUARES: usize = 128;
+
+/// What `assets/eval.wgsl` needs to know about every position of a batch.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Batch {
+    width: u32,
+    height: u32,
+    count: u32,
+    padding: u32,
+    kinds: [[i32; 4]; MAX_KINDS],
+}
+
+impl Batch {
+    /// Packs `boards` with their squares a byte each, they have to be the same size.
+    fn pack(boards: &[BoardState]) -> anyhow::Result<(Self, Vec<[u32; MAX_SQUARES / 4]>)> {
+        let (width, height) = (boards[0].width, boards[0].height);
+        if (width * height) as usize > MAX_SQUARES {
+            return Err(anyhow::anyhow!(
+                "{width}x{height} is too big to evaluate on the GPU"
+            ));
+        }
+        let mut batch = Batch {
+            width,
+            height,
+            count: boards.len() as u32,
+            padding: 0,
+            kinds: [[0; 4]; MAX_KINDS],
+        };
+        let mut kinds = vec![None];
+        let mut positions = Vec::with_capacity(boards.len());
+        for board in boards {
+            if (board.width, board.height) != (width, height) {
+                return Err(anyhow::anyhow!("boards of a batch differ in size"));
+            }
+            let mut squares = [0; MAX_SQUARES / 4];
+            for (square, piece) in board.pieces.iter().enumerate() {
+                let Some(piece) = piece else {
+                    continue;
+                };
+                let index = match kinds.iter().position(|&kind| kind == Some(piece.kind)) {
+                    Some(index) => index,
+                    None if kinds.len() < MAX_KINDS => {
+                        let value = eval::piece_value(&piece.kind);
+                        batch.kinds[kinds.len()] = [
+                            value.mg,
+                            value.eg,
+                            eval::phase_weight(&piece.kind),
+                            eval::piece_table(&piece.kind) as i32,
+                        ];
+                        kinds.push(Some(piece.kind));
+                        kinds.len() - 1
+                    }
+                    None => return Err(anyhow::anyhow!("too many kinds of pieces in a batch")),
+                };
+                let code = index as u32 | (piece.white as u32) << 4;
+                squares[square / 4] |= code << (square % 4 * 8);
+            }
+            positions.push(squares);
+        }
+        Ok((batch, positions))
+    }
+}
+
+// sized for `capacity` positions, replaced when a bigger batch comes
+struct Buffers {
+    capacity: usize,
+    positions: wgpu::Buffer,
+    scores: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Evaluates positions in batches on the GPU, a compute invocation each. So far it knows
+/// material and piece-square tables, the terms of `Evaluation::placement`.
+pub struct GpuEvaluator {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    batch: wgpu::Buffer,
+    tables: wgpu::Buffer,
+    buffers: Mutex<Option<Buffers>>,
+}
+
+impl GpuEvaluator {
+    /// Evaluates on `device`, which may be the window's.
+    pub fn new(device: wgpu::Device, queue: wgpu::Queue) -> anyhow::Result<Self> {
+        let shader = gpu::shader(&device, "Eval Shader", &["eval.wgsl"])?;
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Eval Pipeline"),
+            layout: None,
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        let batch = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Eval Batch Buffer"),
+            size: size_of::<Batch>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let tables = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Piece Square Table Buffer"),
+            contents: bytemuck::cast_slice(&eval::PIECE_SQUARE_TABLES),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+            batch,
+            tables,
+            buffers: Mutex::new(None),
+        })
+    }
+
+    /// Evaluates on a device of its own, for the engine without a window.
+    pub fn headless() -> anyhow::Result<Self> {
+        let (device, queue) = gpu::headless()?;
+        Self::new(device, queue)
+    }
+
+    /// Centipawn scores of `boards` from white's point of view, in order.
+    pub fn evaluate(&self, boards: &[BoardState]) -> anyhow::Result<Vec<i32>> {
+        if boards.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (batch, positions) = Batch::pack(boards)?;
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers
+            .as_ref()
+            .is_none_or(|buffers| buffers.capacity < boards.len())
+        {
+            *buffers = Some(self.buffers(boards.len().next_power_of_two()));
+        }
+        let buffers = buffers.as_ref().unwrap();
+
+        self.queue
+            .write_buffer(&self.batch, 0, bytemuck::bytes_of(&batch));
+        self.queue
+            .write_buffer(&buffers.positions, 0, bytemuck::cast_slice(&positions));
+        let size = (boards.len() * 4) as wgpu::BufferAddress;
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Eval Encoder"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Eval Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &buffers.bind_group, &[]);
+            pass.dispatch_workgroups((boards.len() as u32).div_ceil(64), 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&buffers.scores, 0, &buffers.readback, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        let slice = buffers.readback.slice(..size);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        receiver.recv()??;
+        let scores = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        buffers.readback.unmap();
+        Ok(scores)
+    }
+
+    fn buffers(&self, capacity: usize) -> Buffers {
+        let buffer = |label, size, usage| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (capacity * size) as wgpu::BufferAddress,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let positions = buffer(
+            "Eval Position Buffer",
+            MAX_SQUARES,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+        let scores = buffer(
+            "Eval Score Buffer",
+            4,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+        let readback = buffer(
+            "Eval Readback Buffer",
+            4,
+            wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        );
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Eval Bind Group"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.batch.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.tables.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: positions.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: scores.as_entire_binding(),
+                },
+            ],
+        });
+        Buffers {
+            capacity,
+            positions,
+            scores,
+            readback,
+            bind_group,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Evaluation;
+    use crate::logic::{CAPABLANCA_FEN, STARTING_FEN};
+
+    #[test]
+    fn gpu_matches_cpu() {
+        let Some(evaluator) = GpuEvaluator::headless()
+            .inspect_err(|e| eprintln!("{e}, skipping"))
+            .ok()
+        else {
+            return;
+        };
+        for fen in [
+            STARTING_FEN,
+            CAPABLANCA_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "4k3/1P6/8/8/8/8/6p1/1a2K2C w - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ] {
+            let board = BoardState::from_fen(fen).unwrap();
+            let mut boards = vec![board.clone()];
+            for mv in board.moves() {
+                let mut child = board.clone();
+                child.make(mv);
+                boards.push(child);
+            }
+            let scores = evaluator.evaluate(&boards).unwrap();
+            for (board, score) in boards.iter().zip(scores) {
+                assert_eq!(score, Evaluation::placement(board).total(), "{fen}");
+            }
+        }
+    }
+}
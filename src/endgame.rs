@@ -0,0 +1,734 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::logic::{BoardState, Piece, PieceKind};
+
+const MAGIC: &[u8; 4] = b"CGTB";
+const VERSION: u8 = 1;
+const EXTENSION: &str = "cgtb";
+
+// One byte per position: 0 is a draw, 1 to 127 mate in that many moves and LOSS plus n mated
+// after n moves. BROKEN marks illegal positions and symmetric copies of stored ones.
+const DRAW: u8 = 0;
+const MAX_WIN: u8 = 127;
+const LOSS: u8 = 128;
+const UNKNOWN: u8 = 254;
+const BROKEN: u8 = 255;
+
+// where the white king may stand, the rest of the board being a mirror image of it
+const TRIANGLE: [u32; 10] = [0, 1, 2, 3, 9, 10, 11, 18, 19, 27];
+
+/// Distance to mate with perfect play, counted in moves of the side to play.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Dtm {
+    /// Mates on its `n`th move.
+    Win(u32),
+    Draw,
+    /// Gets mated after `n` moves of its own, 0 when mated already.
+    Loss(u32),
+}
+
+impl Dtm {
+    fn from_byte(value: u8) -> Option<Self> {
+        match value {
+            DRAW => Some(Dtm::Draw),
+            1..=MAX_WIN => Some(Dtm::Win(value as u32)),
+            LOSS..UNKNOWN => Some(Dtm::Loss((value - LOSS) as u32)),
+            _ => None,
+        }
+    }
+
+    fn to_byte(self) -> anyhow::Result<u8> {
+        match self {
+            Dtm::Draw => Ok(DRAW),
+            Dtm::Win(n) if n <= MAX_WIN as u32 => Ok(n as u8),
+            Dtm::Loss(n) if n < (UNKNOWN - LOSS) as u32 => Ok(LOSS + n as u8),
+            _ => Err(anyhow::anyhow!("{self:?} is too long to store")),
+        }
+    }
+
+    /// What the position is worth to the side that moved into it.
+    fn before_move(self) -> Self {
+        match self {
+            Dtm::Win(n) => Dtm::Loss(n),
+            Dtm::Draw => Dtm::Draw,
+            Dtm::Loss(n) => Dtm::Win(n + 1),
+        }
+    }
+
+    // higher is better for the side to play: quick wins, then draws, then slow losses
+    fn rank(self) -> i32 {
+        match self {
+            Dtm::Win(n) => 1000 - n as i32,
+            Dtm::Draw => 0,
+            Dtm::Loss(n) => -1000 + n as i32,
+        }
+    }
+}
+
+/// Pieces besides the kings, white's and black's, strongest first.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Material([Vec<PieceKind>; 2]);
+
+const KINDS: [PieceKind; 5] = [
+    PieceKind::Queen,
+    PieceKind::Rook,
+    PieceKind::Bishop,
+    PieceKind::Knight,
+    PieceKind::Pawn,
+];
+
+impl Material {
+    /// Reads names like `KRPvKR`.
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::anyhow!("{name:?} is not a material like KQvK");
+        let (white, black) = name.split_once('v').ok_or_else(invalid)?;
+        let side = |side: &str| -> anyhow::Result<Vec<PieceKind>> {
+            let pieces = side.strip_prefix('K').ok_or_else(invalid)?;
+            let mut kinds = pieces
+                .chars()
+                .map(|c| match Piece::from_char(c).ok_or_else(invalid)?.kind {
+                    kind @ (PieceKind::King | PieceKind::Fairy(_)) => {
+                        Err(anyhow::anyhow!("{kind:?} can't be in a table"))
+                    }
+                    kind => Ok(kind),
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            kinds.sort_by_key(|kind| KINDS.iter().position(|k| k == kind));
+            Ok(kinds)
+        };
+        if !name.chars().all(|c| c == 'v' || "KQRBNP".contains(c)) {
+            return Err(invalid());
+        }
+        Ok(Self([side(white)?, side(black)?]))
+    }
+
+    fn of(board: &BoardState) -> Option<Self> {
+        let mut sides = [Vec::new(), Vec::new()];
+        let mut kings = 0;
+        for piece in board.pieces.iter().flatten() {
+            match piece.kind {
+                PieceKind::King => kings += 1,
+                PieceKind::Fairy(_) => return None,
+                kind => sides[!piece.white as usize].push(kind),
+            }
+        }
+        for side in &mut sides {
+            side.sort_by_key(|kind| KINDS.iter().position(|k| k == kind));
+        }
+        (kings == 2).then_some(Self(sides))
+    }
+
+    fn name(&self) -> String {
+        let side = |kinds: &[PieceKind]| {
+            let pieces = kinds
+                .iter()
+                .map(|&kind| Piece { kind, white: true }.to_char())
+                .collect::<String>();
+            format!("K{pieces}")
+        };
+        format!("{}v{}", side(&self.0[0]), side(&self.0[1]))
+    }
+
+    fn flipped(&self) -> Self {
+        Self([self.0[1].clone(), self.0[0].clone()])
+    }
+
+    /// Tables are stored with the stronger side as white.
+    fn canonical(&self) -> Self {
+        let strength = |kinds: &[PieceKind]| {
+            let value = kinds
+                .iter()
+                .map(|kind| crate::eval::piece_value(kind).mg)
+                .sum::<i32>();
+            (value, kinds.len())
+        };
+        let flipped = self.flipped();
+        if (strength(&self.0[0]), self.name()) >= (strength(&self.0[1]), flipped.name()) {
+            self.clone()
+        } else {
+            flipped
+        }
+    }
+
+    fn has_pawns(&self) -> bool {
+        self.0.iter().flatten().any(|&kind| kind == PieceKind::Pawn)
+    }
+
+    /// What captures and promotions turn this into.
+    fn children(&self) -> Vec<Self> {
+        let mut children = Vec::new();
+        for side in 0..2 {
+            for (i, &kind) in self.0[side].iter().enumerate() {
+                let mut captured = self.clone();
+                captured.0[side].remove(i);
+                children.push(captured.canonical());
+                if kind == PieceKind::Pawn {
+                    for promotion in &KINDS[..4] {
+                        let mut promoted = self.clone();
+                        promoted.0[side][i] = *promotion;
+                        promoted.0[side].sort_by_key(|kind| KINDS.iter().position(|k| k == kind));
+                        children.push(promoted.canonical());
+                    }
+                }
+            }
+        }
+        children.dedup();
+        children
+    }
+
+    // kings first, then white's pieces and black's
+    fn pieces(&self) -> Vec<Piece> {
+        let mut pieces = vec![
+            Piece {
+                kind: PieceKind::King,
+                white: true,
+            },
+            Piece {
+                kind: PieceKind::King,
+                white: false,
+            },
+        ];
+        for (side, kinds) in self.0.iter().enumerate() {
+            pieces.extend(kinds.iter().map(|&kind| Piece {
+                kind,
+                white: side == 0,
+            }));
+        }
+        pieces
+    }
+}
+
+/// Distance to mate of every position with one material balance.
+pub struct Table {
+    material: Material,
+    pieces: Vec<Piece>,
+    // squares the white king is stored on
+    king_squares: Vec<u32>,
+    values: Vec<u8>,
+}
+
+impl Table {
+    fn new(material: Material) -> Self {
+        let king_squares = if material.has_pawns() {
+            // pawns only allow mirroring the files
+            (0..64).filter(|square| square % 8 < 4).collect()
+        } else {
+            TRIANGLE.to_vec()
+        };
+        Self {
+            pieces: material.pieces(),
+            material,
+            king_squares,
+            values: Vec::new(),
+        }
+    }
+
+    fn size(&self) -> usize {
+        2 * self.king_squares.len() * 64usize.pow(self.pieces.len() as u32 - 1)
+    }
+
+    // the position of index `idx`, unless pieces share a square or pawns are on the last rank
+    fn board(&self, idx: usize, empty: &BoardState) -> Option<BoardState> {
+        let mut board = empty.clone();
+        let mut rest = idx;
+        let mut squares = vec![0; self.pieces.len()];
+        for square in squares[1..].iter_mut().rev() {
+            *square = (rest % 64) as u32;
+            rest /= 64;
+        }
+        squares[0] = self.king_squares[rest % self.king_squares.len()];
+        board.white_to_play = rest / self.king_squares.len() == 1;
+        for (&square, &piece) in squares.iter().zip(&self.pieces) {
+            let on_edge = !(8..56).contains(&square);
+            if board.pieces[square as usize].is_some() || (piece.kind == PieceKind::Pawn && on_edge)
+            {
+                return None;
+            }
+            board.pieces[square as usize] = Some(piece);
+        }
+        board.rehash();
+        Some(board)
+    }
+
+    // The index of `board`, which has this table's material, or with colours swapped if
+    // `flip`. Of all mirror images the one with the lowest index is stored.
+    fn index(&self, board: &BoardState, flip: bool) -> Option<usize> {
+        let mut squares = Vec::with_capacity(self.pieces.len());
+        for (i, piece) in self.pieces.iter().enumerate() {
+            let piece = Piece {
+                white: piece.white != flip,
+                ..*piece
+            };
+            // the nth of several like pieces takes the nth square holding one
+            let nth = self.pieces[..i]
+                .iter()
+                .filter(|&&other| other == self.pieces[i])
+                .count();
+            let square = board
+                .pieces
+                .iter()
+                .enumerate()
+                .filter(|&(_, &p)| p == Some(piece))
+                .nth(nth)?
+                .0 as u32;
+            squares.push(if flip { square ^ 56 } else { square });
+        }
+        let white_to_play = board.white_to_play != flip;
+
+        let symmetries = if self.material.has_pawns() { 2 } else { 8 };
+        (0..symmetries)
+            .filter_map(|symmetry| {
+                let mut squares = squares
+                    .iter()
+                    .map(|&square| mirror(square, symmetry))
+                    .collect::<Vec<_>>();
+                let king = self.king_squares.iter().position(|&k| k == squares[0])?;
+                // like pieces are interchangeable, so keep them in order
+                let mut start = 2;
+                while start < squares.len() {
+                    let len = self.pieces[start..]
+                        .iter()
+                        .take_while(|&&piece| piece == self.pieces[start])
+                        .count();
+                    squares[start..start + len].sort_unstable();
+                    start += len;
+                }
+                let idx = squares[1..].iter().fold(
+                    white_to_play as usize * self.king_squares.len() + king,
+                    |idx, &square| idx * 64 + square as usize,
+                );
+                Some(idx)
+            })
+            .min()
+    }
+
+    fn get(&self, board: &BoardState, flip: bool) -> Option<Dtm> {
+        Dtm::from_byte(*self.values.get(self.index(board, flip)?)?)
+    }
+
+    // positions that reach `board` in one move without changing the material
+    fn predecessors(&self, board: &BoardState) -> Vec<usize> {
+        let moved = !board.white_to_play;
+        let mut predecessors = Vec::new();
+        for (square, piece) in board.pieces.iter().enumerate() {
+            let Some(piece) = *piece else {
+                continue;
+            };
+            if piece.white != moved {
+                continue;
+            }
+            let square = square as u32;
+            let from = if piece.kind == PieceKind::Pawn {
+                // one square back, or two back onto the starting rank
+                let back = if piece.white { -8 } else { 8 };
+                let start_rank = if piece.white { 1 } else { 6 };
+                let one = square as i32 + back;
+                let mut from = Vec::new();
+                if (8..56).contains(&one) && board.pieces[one as usize].is_none() {
+                    from.push(one as u32);
+                    let two = one + back;
+                    if two / 8 == start_rank && board.pieces[two as usize].is_none() {
+                        from.push(two as u32);
+                    }
+                }
+                from
+            } else {
+                board
+                    .attacks(square)
+                    .into_iter()
+                    .filter(|&from| board.pieces[from as usize].is_none())
+                    .collect()
+            };
+            for from in from {
+                let mut before = board.clone();
+                before.pieces[from as usize] = before.pieces[square as usize].take();
+                before.white_to_play = moved;
+                before.rehash();
+                // the side that didn't move can't have been left in check
+                let waiting_king = before.king_square(!moved);
+                if waiting_king.is_some_and(|king| before.is_attacked(king, moved)) {
+                    continue;
+                }
+                predecessors.extend(self.index(&before, false));
+            }
+        }
+        predecessors.sort_unstable();
+        predecessors.dedup();
+        predecessors
+    }
+
+    /// Solves every position by retrograde analysis: mates first, then positions that win by
+    /// moving into a loss and positions where every move runs into a win, one move at a time.
+    /// Captures and promotions are looked up in `endgames`, which must hold those tables.
+    fn generate(material: Material, endgames: &Endgames) -> anyhow::Result<Self> {
+        let mut table = Table::new(material);
+        let size = table.size();
+        let empty = BoardState::from_fen("8/8/8/8/8/8/8/8 w - - 0 1")?;
+        let mut values = vec![UNKNOWN; size];
+        // distinct successors within the table not yet known to win for the opponent
+        let mut counters = vec![0u8; size];
+        // best outcome by a capture or promotion
+        let mut conversions = vec![None; size];
+        // positions found to win or lose in n moves, settled in order of n
+        let mut wins: Vec<Vec<usize>> = Vec::new();
+        let mut losses: Vec<Vec<usize>> = Vec::new();
+        fn push(lists: &mut Vec<Vec<usize>>, n: u32, idx: usize) {
+            if lists.len() <= n as usize {
+                lists.resize(n as usize + 1, Vec::new());
+            }
+            lists[n as usize].push(idx);
+        }
+
+        for idx in 0..size {
+            let Some(mut board) = table.board(idx, &empty) else {
+                values[idx] = BROKEN;
+                continue;
+            };
+            let waiting_king = board.king_square(!board.white_to_play);
+            if table.index(&board, false) != Some(idx)
+                || waiting_king.is_some_and(|king| board.is_attacked(king, board.white_to_play))
+            {
+                values[idx] = BROKEN;
+                continue;
+            }
+            let moves = board.moves();
+            if moves.is_empty() {
+                if board.in_check() {
+                    push(&mut losses, 0, idx);
+                } else {
+                    values[idx] = DRAW;
+                }
+                continue;
+            }
+
+            let mut successors = Vec::new();
+            let mut conversion: Option<Dtm> = None;
+            for mv in moves {
+                let converts = board.is_capture(mv) || mv.promotion.is_some();
+                let undo = board.make(mv);
+                if converts {
+                    let dtm = endgames.probe(&board).ok_or_else(|| {
+                        let missing = Material::of(&board).map(|m| m.canonical().name());
+                        anyhow::anyhow!("table {} is needed first", missing.unwrap_or_default())
+                    })?;
+                    let dtm = dtm.before_move();
+                    if conversion.is_none_or(|best| dtm.rank() > best.rank()) {
+                        conversion = Some(dtm);
+                    }
+                } else {
+                    successors.extend(table.index(&board, false));
+                }
+                board.unmake(undo);
+            }
+            successors.sort_unstable();
+            successors.dedup();
+            counters[idx] = successors.len() as u8;
+            conversions[idx] = conversion;
+            match conversion {
+                Some(Dtm::Win(n)) => push(&mut wins, n, idx),
+                Some(Dtm::Loss(n)) if successors.is_empty() => push(&mut losses, n, idx),
+                _ if successors.is_empty() => values[idx] = DRAW,
+                _ => {}
+            }
+        }
+
+        let mut n = 0;
+        while n < losses.len() || n + 1 < wins.len() {
+            for idx in losses.get_mut(n).map(std::mem::take).unwrap_or_default() {
+                if values[idx] != UNKNOWN {
+                    continue;
+                }
+                values[idx] = Dtm::Loss(n as u32).to_byte()?;
+                let board = table.board(idx, &empty).unwrap();
+                for predecessor in table.predecessors(&board) {
+                    if values[predecessor] == UNKNOWN {
+                        push(&mut wins, n as u32 + 1, predecessor);
+                    }
+                }
+            }
+            for idx in wins.get_mut(n + 1).map(std::mem::take).unwrap_or_default() {
+                if values[idx] != UNKNOWN {
+                    continue;
+                }
+                values[idx] = Dtm::Win(n as u32 + 1).to_byte()?;
+                let board = table.board(idx, &empty).unwrap();
+                for predecessor in table.predecessors(&board) {
+                    if values[predecessor] != UNKNOWN || counters[predecessor] == 0 {
+                        continue;
+                    }
+                    counters[predecessor] -= 1;
+                    if counters[predecessor] > 0 {
+                        continue;
+                    }
+                    // every move within the table loses, so a conversion may do better
+                    let lost = Dtm::Loss(n as u32 + 1);
+                    match conversions[predecessor].filter(|dtm: &Dtm| dtm.rank() > lost.rank()) {
+                        None => push(&mut losses, n as u32 + 1, predecessor),
+                        Some(Dtm::Loss(m)) => push(&mut losses, m, predecessor),
+                        Some(Dtm::Draw) => values[predecessor] = DRAW,
+                        // already waiting in `wins`
+                        Some(Dtm::Win(_)) => {}
+                    }
+                }
+            }
+            n += 1;
+        }
+
+        // what never got settled can't be forced either way
+        for value in &mut values {
+            if *value == UNKNOWN {
+                *value = DRAW;
+            }
+        }
+        table.values = values;
+        Ok(table)
+    }
+
+    fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let name = self.material.name();
+        let mut bytes = Vec::with_capacity(6 + name.len() + self.values.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&self.values);
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let invalid = || anyhow::anyhow!("{} is not a tablebase file", path.display());
+        if bytes.len() < 6 || &bytes[..4] != MAGIC {
+            return Err(invalid());
+        }
+        if bytes[4] != VERSION {
+            return Err(anyhow::anyhow!(
+                "{} has version {}, expected {VERSION}",
+                path.display(),
+                bytes[4]
+            ));
+        }
+        let name_end = 6 + bytes[5] as usize;
+        let name = std::str::from_utf8(bytes.get(6..name_end).ok_or_else(invalid)?)?;
+        let mut table = Table::new(Material::parse(name)?);
+        if bytes.len() - name_end != table.size() {
+            return Err(invalid());
+        }
+        table.values = bytes[name_end..].to_vec();
+        Ok(table)
+    }
+}
+
+// one of the eight ways to turn the board onto itself: bit 0 mirrors files, bit 1 ranks and
+// bit 2 the a1-h8 diagonal
+fn mirror(square: u32, symmetry: u32) -> u32 {
+    let (mut rank, mut file) = (square / 8, square % 8);
+    if symmetry & 1 != 0 {
+        file = 7 - file;
+    }
+    if symmetry & 2 != 0 {
+        rank = 7 - rank;
+    }
+    if symmetry & 4 != 0 {
+        std::mem::swap(&mut rank, &mut file);
+    }
+    rank * 8 + file
+}
+
+/// Endgame tables generated by `make-tablebase`, holding the distance to mate of every
+/// position with a few pieces on a standard board.
+#[derive(Default)]
+pub struct Endgames {
+    // by canonical material name
+    tables: HashMap<String, Table>,
+}
+
+impl Endgames {
+    /// Loads every table in `dir`.
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        let mut endgames = Self::default();
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| anyhow::anyhow!("unable to read tablebase directory {dir:?}: {e}"))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == EXTENSION)
+            {
+                let table = Table::read(&path)?;
+                endgames.tables.insert(table.material.name(), table);
+            }
+        }
+        Ok(endgames)
+    }
+
+    /// Distance to mate for the side to play, `None` without a table for the position.
+    pub fn probe(&self, board: &BoardState) -> Option<Dtm> {
+        if board.width != 8
+            || board.height != 8
+            || board.fog_of_war
+            || board.white_can_oo
+            || board.white_can_ooo
+            || board.black_can_oo
+            || board.black_can_ooo
+        {
+            return None;
+        }
+        let material = Material::of(board)?;
+        if material.0.iter().all(Vec::is_empty) {
+            return Some(Dtm::Draw);
+        }
+        let canonical = material.canonical();
+        let table = self.tables.get(&canonical.name())?;
+        table.get(board, canonical != material)
+    }
+
+    /// Generates the table for `material` along with any it converts into that are missing,
+    /// returning the names of the new tables.
+    fn generate(&mut self, material: &Material) -> anyhow::Result<Vec<String>> {
+        let material = material.canonical();
+        let name = material.name();
+        if self.tables.contains_key(&name) {
+            return Ok(Vec::new());
+        }
+        let mut generated = Vec::new();
+        for child in material.children() {
+            if child.0.iter().any(|side| !side.is_empty()) {
+                generated.extend(self.generate(&child)?);
+            }
+        }
+        let start = std::time::Instant::now();
+        let table = Table::generate(material, self)?;
+        log::info!("generated {name} in {:.1?}", start.elapsed());
+        self.tables.insert(name.clone(), table);
+        generated.push(name);
+        Ok(generated)
+    }
+}
+
+/// `make-tablebase [options] <dir> <material>...`: generates tables like `KQvK` into `dir`,
+/// along with the smaller ones they need.
+pub fn make_tablebase(args: Vec<String>) -> anyhow::Result<()> {
+    let usage = || anyhow::anyhow!("usage: make-tablebase <dir> <material>..., e.g. tables KQvK");
+    let Some((dir, names)) = args.split_first() else {
+        return Err(usage());
+    };
+    if names.is_empty() {
+        return Err(usage());
+    }
+    let dir = Path::new(dir);
+    std::fs::create_dir_all(dir)?;
+    let mut endgames = Endgames::open(dir)?;
+    for name in names {
+        for name in endgames.generate(&Material::parse(name)?)? {
+            let table = &endgames.tables[&name];
+            table.write(&dir.join(format!("{name}.{EXTENSION}")))?;
+
+            let mut longest = 0;
+            let mut counts = [0usize; 3];
+            for dtm in table
+                .values
+                .iter()
+                .filter_map(|&value| Dtm::from_byte(value))
+            {
+                match dtm {
+                    Dtm::Win(n) => {
+                        counts[0] += 1;
+                        longest = longest.max(n);
+                    }
+                    Dtm::Draw => counts[1] += 1,
+                    Dtm::Loss(_) => counts[2] += 1,
+                }
+            }
+            println!(
+                "{name}: {} wins, {} draws, {} losses, longest mate in {longest}",
+                counts[0], counts[1], counts[2]
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn longest_mate(table: &Table) -> u32 {
+        table
+            .values
+            .iter()
+            .filter_map(|&value| match Dtm::from_byte(value) {
+                Some(Dtm::Win(n)) => Some(n),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn probe(endgames: &Endgames, fen: &str) -> Option<Dtm> {
+        endgames.probe(&BoardState::from_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn generates_the_known_values() {
+        let mut endgames = Endgames::default();
+        let generated = endgames
+            .generate(&Material::parse("KPvK").unwrap())
+            .unwrap();
+        for name in ["KQvK", "KRvK", "KPvK"] {
+            assert!(
+                generated.contains(&name.to_string()),
+                "{name} in {generated:?}"
+            );
+        }
+        assert_eq!(longest_mate(&endgames.tables["KQvK"]), 10);
+        assert_eq!(longest_mate(&endgames.tables["KRvK"]), 16);
+        assert_eq!(longest_mate(&endgames.tables["KBvK"]), 0);
+        assert_eq!(longest_mate(&endgames.tables["KNvK"]), 0);
+
+        let cases = [
+            ("7k/8/6K1/8/8/8/8/R7 w - - 0 1", Dtm::Win(1)),
+            ("R6k/8/6K1/8/8/8/8/8 b - - 0 1", Dtm::Loss(0)),
+            ("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", Dtm::Draw),
+            ("6k1/8/6K1/8/8/8/8/Q7 w - - 0 1", Dtm::Win(1)),
+            // the colours reversed
+            ("r7/8/8/8/8/6k1/8/7K b - - 0 1", Dtm::Win(1)),
+            // the king on the sixth rank wins whoever is to move, but not one square further
+            ("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1", Dtm::Win(11)),
+            ("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1", Dtm::Loss(12)),
+            ("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1", Dtm::Draw),
+            ("7k/8/7K/7P/8/8/8/8 w - - 0 1", Dtm::Draw),
+            ("8/8/8/8/8/k7/8/1K5B w - - 0 1", Dtm::Draw),
+        ];
+        for (fen, dtm) in cases {
+            assert_eq!(probe(&endgames, fen), Some(dtm), "{fen}");
+        }
+        assert_eq!(probe(&endgames, "8/8/8/8/8/8/k7/1K3R1R w - - 0 1"), None);
+    }
+
+    #[test]
+    fn written_tables_open_and_probe_the_same() {
+        let dir = std::env::temp_dir().join(format!("chess-gpu-endgames-{}", std::process::id()));
+        make_tablebase(vec![dir.display().to_string(), "KRvK".into()]).unwrap();
+        let mut generated = Endgames::default();
+        generated
+            .generate(&Material::parse("KRvK").unwrap())
+            .unwrap();
+
+        let opened = Endgames::open(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let opened = opened.unwrap();
+        assert_eq!(opened.tables.len(), 1);
+        assert!(opened.tables["KRvK"].values == generated.tables["KRvK"].values);
+        assert_eq!(
+            probe(&opened, "8/8/8/8/8/2k5/1R6/K7 w - - 0 1"),
+            Some(Dtm::Win(16))
+        );
+        assert_eq!(
+            probe(&opened, "8/8/8/8/8/8/1kR5/7K b - - 0 1"),
+            Some(Dtm::Draw)
+        );
+    }
+}
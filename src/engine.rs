@@ -0,0 +1,361 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+
+use crate::batch_eval::GpuEvaluator;
+use crate::book::Book;
+use crate::endgame::Endgames;
+use crate::logic::{BoardState, Move};
+use crate::nnue::Network;
+use crate::process::Interrupt;
+use crate::search::{Limits, SearchResult, Searcher};
+use crate::syzygy::Tablebases;
+use crate::tt::TranspositionTable;
+use crate::uci_client::UciEngine;
+use crate::xboard_client::XboardEngine;
+
+/// Where the computer's moves come from.
+#[derive(Clone)]
+pub enum Backend {
+    Internal {
+        tt: Arc<TranspositionTable>,
+        tablebases: Option<Arc<Tablebases>>,
+        endgames: Option<Arc<Endgames>>,
+        network: Option<Arc<Network>>,
+        evaluator: Option<Arc<GpuEvaluator>>,
+        threads: usize,
+    },
+    Uci(Arc<Mutex<UciEngine>>),
+    Xboard(Arc<Mutex<XboardEngine>>),
+}
+
+impl Backend {
+    pub fn name(&self) -> String {
+        match self {
+            Backend::Internal { .. } => format!("chess-gpu {}", env!("CARGO_PKG_VERSION")),
+            Backend::Uci(engine) => engine.lock().unwrap().name().to_owned(),
+            Backend::Xboard(engine) => engine.lock().unwrap().name().to_owned(),
+        }
+    }
+
+    /// Searches `board`, blocking until done.
+    pub fn search(&self, board: &BoardState, limits: Limits) -> anyhow::Result<SearchResult> {
+        match self {
+            Backend::Internal { .. } => Ok(self.searcher(limits).unwrap().search(board)),
+            Backend::Uci(engine) => engine.lock().unwrap().search(board, &[], limits),
+            Backend::Xboard(engine) => engine.lock().unwrap().search(board, &[], limits),
+        }
+    }
+
+    /// The built in engine with everything it was given, `None` for external engines.
+    pub fn searcher(&self, limits: Limits) -> Option<Searcher> {
+        match self {
+            Backend::Internal {
+                tt,
+                tablebases,
+                endgames,
+                network,
+                evaluator,
+                threads,
+            } => Some(
+                Searcher::with_table(limits, tt.clone())
+                    .with_tablebases(tablebases.clone())
+                    .with_endgames(endgames.clone())
+                    .with_network(network.clone())
+                    .with_evaluator(evaluator.clone())
+                    .with_threads(*threads),
+            ),
+            Backend::Uci(_) | Backend::Xboard(_) => None,
+        }
+    }
+}
+
+/// A MultiPV search of one position on a background thread, stopped when dropped. It always
+/// runs the built in engine, external ones are only asked for moves.
+pub struct Analysis {
+    stop: Arc<AtomicBool>,
+    results: mpsc::Receiver<SearchResult>,
+}
+
+impl Analysis {
+    pub fn start(backend: &Backend, board: &BoardState, lines: usize, limits: Limits) -> Self {
+        let mut searcher = backend
+            .searcher(limits)
+            .unwrap_or_else(|| Searcher::new(limits))
+            .with_multipv(lines);
+        let stop = searcher.stop_flag();
+        let (sender, results) = mpsc::channel();
+        let board = board.clone();
+        std::thread::spawn(move || {
+            searcher.search_with_report(&board, |result| {
+                // nobody listens once the position changed
+                let _ = sender.send(result.clone());
+            });
+        });
+        Self { stop, results }
+    }
+
+    /// An analysis that never reports, for positions with nothing to search.
+    pub fn idle() -> Self {
+        Self {
+            stop: Arc::new(AtomicBool::new(true)),
+            results: mpsc::channel().1,
+        }
+    }
+
+    /// The newest finished iteration, if there was one since the last call.
+    pub fn poll(&self) -> Option<SearchResult> {
+        self.results.try_iter().last()
+    }
+}
+
+impl Drop for Analysis {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// ends a running search early
+enum Stop {
+    Flag(Arc<AtomicBool>),
+    Command(Interrupt),
+}
+
+/// Computer opponent, thinking on a background thread so the window keeps drawing.
+pub struct Engine {
+    pub white: bool,
+    pub limits: Limits,
+    /// Opening book played from before searching.
+    pub book: Option<Arc<Book>>,
+    backend: Backend,
+    thinking: Option<mpsc::Receiver<Option<Move>>>,
+    stop: Option<Stop>,
+    // for external engines, which are busy while searching
+    interrupt: Option<Interrupt>,
+    // set once a search came back without a move, so a broken engine isn't restarted every frame
+    failed: bool,
+    // the result was sent already, stepping back and forth over the end doesn't repeat it
+    result_sent: bool,
+}
+
+impl Engine {
+    pub fn new(white: bool, limits: Limits, backend: Backend) -> Self {
+        let interrupt = match &backend {
+            Backend::Internal { .. } => None,
+            Backend::Uci(engine) => Some(engine.lock().unwrap().interrupt()),
+            Backend::Xboard(engine) => Some(engine.lock().unwrap().interrupt()),
+        };
+        Self {
+            white,
+            limits,
+            book: None,
+            backend,
+            thinking: None,
+            stop: None,
+            interrupt,
+            failed: false,
+            result_sent: false,
+        }
+    }
+
+    pub fn is_thinking(&self) -> bool {
+        self.thinking.is_some()
+    }
+
+    /// Starts searching `board` unless a search is running already.
+    pub fn start(&mut self, board: &BoardState) {
+        if self.is_thinking() || self.failed {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        if let Some(mv) = self.book.as_ref().and_then(|book| book.choose(board)) {
+            log::info!("book move {}", board.san(mv));
+            let _ = sender.send(Some(mv));
+            self.thinking = Some(receiver);
+            return;
+        }
+        // an external engine starts a new game from the position it is sent
+        self.result_sent = false;
+        let backend = self.backend.clone();
+        let limits = self.limits;
+        let board = board.clone();
+        let searcher = backend.searcher(limits);
+        self.stop = match &searcher {
+            Some(searcher) => Some(Stop::Flag(searcher.stop_flag())),
+            None => self.interrupt.clone().map(Stop::Command),
+        };
+        std::thread::spawn(move || {
+            let result = match searcher {
+                Some(mut searcher) => Ok(searcher.search(&board)),
+                None => backend.search(&board, limits),
+            };
+            let best_move = match result {
+                Ok(result) => {
+                    log::info!("{}: {}", backend.name(), result.describe(&board));
+                    result.best_move
+                }
+                Err(e) => {
+                    log::error!("{e}");
+                    None
+                }
+            };
+            // the receiver is gone when the window closed mid search
+            let _ = sender.send(best_move);
+        });
+        self.thinking = Some(receiver);
+    }
+
+    /// Stops the running search, its move won't be played.
+    pub fn cancel(&mut self) {
+        self.thinking = None;
+        match self.stop.take() {
+            Some(Stop::Flag(stop)) => stop.store(true, Ordering::Relaxed),
+            Some(Stop::Command(interrupt)) => {
+                if let Err(e) = interrupt.send() {
+                    log::error!("{e}");
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Tells an XBoard engine how the game ended, once per game. UCI has no such command.
+    pub fn game_over(&mut self, board: &BoardState) {
+        let Backend::Xboard(engine) = &self.backend else {
+            return;
+        };
+        if self.result_sent {
+            return;
+        }
+        let result = match board.game_over {
+            0 => return,
+            1 if board.white_to_play => "0-1 {Black wins}",
+            1 => "1-0 {White wins}",
+            2 => "1/2-1/2 {Stalemate}",
+            _ => "1/2-1/2 {Draw}",
+        };
+        self.result_sent = true;
+        if let Err(e) = engine.lock().unwrap().result(result) {
+            log::error!("{e}");
+        }
+    }
+
+    /// The chosen move once the search has finished, `None` while it is still running.
+    pub fn poll(&mut self) -> Option<Move> {
+        let receiver = self.thinking.as_ref()?;
+        match receiver.try_recv() {
+            Ok(best_move) => {
+                self.thinking = None;
+                self.stop = None;
+                self.failed = best_move.is_none();
+                best_move
+            }
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.thinking = None;
+                self.stop = None;
+                self.failed = true;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::STARTING_FEN;
+
+    #[test]
+    fn cancel_stops_the_built_in_engine() {
+        let backend = Backend::Internal {
+            tt: Arc::new(TranspositionTable::new(1)),
+            tablebases: None,
+            endgames: None,
+            network: None,
+            evaluator: None,
+            threads: 1,
+        };
+        let mut engine = Engine::new(true, Limits::default(), backend);
+        engine.start(&BoardState::from_fen(STARTING_FEN).unwrap());
+        let Some(Stop::Flag(stop)) = &engine.stop else {
+            panic!("the search has no stop flag");
+        };
+        let stop = stop.clone();
+        engine.cancel();
+        assert!(stop.load(Ordering::Relaxed));
+        assert!(!engine.is_thinking());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cancel_stops_an_external_engine() {
+        // searches until told to stop
+        let path = crate::process::tests::stand_in(
+            "cancel-stand-in",
+            r#"case "$line" in
+uci) echo "id name Stand-in"; echo uciok ;;
+isready) echo readyok ;;
+stop) echo "bestmove e2e4" ;;
+quit) exit 0 ;;
+esac"#,
+        );
+        let log = path.with_extension("log");
+        let uci = UciEngine::spawn(path.to_str().unwrap()).unwrap();
+        let mut engine = Engine::new(
+            true,
+            Limits::default(),
+            Backend::Uci(Arc::new(Mutex::new(uci))),
+        );
+        // the stand-in's log shows what it was sent so far
+        let received = |command: &str| {
+            let start = std::time::Instant::now();
+            while start.elapsed() < std::time::Duration::from_secs(5) {
+                let commands = std::fs::read_to_string(&log).unwrap_or_default();
+                if commands.lines().any(|line| line.starts_with(command)) {
+                    return true;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            false
+        };
+        engine.start(&BoardState::from_fen(STARTING_FEN).unwrap());
+        assert!(received("go"));
+        engine.cancel();
+        assert!(received("stop"));
+        assert!(!engine.is_thinking());
+
+        drop(engine);
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(&log);
+    }
+    #[cfg(unix)]
+    #[test]
+    fn sends_the_result_once() {
+        let path = crate::process::tests::stand_in(
+            "result-stand-in",
+            r#"case "$line" in
+"protover 2") echo 'feature myname="Stand-in" done=1' ;;
+quit) exit 0 ;;
+esac"#,
+        );
+        let log = path.with_extension("log");
+        let xboard = XboardEngine::spawn(path.to_str().unwrap()).unwrap();
+        let mut engine = Engine::new(
+            false,
+            Limits::default(),
+            Backend::Xboard(Arc::new(Mutex::new(xboard))),
+        );
+        let mut board = BoardState::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 1 1").unwrap();
+        board.game_over = 1;
+        // as when stepping back from the end and forward again
+        engine.game_over(&board);
+        engine.game_over(&board);
+
+        drop(engine);
+        let commands = std::fs::read_to_string(&log).unwrap();
+        let results = commands.lines().filter(|line| line.starts_with("result"));
+        assert_eq!(results.collect::<Vec<_>>(), ["result 1-0 {White wins}"]);
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(&log);
+    }
+}
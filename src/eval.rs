@@ -0,0 +1,512 @@
+use std::fmt;
+
+use crate::logic::{BoardState, Piece, PieceKind};
+
+/// Middlegame and endgame halves of a term, blended by the game phase.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Score {
+    pub const fn new(mg: i32, eg: i32) -> Self {
+        Self { mg, eg }
+    }
+
+    /// Adds for white, subtracts for black, so every term stays from white's point of view.
+    fn add(&mut self, white: bool, score: Score) {
+        let sign = if white { 1 } else { -1 };
+        self.mg += sign * score.mg;
+        self.eg += sign * score.eg;
+    }
+
+    fn scaled(self, n: i32) -> Score {
+        Score::new(self.mg * n, self.eg * n)
+    }
+
+    pub fn taper(self, phase: i32) -> i32 {
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+/// Phase of a full standard army on both sides, anything above is clamped.
+pub const MAX_PHASE: i32 = 24;
+
+const PAWN_VALUE: Score = Score::new(100, 120);
+const KNIGHT_VALUE: Score = Score::new(320, 300);
+const BISHOP_VALUE: Score = Score::new(330, 320);
+const ROOK_VALUE: Score = Score::new(500, 520);
+const QUEEN_VALUE: Score = Score::new(900, 950);
+
+const DOUBLED_PAWN: Score = Score::new(-10, -20);
+const ISOLATED_PAWN: Score = Score::new(-10, -15);
+// by how far the pawn has come, on an 8 rank scale
+const PASSED_PAWN: [i32; 8] = [0, 5, 10, 20, 35, 60, 100, 0];
+
+const KNIGHT_MOBILITY: Score = Score::new(4, 4);
+const BISHOP_MOBILITY: Score = Score::new(5, 5);
+const ROOK_MOBILITY: Score = Score::new(2, 4);
+const QUEEN_MOBILITY: Score = Score::new(1, 2);
+const FAIRY_MOBILITY: Score = Score::new(2, 3);
+
+const PAWN_SHIELD: [Score; 2] = [Score::new(12, 0), Score::new(6, 0)];
+const OPEN_FILE_NEAR_KING: Score = Score::new(-15, 0);
+const KING_ZONE_ATTACK: Score = Score::new(-8, 0);
+
+// Piece-square tables from white's side, a8 first, like the board is printed.
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const PAWN_ENDGAME_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    80, 80, 80, 80, 80, 80, 80, 80,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    30, 30, 30, 30, 30, 30, 30, 30,
+    20, 20, 20, 20, 20, 20, 20, 20,
+    10, 10, 10, 10, 10, 10, 10, 10,
+    10, 10, 10, 10, 10, 10, 10, 10,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+   -50,-40,-30,-30,-30,-30,-40,-50,
+   -40,-20,  0,  0,  0,  0,-20,-40,
+   -30,  0, 10, 15, 15, 10,  0,-30,
+   -30,  5, 15, 20, 20, 15,  5,-30,
+   -30,  0, 15, 20, 20, 15,  0,-30,
+   -30,  5, 10, 15, 15, 10,  5,-30,
+   -40,-20,  0,  5,  5,  0,-20,-40,
+   -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+   -20,-10,-10,-10,-10,-10,-10,-20,
+   -10,  0,  0,  0,  0,  0,  0,-10,
+   -10,  0,  5, 10, 10,  5,  0,-10,
+   -10,  5,  5, 10, 10,  5,  5,-10,
+   -10,  0, 10, 10, 10, 10,  0,-10,
+   -10, 10, 10, 10, 10, 10, 10,-10,
+   -10,  5,  0,  0,  0,  0,  5,-10,
+   -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+   -20,-10,-10, -5, -5,-10,-10,-20,
+   -10,  0,  0,  0,  0,  0,  0,-10,
+   -10,  0,  5,  5,  5,  5,  0,-10,
+    -5,  0,  5,  5,  5,  5,  0, -5,
+     0,  0,  5,  5,  5,  5,  0, -5,
+   -10,  5,  5,  5,  5,  5,  0,-10,
+   -10,  0,  5,  0,  0,  0,  0,-10,
+   -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const KING_TABLE: [i32; 64] = [
+   -30,-40,-40,-50,-50,-40,-40,-30,
+   -30,-40,-40,-50,-50,-40,-40,-30,
+   -30,-40,-40,-50,-50,-40,-40,-30,
+   -30,-40,-40,-50,-50,-40,-40,-30,
+   -20,-30,-30,-40,-40,-30,-30,-20,
+   -10,-20,-20,-20,-20,-20,-20,-10,
+    20, 20,  0,  0,  0,  0, 20, 20,
+    20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+#[rustfmt::skip]
+const KING_ENDGAME_TABLE: [i32; 64] = [
+   -50,-40,-30,-20,-20,-30,-40,-50,
+   -30,-20,-10,  0,  0,-10,-20,-30,
+   -30,-10, 20, 30, 30, 20,-10,-30,
+   -30,-10, 30, 40, 40, 30,-10,-30,
+   -30,-10, 30, 40, 40, 30,-10,-30,
+   -30,-10, 20, 30, 30, 20,-10,-30,
+   -30,-30,  0,  0,  0,  0,-30,-30,
+   -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+pub fn piece_value(kind: &PieceKind) -> Score {
+    match kind {
+        PieceKind::Pawn => PAWN_VALUE,
+        PieceKind::Knight => KNIGHT_VALUE,
+        PieceKind::Bishop => BISHOP_VALUE,
+        PieceKind::Rook => ROOK_VALUE,
+        PieceKind::Queen => QUEEN_VALUE,
+        PieceKind::King => Score::default(),
+        PieceKind::Fairy(def) => Score::new(def.value, def.value),
+    }
+}
+
+/// How much a piece counts towards the middlegame.
+pub fn phase_weight(kind: &PieceKind) -> i32 {
+    match kind {
+        PieceKind::Pawn | PieceKind::King => 0,
+        PieceKind::Knight | PieceKind::Bishop => 1,
+        PieceKind::Rook => 2,
+        PieceKind::Queen => 4,
+        PieceKind::Fairy(def) => (def.value + 150) / 300,
+    }
+}
+
+/// Looks `square` up in an 8x8 table, stretching other board sizes onto it.
+fn table_lookup(board: &BoardState, table: &[i32; 64], square: u32, white: bool) -> i32 {
+    let file = board.file_of(square) * 8 / board.width;
+    let rank = board.rank_of(square) * 8 / board.height;
+    let rank = if white { rank } else { 7 - rank };
+    table[((7 - rank) * 8 + file) as usize]
+}
+
+/// Middlegame and endgame tables of every piece, in the order of `piece_table`.
+pub const PIECE_SQUARE_TABLES: [[[i32; 64]; 2]; 6] = [
+    [PAWN_TABLE, PAWN_ENDGAME_TABLE],
+    [KNIGHT_TABLE, KNIGHT_TABLE],
+    [BISHOP_TABLE, BISHOP_TABLE],
+    [ROOK_TABLE, ROOK_TABLE],
+    [QUEEN_TABLE, QUEEN_TABLE],
+    [KING_TABLE, KING_ENDGAME_TABLE],
+];
+
+/// Index of a piece's tables in `PIECE_SQUARE_TABLES`, fairy pieces move like knights there.
+pub fn piece_table(kind: &PieceKind) -> usize {
+    match kind {
+        PieceKind::Pawn => 0,
+        PieceKind::Knight | PieceKind::Fairy(_) => 1,
+        PieceKind::Bishop => 2,
+        PieceKind::Rook => 3,
+        PieceKind::Queen => 4,
+        PieceKind::King => 5,
+    }
+}
+
+fn piece_square(board: &BoardState, piece: &Piece, square: u32) -> Score {
+    let [mg, eg] = &PIECE_SQUARE_TABLES[piece_table(&piece.kind)];
+    Score::new(
+        table_lookup(board, mg, square, piece.white),
+        table_lookup(board, eg, square, piece.white),
+    )
+}
+
+/// A position's score split into its terms, each from white's point of view.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Evaluation {
+    pub material: Score,
+    pub piece_squares: Score,
+    pub pawn_structure: Score,
+    pub mobility: Score,
+    pub king_safety: Score,
+    /// `MAX_PHASE` with every piece on the board down to 0 with only kings and pawns.
+    pub phase: i32,
+}
+
+impl Evaluation {
+    pub fn new(board: &BoardState) -> Self {
+        let mut eval = Evaluation::placement(board);
+
+        for (square, piece) in board.pieces.iter().enumerate() {
+            let Some(piece) = piece else {
+                continue;
+            };
+            let square = square as u32;
+            let weight = match piece.kind {
+                PieceKind::Pawn | PieceKind::King => continue,
+                PieceKind::Knight => KNIGHT_MOBILITY,
+                PieceKind::Bishop => BISHOP_MOBILITY,
+                PieceKind::Rook => ROOK_MOBILITY,
+                PieceKind::Queen => QUEEN_MOBILITY,
+                PieceKind::Fairy(_) => FAIRY_MOBILITY,
+            };
+            let reachable = board
+                .attacks(square)
+                .into_iter()
+                .filter(|&target| !matches!(&board.pieces[target as usize], Some(other) if other.white == piece.white))
+                .count();
+            eval.mobility
+                .add(piece.white, weight.scaled(reachable as i32));
+        }
+
+        for white in [true, false] {
+            eval.pawn_structure.add(white, pawn_structure(board, white));
+            eval.king_safety.add(white, king_safety(board, white));
+        }
+
+        eval
+    }
+
+    /// Only material and piece squares, the terms the GPU evaluator in `batch_eval.rs` has.
+    pub fn placement(board: &BoardState) -> Self {
+        let mut eval = Evaluation::default();
+        for (square, piece) in board.pieces.iter().enumerate() {
+            let Some(piece) = piece else {
+                continue;
+            };
+            eval.phase += phase_weight(&piece.kind);
+            eval.material.add(piece.white, piece_value(&piece.kind));
+            eval.piece_squares
+                .add(piece.white, piece_square(board, piece, square as u32));
+        }
+        eval.phase = eval.phase.min(MAX_PHASE);
+        eval
+    }
+
+    pub fn terms(&self) -> [(&'static str, Score); 5] {
+        [
+            ("material", self.material),
+            ("piece squares", self.piece_squares),
+            ("pawn structure", self.pawn_structure),
+            ("mobility", self.mobility),
+            ("king safety", self.king_safety),
+        ]
+    }
+
+    /// Centipawns from white's point of view.
+    pub fn total(&self) -> i32 {
+        self.terms()
+            .iter()
+            .map(|(_, score)| score.taper(self.phase))
+            .sum()
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<16}{:>7}{:>7}{:>7}", "term", "mg", "eg", "total")?;
+        for (name, score) in self.terms() {
+            writeln!(
+                f,
+                "{:<16}{:>+7}{:>+7}{:>+7}",
+                name,
+                score.mg,
+                score.eg,
+                score.taper(self.phase)
+            )?;
+        }
+        write!(
+            f,
+            "{:<16}{:>21}",
+            format!("phase {}/{}", self.phase, MAX_PHASE),
+            format!("{:+}", self.total())
+        )
+    }
+}
+
+/// Centipawn score of `board` from white's point of view.
+pub fn evaluate(board: &BoardState) -> i32 {
+    Evaluation::new(board).total()
+}
+
+fn pawns_per_file(board: &BoardState, white: bool) -> Vec<i32> {
+    let mut files = vec![0; board.width as usize];
+    for (square, piece) in board.pieces.iter().enumerate() {
+        if let Some(piece) = piece
+            && piece.kind == PieceKind::Pawn
+            && piece.white == white
+        {
+            files[board.file_of(square as u32) as usize] += 1;
+        }
+    }
+    files
+}
+
+fn pawn_structure(board: &BoardState, white: bool) -> Score {
+    let mut score = Score::default();
+    let own_files = pawns_per_file(board, white);
+
+    for &count in own_files.iter() {
+        if count > 1 {
+            score.add(true, DOUBLED_PAWN.scaled(count - 1));
+        }
+    }
+
+    for (square, piece) in board.pieces.iter().enumerate() {
+        let Some(piece) = piece else {
+            continue;
+        };
+        if piece.kind != PieceKind::Pawn || piece.white != white {
+            continue;
+        }
+        let square = square as u32;
+        let file = board.file_of(square) as usize;
+        let rank = board.rank_of(square);
+
+        let neighbours = file.checked_sub(1).map_or(0, |left| own_files[left])
+            + own_files.get(file + 1).copied().unwrap_or(0);
+        if neighbours == 0 {
+            score.add(true, ISOLATED_PAWN);
+        }
+
+        let blocked = board.pieces.iter().enumerate().any(|(other, other_piece)| {
+            let other = other as u32;
+            matches!(other_piece, Some(p) if p.kind == PieceKind::Pawn && p.white != white)
+                && board.file_of(other).abs_diff(file as u32) <= 1
+                && if white {
+                    board.rank_of(other) > rank
+                } else {
+                    board.rank_of(other) < rank
+                }
+        });
+        if !blocked {
+            let advanced = if white { rank } else { board.height - 1 - rank };
+            let bonus = PASSED_PAWN[(advanced * 7 / (board.height - 1)) as usize];
+            score.add(true, Score::new(bonus / 2, bonus));
+        }
+    }
+
+    score
+}
+
+fn king_safety(board: &BoardState, white: bool) -> Score {
+    let mut score = Score::default();
+    let Some(king_square) = board.pieces.iter().position(
+        |p| matches!(p, Some(piece) if piece.kind == PieceKind::King && piece.white == white),
+    ) else {
+        return score;
+    };
+    let king_square = king_square as u32;
+    let king_rank = board.rank_of(king_square) as i32;
+    let king_file = board.file_of(king_square) as i32;
+    let forward = if white { 1 } else { -1 };
+    let own_files = pawns_per_file(board, white);
+
+    // a shield only matters while the king is still tucked away on its back ranks
+    let home_rank = if white { 0 } else { board.height as i32 - 1 };
+    if (king_rank - home_rank).abs() <= 1 {
+        for file in king_file - 1..=king_file + 1 {
+            for (distance, shield) in PAWN_SHIELD.iter().enumerate() {
+                if let Some(square) =
+                    board.square_at(king_rank + forward * (distance as i32 + 1), file)
+                    && matches!(&board.pieces[square as usize], Some(p) if p.kind == PieceKind::Pawn && p.white == white)
+                {
+                    score.add(true, *shield);
+                    break;
+                }
+            }
+            if (0..board.width as i32).contains(&file) && own_files[file as usize] == 0 {
+                score.add(true, OPEN_FILE_NEAR_KING);
+            }
+        }
+    }
+
+    let mut zone = board.attacks(king_square);
+    zone.push(king_square);
+    for (square, piece) in board.pieces.iter().enumerate() {
+        if let Some(piece) = piece
+            && piece.white != white
+            && !matches!(piece.kind, PieceKind::Pawn | PieceKind::King)
+        {
+            let hits = board
+                .attacks(square as u32)
+                .iter()
+                .filter(|target| zone.contains(target))
+                .count();
+            score.add(true, KING_ZONE_ATTACK.scaled(hits as i32));
+        }
+    }
+
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::{CAPABLANCA_FEN, STARTING_FEN};
+
+    // the same position with the colours swapped and the board turned upside down
+    fn mirrored(board: &BoardState) -> BoardState {
+        let mut mirrored = board.clone();
+        for square in 0..board.width * board.height {
+            let rank = board.height - 1 - board.rank_of(square);
+            let target = rank * board.width + board.file_of(square);
+            mirrored.pieces[target as usize] = board.pieces[square as usize].map(|piece| Piece {
+                white: !piece.white,
+                ..piece
+            });
+        }
+        mirrored.white_to_play = !board.white_to_play;
+        mirrored
+    }
+
+    #[test]
+    fn start_position_is_even() {
+        for fen in [STARTING_FEN, CAPABLANCA_FEN] {
+            let eval = Evaluation::new(&BoardState::from_fen(fen).unwrap());
+            for (name, score) in eval.terms() {
+                assert_eq!(score, Score::default(), "{name} in {fen}");
+            }
+            assert_eq!(eval.total(), 0);
+        }
+    }
+
+    #[test]
+    fn mirrored_positions_negate() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+            "4k3/1P6/8/8/8/8/6p1/1a2K2C w - - 0 1",
+        ] {
+            let board = BoardState::from_fen(fen).unwrap();
+            let eval = Evaluation::new(&board);
+            let flipped = Evaluation::new(&mirrored(&board));
+            for ((name, score), (_, flipped)) in eval.terms().into_iter().zip(flipped.terms()) {
+                assert_eq!(
+                    score,
+                    Score::new(-flipped.mg, -flipped.eg),
+                    "{name} in {fen}"
+                );
+            }
+            assert_eq!(eval.total(), -flipped.total(), "{fen}");
+        }
+    }
+
+    #[test]
+    fn pawn_terms() {
+        let pawns = |fen: &str| pawn_structure(&BoardState::from_fen(fen).unwrap(), true);
+        // c2 and c3 share a file, black's pawns stop all three from being passed
+        assert_eq!(pawns("4k3/2pp4/8/8/8/2P5/2PP4/4K3 w - - 0 1"), DOUBLED_PAWN);
+        assert_eq!(pawns("4k3/pp6/8/8/8/8/P7/4K3 w - - 0 1"), ISOLATED_PAWN);
+
+        let passed = pawns("4k3/8/8/8/3PP3/8/8/4K3 w - - 0 1");
+        assert!(passed.mg > 0 && passed.eg > 0);
+        let further = pawns("4k3/8/3PP3/8/8/8/8/4K3 w - - 0 1");
+        assert!(further.eg > passed.eg);
+
+        // a2 and a3 are doubled, isolated and passed all at once
+        let eval =
+            Evaluation::new(&BoardState::from_fen("4k3/8/8/8/8/P7/P7/4K3 w - - 0 1").unwrap());
+        let passed = Score::new(
+            PASSED_PAWN[1] / 2 + PASSED_PAWN[2] / 2,
+            PASSED_PAWN[1] + PASSED_PAWN[2],
+        );
+        let expected = Score::new(
+            DOUBLED_PAWN.mg + 2 * ISOLATED_PAWN.mg + passed.mg,
+            DOUBLED_PAWN.eg + 2 * ISOLATED_PAWN.eg + passed.eg,
+        );
+        assert_eq!(eval.pawn_structure, expected);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::logic::{BoardState, Piece};
+use crate::logic::{BoardState, MAX_SQUARES, Piece};
 
 impl BoardState {
     pub fn from_fen(fen: &str) -> anyhow::Result<BoardState> {
@@ -10,52 +10,72 @@ impl BoardState {
         let (white_can_oo, white_can_ooo, black_can_oo, black_can_ooo) =
             Self::parse_castling(parts[2]);
 
-        Ok(BoardState {
-            pieces: Self::parse_placement(parts[0])?,
+        let (pieces, width, height) = Self::parse_placement(parts[0])?;
+
+        let mut board = BoardState {
+            en_passant_square: Self::parse_en_passant(parts[3], width, height)?,
+            pieces,
+            width,
+            height,
             white_to_play: Self::parse_side_to_play(parts[1])?,
             white_can_oo,
             white_can_ooo,
             black_can_oo,
             black_can_ooo,
-            en_passant_square: Self::parse_en_passant(parts[3])?,
             halfmove_clock: Self::parse_halfmove(parts[4])?,
             fullmove_number: Self::parse_fullmove(parts[5])?,
             game_over: 0,
-        })
+            fog_of_war: false,
+            key: 0,
+        };
+        board.rehash();
+        Ok(board)
     }
 
-    fn parse_placement(placement_str: &str) -> anyhow::Result<Vec<Option<Piece>>> {
-        let mut placement = vec![None; 64];
+    fn parse_placement(placement_str: &str) -> anyhow::Result<(Vec<Option<Piece>>, u32, u32)> {
         let lines = placement_str.split('/').collect::<Vec<_>>();
+        let mut ranks: Vec<Vec<Option<Piece>>> = Vec::new();
 
-        if lines.len() != 8 {
-            return Err(anyhow::anyhow!("bad placement: {placement_str}"));
-        }
-
-        for (rank, pieces) in lines.iter().rev().enumerate() {
-            let mut file = 0;
+        for pieces in lines.iter().rev() {
+            let mut rank = Vec::new();
+            let mut blanks = 0usize;
+            let too_long = || anyhow::anyhow!("rank {pieces} has too many squares");
 
             for piece_char in pieces.chars() {
                 if let Some(n) = piece_char.to_digit(10) {
-                    Self::increment_file(&mut file, n as usize, pieces)?;
+                    // blanks can take more than one digit on boards wider than 9
+                    blanks = blanks
+                        .checked_mul(10)
+                        .and_then(|blanks| blanks.checked_add(n as usize))
+                        .ok_or_else(too_long)?;
                 } else {
-                    let piece = Piece::from_char(piece_char);
-                    placement[rank * 8 + file] = Some(piece);
-                    file += 1;
+                    rank.resize(rank.len() + blanks, None);
+                    blanks = 0;
+                    let piece = Piece::from_char(piece_char)
+                        .ok_or_else(|| anyhow::anyhow!("no such piece: {piece_char}"))?;
+                    rank.push(Some(piece));
+                }
+                if rank.len() + blanks > MAX_SQUARES as usize {
+                    return Err(too_long());
                 }
             }
-        }
+            rank.resize(rank.len() + blanks, None);
 
-        Ok(placement)
-    }
+            if let Some(first) = ranks.first()
+                && first.len() != rank.len()
+            {
+                return Err(anyhow::anyhow!("wrong number of squares in rank {pieces}"));
+            }
+            ranks.push(rank);
+        }
 
-    fn increment_file(file: &mut usize, n: usize, rank: &str) -> anyhow::Result<()> {
-        *file += n;
-        if *file > 8 {
-            Err(anyhow::anyhow!("too many pieces in rank {rank}"))
-        } else {
-            Ok(())
+        let height = ranks.len() as u32;
+        let width = ranks.first().map_or(0, |rank| rank.len() as u32);
+        if width < 5 || height < 4 || width * height > MAX_SQUARES {
+            return Err(anyhow::anyhow!("bad board size: {width}x{height}"));
         }
+
+        Ok((ranks.concat(), width, height))
     }
 
     fn parse_side_to_play(side_to_play: &str) -> anyhow::Result<bool> {
@@ -75,43 +95,27 @@ impl BoardState {
         (white_oo, white_ooo, black_oo, black_ooo)
     }
 
-    fn parse_en_passant(en_passant: &str) -> anyhow::Result<Option<u32>> {
+    fn parse_en_passant(en_passant: &str, width: u32, height: u32) -> anyhow::Result<Option<u32>> {
         if en_passant == "-" {
             return Ok(None);
         }
 
-        if en_passant.len() != 2 {
-            return Err(anyhow::anyhow!("bad en passant: {en_passant}"));
+        let mut chars = en_passant.chars();
+        let file = chars
+            .next()
+            .filter(char::is_ascii_lowercase)
+            .map(|file| file as u32 - 'a' as u32)
+            .filter(|&file| file < width);
+        let rank = chars
+            .as_str()
+            .parse::<u32>()
+            .ok()
+            .filter(|rank| (1..=height).contains(rank));
+
+        match (file, rank) {
+            (Some(file), Some(rank)) => Ok(Some(file + (rank - 1) * width)),
+            _ => Err(anyhow::anyhow!("bad en passant: {en_passant}")),
         }
-
-        let chars = en_passant.chars().collect::<Vec<_>>();
-        let (file, rank) = (chars[0], chars[1]);
-
-        let file = match file {
-            'a' => 0,
-            'b' => 1,
-            'c' => 2,
-            'd' => 3,
-            'e' => 4,
-            'f' => 5,
-            'g' => 6,
-            'h' => 7,
-            _ => return Err(anyhow::anyhow!("bad en passant: {en_passant}")),
-        };
-
-        let rank = match rank {
-            '1' => 0,
-            '2' => 1,
-            '3' => 2,
-            '4' => 3,
-            '5' => 4,
-            '6' => 5,
-            '7' => 6,
-            '8' => 7,
-            _ => return Err(anyhow::anyhow!("bad en passant: {en_passant}")),
-        };
-
-        Ok(Some(file + rank * 8))
     }
 
     fn parse_halfmove(halfmove: &str) -> anyhow::Result<u32> {
@@ -148,11 +152,11 @@ impl BoardState {
     fn make_placement(&self) -> String {
         let mut placement = String::new();
 
-        for rank in (0..8).rev() {
+        for rank in (0..self.height).rev() {
             let mut blanks = 0;
 
-            for file in 0..8 {
-                match self.pieces[rank * 8 + file] {
+            for file in 0..self.width {
+                match self.pieces[(rank * self.width + file) as usize] {
                     Some(ref piece) => {
                         if blanks != 0 {
                             placement.push_str(&blanks.to_string());
@@ -207,29 +211,8 @@ impl BoardState {
     fn make_en_passant(&self) -> String {
         match self.en_passant_square {
             Some(en_passant) => {
-                let file = match en_passant % 8 {
-                    0 => 'a',
-                    1 => 'b',
-                    2 => 'c',
-                    3 => 'd',
-                    4 => 'e',
-                    5 => 'f',
-                    6 => 'g',
-                    7 => 'h',
-                    _ => unreachable!(),
-                };
-
-                let rank = match en_passant / 8 {
-                    0 => '1',
-                    1 => '2',
-                    2 => '3',
-                    3 => '4',
-                    4 => '5',
-                    5 => '6',
-                    6 => '7',
-                    7 => '8',
-                    _ => unreachable!(),
-                };
+                let file = char::from(b'a' + self.file_of(en_passant) as u8);
+                let rank = self.rank_of(en_passant) + 1;
 
                 format!("{}{}", file, rank)
             }
@@ -246,3 +229,41 @@ impl BoardState {
         self.fullmove_number.to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::logic::{BoardState, CAPABLANCA_FEN, GOTHIC_FEN, STARTING_FEN};
+
+    #[test]
+    fn round_trips() {
+        for fen in [
+            STARTING_FEN,
+            CAPABLANCA_FEN,
+            GOTHIC_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "r4k3r/10/10/2p7/3Pp5/10/10/R4K3R b Kq d3 0 12",
+        ] {
+            assert_eq!(BoardState::from_fen(fen).unwrap().to_fen(), fen);
+        }
+        let board = BoardState::from_fen(CAPABLANCA_FEN).unwrap();
+        assert_eq!((board.width, board.height), (10, 8));
+    }
+
+    #[test]
+    fn rejects_bad_placements() {
+        for fen in [
+            "99999999999999999999999/8/8/8/8/8/8/K6k w - - 0 1",
+            "999999/8/8/8/8/8/8/K6k w - - 0 1",
+            "129/8/8/8/8/8/8/K6k w - - 0 1",
+            "9/8/8/8/8/8/8/K6k w - - 0 1",
+            "8/8/K6k w - - 0 1",
+            "17/17/17/17/17/17/17/K15k w - - 0 1",
+            "8/8/8/8/8/8/8/K6k w - - 0",
+            "8/8/8/8/8/8/8/K6k x - - 0 1",
+            "8/8/8/8/8/8/8/K6k w - i3 0 1",
+        ] {
+            assert!(BoardState::from_fen(fen).is_err(), "{fen}");
+        }
+    }
+}
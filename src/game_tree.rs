@@ -0,0 +1,259 @@
+use crate::annotations::Annotations;
+use crate::logic::{BoardState, Move};
+
+/// The start position's node.
+pub const ROOT: usize = 0;
+
+/// One position of a game, reached from its parent by `mv`.
+#[derive(Clone, Debug, Default)]
+pub struct Node {
+    /// `None` for the start position.
+    pub mv: Option<Move>,
+    pub parent: Option<usize>,
+    /// Moves played from here, the main line's first and variations after it.
+    pub children: Vec<usize>,
+    /// Numeric annotation glyphs of the move, 1 for `!`, 2 for `?` and so on.
+    pub nags: Vec<u8>,
+    pub comment: String,
+    /// Marks drawn on the position.
+    pub annotations: Annotations,
+}
+
+/// A game with its variations, and the position it is looked at in.
+pub struct GameTree {
+    start: BoardState,
+    nodes: Vec<Node>,
+    current: usize,
+    // the position at `current`
+    board: BoardState,
+}
+
+impl GameTree {
+    pub fn new(start: BoardState) -> Self {
+        Self {
+            board: start.clone(),
+            start,
+            nodes: vec![Node::default()],
+            current: ROOT,
+        }
+    }
+
+    pub fn start(&self) -> &BoardState {
+        &self.start
+    }
+
+    /// The position at the current node.
+    pub fn board(&self) -> &BoardState {
+        &self.board
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn node(&self, node: usize) -> &Node {
+        &self.nodes[node]
+    }
+
+    pub fn node_mut(&mut self, node: usize) -> &mut Node {
+        &mut self.nodes[node]
+    }
+
+    /// Whether nothing was played from the current position yet.
+    pub fn at_end(&self) -> bool {
+        self.nodes[self.current].children.is_empty()
+    }
+
+    /// Adds `mv` from `parent` unless it is there already, as the main line if nothing was
+    /// played from `parent` before and as a variation otherwise. Returns the move's node.
+    pub fn add(&mut self, parent: usize, mv: Move) -> usize {
+        if let Some(&child) = self.nodes[parent]
+            .children
+            .iter()
+            .find(|&&child| self.nodes[child].mv == Some(mv))
+        {
+            return child;
+        }
+        self.nodes.push(Node {
+            mv: Some(mv),
+            parent: Some(parent),
+            ..Default::default()
+        });
+        let child = self.nodes.len() - 1;
+        self.nodes[parent].children.push(child);
+        child
+    }
+
+    /// Plays `mv` from the current position, following the line that continues with it or
+    /// branching off a new one.
+    pub fn play(&mut self, mv: Move) {
+        self.current = self.add(self.current, mv);
+        self.board.make(mv);
+        self.board.game_over = self.board.is_game_over();
+    }
+
+    /// Takes back the current move.
+    pub fn back(&mut self) -> bool {
+        match self.nodes[self.current].parent {
+            Some(parent) => {
+                self.go_to(parent);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replays the next move of the current line.
+    pub fn forward(&mut self) -> bool {
+        match self.nodes[self.current].children.first() {
+            Some(&child) => {
+                let mv = self.nodes[child].mv.unwrap();
+                self.play(mv);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Swaps the current move for the next of its alternatives, or the previous one for a
+    /// negative `step`.
+    pub fn switch_variation(&mut self, step: isize) -> bool {
+        let Some(parent) = self.nodes[self.current].parent else {
+            return false;
+        };
+        let siblings = &self.nodes[parent].children;
+        let index = siblings
+            .iter()
+            .position(|&node| node == self.current)
+            .unwrap();
+        match index
+            .checked_add_signed(step)
+            .and_then(|index| siblings.get(index))
+        {
+            Some(&sibling) => {
+                self.go_to(sibling);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Goes to the end of the current line.
+    pub fn end(&mut self) {
+        while self.forward() {}
+    }
+
+    /// Jumps to `node`, replaying its moves from the start.
+    pub fn go_to(&mut self, node: usize) {
+        self.board = self.start.clone();
+        for step in self.path(node) {
+            self.board.make(self.nodes[step].mv.unwrap());
+        }
+        self.board.game_over = self.board.is_game_over();
+        self.current = node;
+    }
+
+    /// Nodes on the way from the start to `node`, without the start.
+    pub fn path(&self, mut node: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        while let Some(parent) = self.nodes[node].parent {
+            path.push(node);
+            node = parent;
+        }
+        path.reverse();
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::STARTING_FEN;
+
+    fn tree() -> GameTree {
+        GameTree::new(BoardState::from_fen(STARTING_FEN).unwrap())
+    }
+
+    // plays `names` from the current position, returning the node reached
+    fn play(tree: &mut GameTree, names: &[&str]) -> usize {
+        for name in names {
+            let mv = tree.board().parse_move(name).unwrap();
+            tree.play(mv);
+        }
+        tree.current()
+    }
+
+    #[test]
+    fn adds_moves_once() {
+        let mut tree = tree();
+        let e4 = tree.board().parse_move("e2e4").unwrap();
+        let d4 = tree.board().parse_move("d2d4").unwrap();
+        let node = tree.add(ROOT, e4);
+        assert_eq!(tree.add(ROOT, e4), node);
+        let variation = tree.add(ROOT, d4);
+        assert_ne!(variation, node);
+        assert_eq!(tree.node(ROOT).children, [node, variation]);
+        assert_eq!(tree.node(variation).parent, Some(ROOT));
+
+        // playing a move that is there already follows it
+        tree.play(e4);
+        assert_eq!(tree.current(), node);
+        assert_eq!(tree.node(ROOT).children.len(), 2);
+    }
+
+    #[test]
+    fn walks_the_tree() {
+        let mut tree = tree();
+        let nf3 = play(&mut tree, &["e2e4", "e7e5", "g1f3"]);
+        let e5 = tree.node(nf3).parent.unwrap();
+        let e4 = tree.node(e5).parent.unwrap();
+        assert_eq!(tree.path(nf3), [e4, e5, nf3]);
+        assert!(tree.path(ROOT).is_empty());
+        assert!(tree.at_end());
+
+        assert!(tree.back());
+        assert_eq!(tree.current(), e5);
+        assert!(tree.board().white_to_play);
+        assert!(tree.forward());
+        assert_eq!(tree.current(), nf3);
+        assert!(!tree.forward());
+
+        tree.go_to(e4);
+        assert_eq!(
+            tree.board().to_fen(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+        tree.end();
+        assert_eq!(tree.current(), nf3);
+        tree.go_to(ROOT);
+        assert!(!tree.back());
+        assert_eq!(tree.board().to_fen(), STARTING_FEN);
+    }
+
+    #[test]
+    fn switches_variations() {
+        let mut tree = tree();
+        let e4 = play(&mut tree, &["e2e4"]);
+        tree.back();
+        let d4 = play(&mut tree, &["d2d4"]);
+        tree.back();
+        let c4 = play(&mut tree, &["c2c4"]);
+        assert_eq!(tree.node(ROOT).children, [e4, d4, c4]);
+
+        assert!(tree.switch_variation(-1));
+        assert_eq!(tree.current(), d4);
+        assert!(tree.switch_variation(-1));
+        assert_eq!(tree.current(), e4);
+        assert!(!tree.switch_variation(-1));
+        assert!(tree.switch_variation(2));
+        assert_eq!(tree.current(), c4);
+        assert!(!tree.switch_variation(1));
+        assert_eq!(
+            tree.board().to_fen(),
+            "rnbqkbnr/pppppppp/8/8/2P5/8/PP1PPPPP/RNBQKBNR b KQkq c3 0 1"
+        );
+
+        tree.go_to(ROOT);
+        assert!(!tree.switch_variation(1));
+    }
+}
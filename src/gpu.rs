@@ -0,0 +1,47 @@
+/// A device without a window, for compute work from the command line and in tests. Any adapter
+/// that runs compute shaders will do, a software one included.
+pub fn headless() -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    });
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        compatible_surface: None,
+        force_fallback_adapter: false,
+    }))?;
+    if !adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+    {
+        return Err(anyhow::anyhow!(
+            "{} can't run compute shaders",
+            adapter.get_info().name
+        ));
+    }
+    log::info!("compute on {}", adapter.get_info().name);
+    Ok(pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: Some("Headless Device"),
+            ..Default::default()
+        },
+    ))?)
+}
+
+/// Compiles `files` from `assets` as one module, which is how the compute shaders get the
+/// move generator of `movegen.wgsl`.
+pub fn shader(
+    device: &wgpu::Device,
+    label: &str,
+    files: &[&str],
+) -> anyhow::Result<wgpu::ShaderModule> {
+    let mut source = String::new();
+    for file in files {
+        source += &std::fs::read_to_string(std::path::Path::new("assets").join(file))?;
+    }
+    Ok(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    }))
+}
@@ -1,6 +1,9 @@
+use crate::nnue::NnueState;
+use crate::pieces::{self, Movement, PieceDef};
+
 pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum PieceKind {
     Pawn,
     Knight,
@@ -8,16 +11,32 @@ pub enum PieceKind {
     Rook,
     Queen,
     King,
+    Fairy(&'static PieceDef),
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+impl PieceKind {
+    pub fn movement(&self) -> &'static [Movement] {
+        match self {
+            PieceKind::Pawn => &[],
+            PieceKind::Knight => pieces::KNIGHT,
+            PieceKind::Bishop => pieces::BISHOP,
+            PieceKind::Rook => pieces::ROOK,
+            PieceKind::Queen => pieces::QUEEN,
+            PieceKind::King => pieces::KING,
+            PieceKind::Fairy(def) => def.movement,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Piece {
     pub kind: PieceKind,
     pub white: bool,
 }
 
 impl Piece {
-    pub fn from_char(c: char) -> Self {
+    /// The piece a FEN letter stands for, `None` for letters no piece has.
+    pub fn from_char(c: char) -> Option<Self> {
         let (kind, white) = match c {
             'P' => (PieceKind::Pawn, true),
             'N' => (PieceKind::Knight, true),
@@ -31,9 +50,12 @@ impl Piece {
             'r' => (PieceKind::Rook, false),
             'q' => (PieceKind::Queen, false),
             'k' => (PieceKind::King, false),
-            x => panic!("Invalid piece {x}"),
+            x => (
+                PieceKind::Fairy(pieces::find(x.to_ascii_uppercase())?),
+                x.is_ascii_uppercase(),
+            ),
         };
-        Self { kind, white }
+        Some(Self { kind, white })
     }
     pub fn to_char(&self) -> char {
         match (&self.kind, self.white) {
@@ -49,6 +71,8 @@ impl Piece {
             (PieceKind::Rook, false) => 'r',
             (PieceKind::Queen, false) => 'q',
             (PieceKind::King, false) => 'k',
+            (PieceKind::Fairy(def), true) => def.symbol,
+            (PieceKind::Fairy(def), false) => def.symbol.to_ascii_lowercase(),
         }
     }
     pub fn to_idx(&self) -> u32 {
@@ -65,13 +89,35 @@ impl Piece {
             (PieceKind::Queen, false) => 9,
             (PieceKind::King, false) => 10,
             (PieceKind::Pawn, false) => 11,
+            // fairy sprites follow the standard twelve, white then black
+            (PieceKind::Fairy(def), white) => {
+                12 + 2 * pieces::index_of(def).expect("unregistered piece") as u32 + !white as u32
+            }
         }
     }
 }
 
+/// Largest board the renderer can show, limited by the legal move mask in `GameInfo`.
+pub const MAX_SQUARES: u32 = 128;
+
+pub const CAPABLANCA_FEN: &str =
+    "rnabqkbcnr/pppppppppp/10/10/10/10/PPPPPPPPPP/RNABQKBCNR w KQkq - 0 1";
+pub const GOTHIC_FEN: &str = "rnbqckabnr/pppppppppp/10/10/10/10/PPPPPPPPPP/RNBQCKABNR w KQkq - 0 1";
+
+pub fn variant_fen(name: &str) -> Option<&'static str> {
+    match name {
+        "standard" => Some(STARTING_FEN),
+        "capablanca" => Some(CAPABLANCA_FEN),
+        "gothic" => Some(GOTHIC_FEN),
+        _ => None,
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct BoardState {
     pub pieces: Vec<Option<Piece>>,
+    pub width: u32,
+    pub height: u32,
     pub white_to_play: bool,
     pub white_can_oo: bool,
     pub white_can_ooo: bool,
@@ -81,341 +127,447 @@ pub struct BoardState {
     pub halfmove_clock: u32,
     pub fullmove_number: u32,
     pub game_over: u32, // 0 = ongoing, 1 = white wins, 2 = black wins, 3 = draw
+    /// Dark chess: there is no check, each side only sees where its pieces can move
+    /// and the game is won by capturing the king.
+    pub fog_of_war: bool,
+    /// Zobrist hash, kept up to date by `make` and `unmake`. Call `rehash` after setting the
+    /// other fields by hand.
+    pub key: u64,
+}
+
+/// A move from one square to another, `promotion` is only set for pawns reaching the last rank.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Move {
+    pub from: u32,
+    pub to: u32,
+    pub promotion: Option<PieceKind>,
+}
+
+impl Move {
+    pub fn new(from: u32, to: u32) -> Self {
+        Self {
+            from,
+            to,
+            promotion: None,
+        }
+    }
+}
+
+/// Everything `make` changed, so `unmake` can put it back.
+#[derive(Clone, Copy, Debug)]
+pub struct Undo {
+    // squares in the order they were written, with what was there before
+    changed: [Option<(u32, Option<Piece>)>; 4],
+    castling: [bool; 4],
+    en_passant_square: Option<u32>,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+    key: u64,
+}
+
+impl Undo {
+    /// Squares the move wrote to, each once, with what stood there before the move.
+    pub fn changed(&self) -> impl Iterator<Item = (u32, Option<Piece>)> + '_ {
+        let changed = self.changed.iter().flatten();
+        changed
+            .clone()
+            .enumerate()
+            .filter(move |&(i, (square, _))| {
+                !changed
+                    .clone()
+                    .take(i)
+                    .any(|(earlier, _)| earlier == square)
+            })
+            .map(|(_, &change)| change)
+    }
 }
 
 impl BoardState {
-    pub fn legal_moves(&self, square: u32) -> Vec<u32> {
-        let mut moves = Vec::new();
-        let Some(king_square) = self
-            .pieces
+    pub fn rank_of(&self, square: u32) -> u32 {
+        square / self.width
+    }
+
+    pub fn file_of(&self, square: u32) -> u32 {
+        square % self.width
+    }
+
+    /// Square at `(rank, file)`, or `None` when that falls off the board.
+    pub fn square_at(&self, rank: i32, file: i32) -> Option<u32> {
+        if (0..self.height as i32).contains(&rank) && (0..self.width as i32).contains(&file) {
+            Some(rank as u32 * self.width + file as u32)
+        } else {
+            None
+        }
+    }
+
+    /// Coordinate name like `e4`, ranks past 9 just get two digits.
+    pub fn square_name(&self, square: u32) -> String {
+        format!(
+            "{}{}",
+            char::from(b'a' + self.file_of(square) as u8),
+            self.rank_of(square) + 1
+        )
+    }
+
+    pub fn parse_square(&self, name: &str) -> Option<u32> {
+        let mut chars = name.chars();
+        let file = chars.next()? as i32 - 'a' as i32;
+        let rank = chars.as_str().parse::<i32>().ok()? - 1;
+        self.square_at(rank, file)
+    }
+
+    /// Long algebraic notation as spoken by UCI, e.g. `e2e4` or `e7e8q`.
+    pub fn move_name(&self, mv: Move) -> String {
+        let mut name = self.square_name(mv.from) + &self.square_name(mv.to);
+        if let Some(kind) = mv.promotion {
+            name.push(Piece { kind, white: false }.to_char());
+        }
+        name
+    }
+
+    /// Finds the legal move written as `move_name` would write it.
+    pub fn parse_move(&self, text: &str) -> Option<Move> {
+        self.moves()
+            .into_iter()
+            .find(|&mv| self.move_name(mv) == text)
+    }
+
+    pub fn king_square(&self, white: bool) -> Option<u32> {
+        self.pieces
             .iter()
-            .position(|p| matches!(p, Some(piece) if piece.kind == PieceKind::King && piece.white == self.white_to_play))
-        else {
-            return moves
-        };
-        let king_square = king_square as u32;
-        if let Some(piece) = &self.pieces[square as usize] {
-            let checked_squares = if self.white_to_play == piece.white {
-                self.checked_squares()
-            } else {
-                Vec::new()
-            };
-            match piece.kind {
-                PieceKind::Pawn => {
-                    let direction: i8 = if piece.white { 1 } else { -1 };
-                    let start_rank: u32 = if piece.white { 1 } else { 6 };
-                    let rank = square / 8;
-                    let file = square % 8;
-
-                    let forward_square = (rank as i8 + direction) * 8 + file as i8;
-                    if (0..64).contains(&forward_square)
-                        && self.pieces[forward_square as usize].is_none()
-                    {
-                        moves.push(forward_square as u32);
-
-                        if rank == start_rank {
-                            let double_forward_square =
-                                (rank as i8 + 2 * direction) * 8 + file as i8;
-                            if (0..64).contains(&double_forward_square)
-                                && self.pieces[double_forward_square as usize].is_none()
-                            {
-                                moves.push(double_forward_square as u32);
-                            }
-                        }
-                    }
+            .position(|p| matches!(p, Some(piece) if piece.kind == PieceKind::King && piece.white == white))
+            .map(|square| square as u32)
+    }
 
-                    for &df in &[-1, 1] {
-                        let capture_file = file as i8 + df;
-                        let capture_rank = rank as i8 + direction;
-                        if (0..8).contains(&capture_file) && (0..8).contains(&capture_rank) {
-                            let capture_square = capture_rank * 8 + capture_file;
-                            if let Some(target_piece) = &self.pieces[capture_square as usize] {
-                                if target_piece.white != piece.white {
-                                    moves.push(capture_square as u32);
-                                }
-                            } else if Some(capture_square as u32) == self.en_passant_square {
-                                moves.push(capture_square as u32);
-                            }
-                        }
+    pub fn is_capture(&self, mv: Move) -> bool {
+        self.pieces[mv.to as usize].is_some()
+            || matches!(&self.pieces[mv.from as usize], Some(piece) if piece.kind == PieceKind::Pawn && self.file_of(mv.from) != self.file_of(mv.to))
+    }
+
+    /// Whether the side to play is in check, which never happens in fog of war.
+    pub fn in_check(&self) -> bool {
+        !self.fog_of_war
+            && self
+                .king_square(self.white_to_play)
+                .is_some_and(|king| self.is_attacked(king, !self.white_to_play))
+    }
+
+    /// Whether any of `by_white`'s pieces attacks `square`.
+    pub fn is_attacked(&self, square: u32, by_white: bool) -> bool {
+        self.pieces.iter().enumerate().any(|(from, piece)| {
+            matches!(piece, Some(piece) if piece.white == by_white && self.attacks_square(from as u32, piece, square))
+        })
+    }
+
+    fn attacks_square(&self, from: u32, piece: &Piece, to: u32) -> bool {
+        let dr = self.rank_of(to) as i32 - self.rank_of(from) as i32;
+        let df = self.file_of(to) as i32 - self.file_of(from) as i32;
+        if piece.kind == PieceKind::Pawn {
+            return dr == if piece.white { 1 } else { -1 } && df.abs() == 1;
+        }
+        for movement in piece.kind.movement() {
+            for (sr, sf) in movement.directions() {
+                let (sr, sf) = (sr as i32, sf as i32);
+                if !movement.rides() {
+                    if (dr, df) == (sr, sf) {
+                        return true;
                     }
+                    continue;
                 }
-                PieceKind::Bishop => {
-                    for &(dr, df) in &[(-1, -1), (-1, 1), (1, -1), (1, 1)] {
-                        let mut rank = (square / 8) as i8;
-                        let mut file = (square % 8) as i8;
-                        loop {
-                            rank += dr;
-                            file += df;
-                            if !(0..8).contains(&rank) || !(0..8).contains(&file) {
-                                break;
-                            }
-                            let target_square = rank * 8 + file;
-                            if let Some(target_piece) = &self.pieces[target_square as usize] {
-                                if target_piece.white != piece.white {
-                                    moves.push(target_square as u32);
-                                }
-                                break;
-                            } else {
-                                moves.push(target_square as u32);
-                            }
-                        }
-                    }
+                let steps = if sr != 0 { dr / sr } else { df / sf };
+                if steps < 1 || (steps * sr, steps * sf) != (dr, df) {
+                    continue;
                 }
-                PieceKind::Rook => {
-                    for &(dr, df) in &[(-1, 0), (1, 0), (0, -1), (0, 1)] {
-                        let mut rank = (square / 8) as i8;
-                        let mut file = (square % 8) as i8;
-                        loop {
-                            rank += dr;
-                            file += df;
-                            if !(0..8).contains(&rank) || !(0..8).contains(&file) {
-                                break;
-                            }
-                            let target_square = rank * 8 + file;
-                            if let Some(target_piece) = &self.pieces[target_square as usize] {
-                                if target_piece.white != piece.white {
-                                    moves.push(target_square as u32);
-                                }
-                                break;
-                            } else {
-                                moves.push(target_square as u32);
-                            }
-                        }
-                    }
+                let mut rank = self.rank_of(from) as i32;
+                let mut file = self.file_of(from) as i32;
+                let clear = (1..steps).all(|_| {
+                    rank += sr;
+                    file += sf;
+                    self.pieces[(rank as u32 * self.width + file as u32) as usize].is_none()
+                });
+                if clear {
+                    return true;
                 }
-                PieceKind::Queen => {
-                    for &(dr, df) in &[
-                        (-1, -1),
-                        (-1, 1),
-                        (1, -1),
-                        (1, 1),
-                        (-1, 0),
-                        (1, 0),
-                        (0, -1),
-                        (0, 1),
-                    ] {
-                        let mut rank = (square / 8) as i8;
-                        let mut file = (square % 8) as i8;
-                        loop {
-                            rank += dr;
-                            file += df;
-                            if !(0..8).contains(&rank) || !(0..8).contains(&file) {
-                                break;
-                            }
-                            let target_square = rank * 8 + file;
-                            if let Some(target_piece) = &self.pieces[target_square as usize] {
-                                if target_piece.white != piece.white {
-                                    moves.push(target_square as u32);
-                                }
-                                break;
-                            } else {
-                                moves.push(target_square as u32);
-                            }
-                        }
+            }
+        }
+        false
+    }
+
+    /// What pawns may promote to: the standard pieces and any fairy piece in play.
+    pub fn promotion_kinds(&self) -> Vec<PieceKind> {
+        let mut kinds = vec![
+            PieceKind::Queen,
+            PieceKind::Rook,
+            PieceKind::Bishop,
+            PieceKind::Knight,
+        ];
+        for piece in self.pieces.iter().flatten() {
+            if matches!(piece.kind, PieceKind::Fairy(_)) && !kinds.contains(&piece.kind) {
+                kinds.push(piece.kind);
+            }
+        }
+        kinds
+    }
+
+    /// Every legal move for the side to play.
+    pub fn moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for (square, piece) in self.pieces.iter().enumerate() {
+            if matches!(piece, Some(piece) if piece.white == self.white_to_play) {
+                self.pseudo_moves(square as u32, &mut moves);
+            }
+        }
+        self.retain_legal(&mut moves);
+        moves
+    }
+
+    /// Legal moves of the piece on `square`, which has to belong to the side to play.
+    pub fn moves_from(&self, square: u32) -> Vec<Move> {
+        let mut moves = Vec::new();
+        if matches!(&self.pieces[square as usize], Some(piece) if piece.white == self.white_to_play)
+        {
+            self.pseudo_moves(square, &mut moves);
+            self.retain_legal(&mut moves);
+        }
+        moves
+    }
+
+    /// Drops moves that leave the mover's own king attacked.
+    fn retain_legal(&self, moves: &mut Vec<Move>) {
+        if self.king_square(self.white_to_play).is_none() {
+            moves.clear();
+            return;
+        }
+        if self.fog_of_war {
+            return;
+        }
+        let mut board = self.clone();
+        moves.retain(|&mv| {
+            let undo = board.make(mv);
+            let legal = board
+                .king_square(!board.white_to_play)
+                .is_some_and(|king| !board.is_attacked(king, board.white_to_play));
+            board.unmake(undo);
+            legal
+        });
+    }
+
+    /// Squares the piece on `square` can move to. For the side to play these are legal moves,
+    /// for the other side they ignore check, which is what `checked_squares` wants.
+    pub fn legal_moves(&self, square: u32) -> Vec<u32> {
+        let Some(piece) = &self.pieces[square as usize] else {
+            return Vec::new();
+        };
+        let moves = if piece.white == self.white_to_play {
+            self.moves_from(square)
+        } else {
+            let mut moves = Vec::new();
+            self.pseudo_moves(square, &mut moves);
+            moves
+        };
+        let mut targets = Vec::new();
+        for mv in moves {
+            if !targets.contains(&mv.to) {
+                targets.push(mv.to);
+            }
+        }
+        targets
+    }
+
+    fn pseudo_moves(&self, square: u32, moves: &mut Vec<Move>) {
+        let Some(piece) = self.pieces[square as usize] else {
+            return;
+        };
+        match piece.kind {
+            PieceKind::Pawn => {
+                let mut targets = Vec::new();
+                let direction: i32 = if piece.white { 1 } else { -1 };
+                let start_rank: u32 = if piece.white { 1 } else { self.height - 2 };
+                let rank = self.rank_of(square) as i32;
+                let file = self.file_of(square) as i32;
+
+                if let Some(forward_square) = self.square_at(rank + direction, file)
+                    && self.pieces[forward_square as usize].is_none()
+                {
+                    targets.push(forward_square);
+
+                    if rank as u32 == start_rank
+                        && let Some(double_forward_square) =
+                            self.square_at(rank + 2 * direction, file)
+                        && self.pieces[double_forward_square as usize].is_none()
+                    {
+                        targets.push(double_forward_square);
                     }
                 }
-                PieceKind::Knight => {
-                    let rank = (square / 8) as i8;
-                    let file = (square % 8) as i8;
-                    for &(dr, df) in &[
-                        (-2, -1),
-                        (-2, 1),
-                        (-1, -2),
-                        (-1, 2),
-                        (1, -2),
-                        (1, 2),
-                        (2, -1),
-                        (2, 1),
-                    ] {
-                        let new_rank = rank + dr;
-                        let new_file = file + df;
-                        if (0..8).contains(&new_rank) && (0..8).contains(&new_file) {
-                            let target_square = new_rank * 8 + new_file;
-                            if let Some(target_piece) = &self.pieces[target_square as usize] {
-                                if target_piece.white != piece.white {
-                                    moves.push(target_square as u32);
-                                }
-                            } else {
-                                moves.push(target_square as u32);
+
+                for df in [-1, 1] {
+                    if let Some(capture_square) = self.square_at(rank + direction, file + df) {
+                        if let Some(target_piece) = &self.pieces[capture_square as usize] {
+                            if target_piece.white != piece.white {
+                                targets.push(capture_square);
                             }
+                        } else if Some(capture_square) == self.en_passant_square {
+                            targets.push(capture_square);
                         }
                     }
                 }
-                PieceKind::King => {
-                    let rank = (square / 8) as i8;
-                    let file = (square % 8) as i8;
-                    for &(dr, df) in &[
-                        (-1, -1),
-                        (-1, 0),
-                        (-1, 1),
-                        (0, -1),
-                        (0, 1),
-                        (1, -1),
-                        (1, 0),
-                        (1, 1),
-                    ] {
-                        let new_rank = rank + dr;
-                        let new_file = file + df;
-                        if (0..8).contains(&new_rank) && (0..8).contains(&new_file) {
-                            let target_square = new_rank * 8 + new_file;
-
-                            if self.white_to_play == piece.white
-                                && checked_squares.contains(&(target_square as u32))
-                            {
-                                continue;
-                            }
-                            if let Some(target_piece) = &self.pieces[target_square as usize] {
-                                if target_piece.white != piece.white {
-                                    moves.push(target_square as u32);
-                                }
-                            } else {
-                                moves.push(target_square as u32);
-                            }
+
+                let last_rank = if piece.white { self.height - 1 } else { 0 };
+                for to in targets {
+                    if self.rank_of(to) == last_rank {
+                        for kind in self.promotion_kinds() {
+                            moves.push(Move {
+                                from: square,
+                                to,
+                                promotion: Some(kind),
+                            });
                         }
+                    } else {
+                        moves.push(Move::new(square, to));
                     }
-                    if !checked_squares.contains(&square) {
-                        if piece.white {
-                            if self.white_can_ooo
-                                && self.pieces[1].is_none()
-                                && self.pieces[2].is_none()
-                                && self.pieces[3].is_none()
-                                && !checked_squares.contains(&2)
-                                && !checked_squares.contains(&3)
-                                && !moves.contains(&2)
-                            {
-                                moves.push(2);
-                            }
-                            if self.white_can_oo
-                                && self.pieces[5].is_none()
-                                && self.pieces[6].is_none()
-                                && !checked_squares.contains(&5)
-                                && !checked_squares.contains(&6)
-                                && !moves.contains(&6)
-                            {
-                                moves.push(6);
-                            }
-                        } else {
-                            if self.black_can_ooo
-                                && self.pieces[57].is_none()
-                                && self.pieces[58].is_none()
-                                && self.pieces[59].is_none()
-                                && !checked_squares.contains(&58)
-                                && !checked_squares.contains(&59)
-                                && !moves.contains(&58)
-                            {
-                                moves.push(58);
-                            }
-                            if self.black_can_oo
-                                && self.pieces[61].is_none()
-                                && self.pieces[62].is_none()
-                                && !checked_squares.contains(&61)
-                                && !checked_squares.contains(&62)
-                                && !moves.contains(&62)
-                            {
-                                moves.push(62);
-                            }
+                }
+            }
+            PieceKind::King => {
+                for to in self.movement_moves(square, &piece) {
+                    moves.push(Move::new(square, to));
+                }
+                if self.fog_of_war || !self.is_attacked(square, !piece.white) {
+                    let (can_oo, can_ooo) = if piece.white {
+                        (self.white_can_oo, self.white_can_ooo)
+                    } else {
+                        (self.black_can_oo, self.black_can_ooo)
+                    };
+                    // the king lands on the c and second-to-last files, the rook right beside it
+                    for (allowed, king_file, rook_file) in
+                        [(can_ooo, 2, 0), (can_oo, self.width - 2, self.width - 1)]
+                    {
+                        if allowed
+                            && let Some(to) =
+                                self.castling_target(square, &piece, king_file, rook_file)
+                            && !moves.contains(&Move::new(square, to))
+                        {
+                            moves.push(Move::new(square, to));
                         }
                     }
                 }
             }
+            _ => {
+                for to in self.movement_moves(square, &piece) {
+                    moves.push(Move::new(square, to));
+                }
+            }
+        }
+    }
 
-            if piece.kind != PieceKind::King
-                && piece.white == self.white_to_play
-                && checked_squares.contains(&king_square)
+    fn castling_target(
+        &self,
+        square: u32,
+        king: &Piece,
+        king_file: u32,
+        rook_file: u32,
+    ) -> Option<u32> {
+        let back_rank = if king.white { 0 } else { self.height - 1 };
+        if self.rank_of(square) != back_rank {
+            return None;
+        }
+        let rook_square = back_rank * self.width + rook_file;
+        if !matches!(&self.pieces[rook_square as usize], Some(rook) if rook.kind == PieceKind::Rook && rook.white == king.white)
+        {
+            return None;
+        }
+        let king_target = back_rank * self.width + king_file;
+        let rook_target = if rook_file > king_file {
+            king_target - 1
+        } else {
+            king_target + 1
+        };
+
+        // everything between king and rook, and both landing squares, must be empty
+        let lowest = square.min(rook_square).min(king_target).min(rook_target);
+        let highest = square.max(rook_square).max(king_target).max(rook_target);
+        for between in lowest..=highest {
+            if between != square
+                && between != rook_square
+                && self.pieces[between as usize].is_some()
             {
-                let mut new_moves = Vec::new();
-                for &mv in moves.iter() {
-                    let mut temp_board = self.clone();
-                    temp_board.make_move(square, mv);
-                    temp_board.white_to_play = !temp_board.white_to_play;
-                    if !temp_board.checked_squares().contains(&king_square)
-                        && !new_moves.contains(&mv)
-                    {
-                        new_moves.push(mv);
-                    }
+                return None;
+            }
+        }
+
+        // and the king may not pass through an attacked square
+        if !self.fog_of_war {
+            for passed in square.min(king_target)..=square.max(king_target) {
+                if self.is_attacked(passed, !king.white) {
+                    return None;
                 }
-                return new_moves;
             }
         }
-        moves
+
+        Some(king_target)
     }
 
-    pub fn make_move(&mut self, from: u32, to: u32) {
-        let piece = self.pieces[from as usize].take();
-        if let Some(pc) = &piece {
-            match pc.kind {
-                PieceKind::Pawn => {
-                    if let Some(ep_square) = self.en_passant_square
-                        && to == ep_square
-                    {
-                        let capture_square = if pc.white { to - 8 } else { to + 8 };
-                        self.pieces[capture_square as usize] = None;
-                    }
-                    self.en_passant_square = None;
-                    if (pc.white && from / 8 == 1 && to / 8 == 3)
-                        || (!pc.white && from / 8 == 6 && to / 8 == 4)
-                    {
-                        self.en_passant_square = Some((from + to) / 2);
-                    }
-                    if (to / 8 == 7 && pc.white) || (to / 8 == 0 && !pc.white) {
-                        self.pieces[to as usize] = Some(Piece {
-                            kind: PieceKind::Queen, // TODO  make selectable
-                            white: pc.white,
-                        });
-                        self.white_to_play = !self.white_to_play;
-                    } else {
-                        self.pieces[to as usize] = piece.clone();
-                    }
-                }
-                PieceKind::King => {
-                    if pc.white {
-                        self.white_can_oo = false;
-                        self.white_can_ooo = false;
-                        // move rook
-                        if from == 4 && to == 2 {
-                            self.pieces[3] = self.pieces[0].take();
-                        } else if from == 4 && to == 6 {
-                            self.pieces[5] = self.pieces[7].take();
-                        }
-                    } else {
-                        self.black_can_oo = false;
-                        self.black_can_ooo = false;
-                        // move rook
-                        if from == 60 && to == 58 {
-                            self.pieces[59] = self.pieces[56].take();
-                        } else if from == 60 && to == 62 {
-                            self.pieces[61] = self.pieces[63].take();
-                        }
+    fn movement_moves(&self, square: u32, piece: &Piece) -> Vec<u32> {
+        self.movement_attacks(square, piece)
+            .into_iter()
+            .filter(|&target| !matches!(&self.pieces[target as usize], Some(other) if other.white == piece.white))
+            .collect()
+    }
+
+    /// Squares the piece on `square` attacks, empty or occupied by either side.
+    pub fn attacks(&self, square: u32) -> Vec<u32> {
+        let Some(piece) = &self.pieces[square as usize] else {
+            return Vec::new();
+        };
+        if piece.kind == PieceKind::Pawn {
+            let direction = if piece.white { 1 } else { -1 };
+            let rank = self.rank_of(square) as i32;
+            let file = self.file_of(square) as i32;
+            [-1, 1]
+                .into_iter()
+                .filter_map(|df| self.square_at(rank + direction, file + df))
+                .collect()
+        } else {
+            self.movement_attacks(square, piece)
+        }
+    }
+
+    fn movement_attacks(&self, square: u32, piece: &Piece) -> Vec<u32> {
+        let mut attacks = Vec::new();
+        for movement in piece.kind.movement() {
+            for (dr, df) in movement.directions() {
+                let mut rank = self.rank_of(square) as i32;
+                let mut file = self.file_of(square) as i32;
+                loop {
+                    rank += dr as i32;
+                    file += df as i32;
+                    let Some(target_square) = self.square_at(rank, file) else {
+                        break;
+                    };
+                    if !attacks.contains(&target_square) {
+                        attacks.push(target_square);
                     }
-                }
-                PieceKind::Rook => {
-                    if pc.white {
-                        if from == 0 {
-                            self.white_can_ooo = false;
-                        } else if from == 7 {
-                            self.white_can_oo = false;
-                        }
-                    } else if from == 56 {
-                        self.black_can_ooo = false;
-                    } else if from == 63 {
-                        self.black_can_oo = false;
+                    if self.pieces[target_square as usize].is_some() || !movement.rides() {
+                        break;
                     }
                 }
-                _ => {}
-            }
-
-            if !matches!(pc.kind, PieceKind::Pawn) {
-                self.en_passant_square = None;
-                self.pieces[to as usize] = piece;
             }
         }
-        self.white_to_play = !self.white_to_play;
+        attacks
+    }
+
+    pub fn make_move(&mut self, mv: Move) {
+        self.make(mv);
         self.game_over = self.is_game_over();
         // TODO make game over menu
         match self.game_over {
             1 => {
                 println!(
-                    "checkmate {} wins",
+                    "{} {} wins",
+                    if self.fog_of_war {
+                        "king captured,"
+                    } else {
+                        "checkmate"
+                    },
                     if !self.white_to_play {
                         "white"
                     } else {
@@ -433,6 +585,171 @@ impl BoardState {
         }
     }
 
+    /// Plays `mv`, promoting to a queen unless told otherwise. Hand the result to `unmake`
+    /// to take it back.
+    pub fn make(&mut self, mv: Move) -> Undo {
+        let mut undo = Undo {
+            changed: [None; 4],
+            castling: [
+                self.white_can_oo,
+                self.white_can_ooo,
+                self.black_can_oo,
+                self.black_can_ooo,
+            ],
+            en_passant_square: self.en_passant_square,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            key: self.key,
+        };
+        // side, castling and en passant keys come off here and go back on at the end
+        self.key ^= self.state_key();
+        let width = self.width;
+        let last_rank = self.height - 1;
+        let (from, to) = (mv.from, mv.to);
+
+        self.en_passant_square = None;
+        self.halfmove_clock += 1;
+        if let Some(mut piece) = self.pieces[from as usize] {
+            if self.pieces[to as usize].is_some() {
+                self.halfmove_clock = 0;
+            }
+            self.put(&mut undo, from, None);
+            match piece.kind {
+                PieceKind::Pawn => {
+                    self.halfmove_clock = 0;
+                    if Some(to) == undo.en_passant_square && self.file_of(from) != self.file_of(to)
+                    {
+                        let capture_square = if piece.white { to - width } else { to + width };
+                        self.put(&mut undo, capture_square, None);
+                    }
+                    if self.rank_of(from).abs_diff(self.rank_of(to)) == 2 {
+                        self.en_passant_square = Some((from + to) / 2);
+                    }
+                    if (self.rank_of(to) == last_rank && piece.white)
+                        || (self.rank_of(to) == 0 && !piece.white)
+                    {
+                        piece.kind = mv.promotion.unwrap_or(PieceKind::Queen);
+                    }
+                }
+                PieceKind::King => {
+                    if piece.white {
+                        self.white_can_oo = false;
+                        self.white_can_ooo = false;
+                    } else {
+                        self.black_can_oo = false;
+                        self.black_can_ooo = false;
+                    }
+                    // move rook
+                    if self.rank_of(from) == self.rank_of(to)
+                        && self.file_of(from).abs_diff(self.file_of(to)) >= 2
+                    {
+                        let back_rank = self.rank_of(from) * width;
+                        let (rook_from, rook_to) = if to > from {
+                            (back_rank + width - 1, to - 1)
+                        } else {
+                            (back_rank, to + 1)
+                        };
+                        let rook = self.pieces[rook_from as usize];
+                        self.put(&mut undo, rook_from, None);
+                        self.put(&mut undo, rook_to, rook);
+                    }
+                }
+                PieceKind::Rook => self.revoke_castling(from),
+                _ => {}
+            }
+            // a rook captured in its corner takes the castling right with it
+            self.revoke_castling(to);
+            self.put(&mut undo, to, Some(piece));
+        }
+
+        if !self.white_to_play {
+            self.fullmove_number += 1;
+        }
+        self.white_to_play = !self.white_to_play;
+        self.key ^= self.state_key();
+        undo
+    }
+
+    pub fn unmake(&mut self, undo: Undo) {
+        for &(square, piece) in undo.changed.iter().rev().flatten() {
+            self.pieces[square as usize] = piece;
+        }
+        [
+            self.white_can_oo,
+            self.white_can_ooo,
+            self.black_can_oo,
+            self.black_can_ooo,
+        ] = undo.castling;
+        self.en_passant_square = undo.en_passant_square;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.fullmove_number = undo.fullmove_number;
+        self.white_to_play = !self.white_to_play;
+        self.key = undo.key;
+    }
+
+    /// `make`, also updating the network accumulators in `nnue` for the squares it wrote.
+    pub fn make_with(&mut self, mv: Move, nnue: Option<&mut NnueState>) -> Undo {
+        let undo = self.make(mv);
+        if let Some(nnue) = nnue {
+            nnue.make(self, &undo);
+        }
+        undo
+    }
+
+    /// `unmake`, taking `nnue` back to the accumulators it had before the move.
+    pub fn unmake_with(&mut self, undo: Undo, nnue: Option<&mut NnueState>) {
+        self.unmake(undo);
+        if let Some(nnue) = nnue {
+            nnue.unmake();
+        }
+    }
+
+    fn put(&mut self, undo: &mut Undo, square: u32, piece: Option<Piece>) {
+        let slot = undo
+            .changed
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .expect("a move changes at most four squares");
+        let before = self.pieces[square as usize];
+        *slot = Some((square, before));
+        self.key ^= Self::piece_key(square, before) ^ Self::piece_key(square, piece);
+        self.pieces[square as usize] = piece;
+    }
+
+    fn revoke_castling(&mut self, corner: u32) {
+        let last_rank = self.height - 1;
+        if corner == 0 {
+            self.white_can_ooo = false;
+        } else if corner == self.width - 1 {
+            self.white_can_oo = false;
+        } else if corner == last_rank * self.width {
+            self.black_can_ooo = false;
+        } else if corner == last_rank * self.width + self.width - 1 {
+            self.black_can_oo = false;
+        }
+    }
+
+    /// Counts the leaf nodes of the legal move tree, the usual move generator check.
+    pub fn perft(&self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        let mut board = self.clone();
+        moves
+            .into_iter()
+            .map(|mv| {
+                let undo = board.make(mv);
+                let nodes = board.perft(depth - 1);
+                board.unmake(undo);
+                nodes
+            })
+            .sum()
+    }
+
     pub fn checked_squares(&self) -> Vec<u32> {
         let mut squares = Vec::new();
         for (i, square) in self.pieces.iter().enumerate() {
@@ -440,14 +757,8 @@ impl BoardState {
                 && piece.white != self.white_to_play
             {
                 for mv in self.legal_moves(i as u32) {
-                    if piece.kind == PieceKind::Pawn {
-                        if piece.white {
-                            if i as u32 + 8 == mv || i as u32 + 16 == mv {
-                                continue;
-                            }
-                        } else if i as u32 - 8 == mv || i as u32 - 16 == mv {
-                            continue;
-                        }
+                    if piece.kind == PieceKind::Pawn && self.file_of(i as u32) == self.file_of(mv) {
+                        continue;
                     }
                     if !squares.contains(&mv) {
                         squares.push(mv);
@@ -459,27 +770,122 @@ impl BoardState {
     }
 
     pub fn is_game_over(&self) -> u32 {
-        let Some(king_square) = self
-            .pieces
-            .iter()
-            .position(|p| matches!(p, Some(piece) if piece.kind == PieceKind::King && piece.white == self.white_to_play))
-            else {
-                println!("{:?}", self.pieces);
-                return 3
-            };
+        if self.king_square(self.white_to_play).is_none() {
+            if self.fog_of_war {
+                // the king was captured
+                return 1;
+            }
+            println!("{:?}", self.pieces);
+            return 3;
+        }
+        if !self.moves().is_empty() {
+            return 0;
+        }
+
+        if self.in_check() { 1 } else { 2 }
+    }
+
+    /// Squares `white`'s pieces stand on or can move to, everything else is fogged.
+    pub fn visible_squares(&self, white: bool) -> Vec<u32> {
+        let mut squares = Vec::new();
         for (i, square) in self.pieces.iter().enumerate() {
             if let Some(piece) = square
-                && piece.white == self.white_to_play
-                && !self.legal_moves(i as u32).is_empty()
+                && piece.white == white
             {
-                return 0;
+                squares.push(i as u32);
+                for mv in self.legal_moves(i as u32) {
+                    if !squares.contains(&mv) {
+                        squares.push(mv);
+                    }
+                }
             }
         }
+        squares
+    }
+}
 
-        if self.checked_squares().contains(&(king_square as u32)) {
-            1
-        } else {
-            2
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capablanca_perft() {
+        let board = BoardState::from_fen(CAPABLANCA_FEN).unwrap();
+        assert_eq!(board.perft(1), 28);
+        assert_eq!(board.perft(2), 784);
+        assert_eq!(board.perft(3), 25228);
+    }
+
+    #[test]
+    fn castles_on_ten_files() {
+        let mut board = BoardState::from_fen("r4k3r/10/10/10/10/10/10/R4K3R w KQkq - 0 1").unwrap();
+        let square = |board: &BoardState, name| board.parse_square(name).unwrap();
+        let king = square(&board, "f1");
+        let castlings = board
+            .moves()
+            .into_iter()
+            .filter(|mv| {
+                mv.from == king && board.file_of(mv.from).abs_diff(board.file_of(mv.to)) >= 2
+            })
+            .map(|mv| board.square_name(mv.to))
+            .collect::<Vec<_>>();
+        // the king lands on the third file and the second to last one
+        assert_eq!(castlings.len(), 2);
+        assert!(castlings.contains(&"c1".to_owned()) && castlings.contains(&"i1".to_owned()));
+
+        board.make(Move::new(king, square(&board, "i1")));
+        assert_eq!(board.to_fen(), "r4k3r/10/10/10/10/10/10/R6RK1 b kq - 1 1");
+        let king = square(&board, "f8");
+        board.make(Move::new(king, square(&board, "c8")));
+        assert_eq!(board.to_fen(), "2kr5r/10/10/10/10/10/10/R6RK1 w - - 2 2");
+        assert_eq!(
+            board.perft(2),
+            BoardState::from_fen(&board.to_fen()).unwrap().perft(2)
+        );
+    }
+
+    #[test]
+    fn fog_of_war_shows_only_reachable_squares() {
+        let mut board = BoardState::from_fen("4k3/8/8/p7/8/8/4P3/R3K3 w - - 0 1").unwrap();
+        board.fog_of_war = true;
+        let names = |squares: Vec<u32>| {
+            let mut names = squares
+                .into_iter()
+                .map(|square| board.square_name(square))
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        // the rook sees the pawn it can take but not past it
+        assert_eq!(
+            names(board.visible_squares(true)),
+            [
+                "a1", "a2", "a3", "a4", "a5", "b1", "c1", "d1", "d2", "e1", "e2", "e3", "e4", "f1",
+                "f2"
+            ]
+        );
+        assert_eq!(
+            names(board.visible_squares(false)),
+            ["a4", "a5", "d7", "d8", "e7", "e8", "f7", "f8"]
+        );
+    }
+
+    #[test]
+    fn fog_of_war_ends_when_the_king_is_taken() {
+        let mut board = BoardState::from_fen("4k3/8/8/8/8/8/8/4RK2 w - - 0 1").unwrap();
+        board.fog_of_war = true;
+        // the king isn't warned in the fog, so the rook simply takes it
+        board.white_to_play = false;
+        assert!(!board.in_check());
+        board.white_to_play = true;
+        let take = Move::new(
+            board.parse_square("e1").unwrap(),
+            board.parse_square("e8").unwrap(),
+        );
+        assert!(board.moves().contains(&take));
+        board.make(take);
+        assert_eq!(board.is_game_over(), 1);
+        assert!(!board.white_to_play);
+        assert!(board.moves().is_empty());
     }
 }
@@ -4,17 +4,59 @@ use glam::Vec3;
 use wgpu::util::DeviceExt;
 use winit::{
     application::ApplicationHandler,
-    dpi::PhysicalSize,
+    dpi::{PhysicalPosition, PhysicalSize},
     event::*,
     event_loop::{ActiveEventLoop, EventLoop},
-    keyboard::{KeyCode, PhysicalKey},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
     window::Window,
 };
 
+pub mod annotations;
+pub mod arrows;
+pub mod batch_eval;
+pub mod book;
+pub mod endgame;
+pub mod engine;
+pub mod eval;
 pub mod fen;
+pub mod game_tree;
+pub mod gpu;
 pub mod logic;
+pub mod move_list;
+pub mod movegen;
+pub mod nnue;
+pub mod perft;
+pub mod pgn;
+pub mod pieces;
+pub mod process;
+pub mod san;
+pub mod search;
+pub mod solver;
+pub mod syzygy;
 pub mod texture;
+pub mod tt;
+pub mod uci;
+pub mod uci_client;
 pub mod utils;
+pub mod xboard;
+pub mod xboard_client;
+pub mod zobrist;
+
+// arrows drawn at most, from every source together
+const MAX_ARROWS: usize = 256;
+
+// characters of the font atlas in order
+const CHARACTERS: &str =
+    "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+-=()[]{}<>/*:#%!?.,'\"@&$";
+
+// styles `assets/text.wgsl` gives characters of the move list, in the high half of their index
+const MOVE_LIST_STYLE: u32 = 1 << 16;
+const CURRENT_MOVE_STYLE: u32 = 2 << 16;
+// the move list right of the board, its top left corner and how far apart characters are
+const MOVE_LIST_POSITION: [f32; 2] = [0.53, 0.5];
+const MOVE_LIST_ADVANCE: f32 = 0.024;
+const MOVE_LIST_ROW_HEIGHT: f32 = 0.05;
+const MOVE_LIST_ROWS: usize = 20;
 
 pub struct State {
     surface: wgpu::Surface<'static>,
@@ -33,15 +75,48 @@ pub struct State {
     pieces_render_pipeline: wgpu::RenderPipeline,
     board_render_pipeline: wgpu::RenderPipeline,
     text_render_pipeline: wgpu::RenderPipeline,
+    arrows_render_pipeline: wgpu::RenderPipeline,
+    arrow_vb: wgpu::Buffer,
+    arrow_instance_buffer: wgpu::Buffer,
+    arrows: arrows::Arrows,
+    arrow_count: u32,
     pieces_texture_bind_group: wgpu::BindGroup,
     text_texture_bind_group: wgpu::BindGroup,
     game_info_bind_group: wgpu::BindGroup,
     game_info_buffer: wgpu::Buffer,
     game_info: GameInfo,
+    movegen: movegen::MoveGen,
+    // where the piece on each square can go, recomputed whenever the position changes
+    legal_masks: movegen::Masks,
     last_time: std::time::Instant,
+    // the position on the board, `game`'s current node
     board_state: logic::BoardState,
+    game: game_tree::GameTree,
+    backend: engine::Backend,
+    engine: Option<engine::Engine>,
+    book: Option<Arc<book::Book>>,
+    tablebases: Option<Arc<syzygy::Tablebases>>,
+    endgames: Option<Arc<endgame::Endgames>>,
+    // text instances of the tablebase verdict, replaced whenever the position changes
+    tablebase_label: std::ops::Range<usize>,
+    // running while the analysis mode is on, restarted whenever the position changes
+    analysis: Option<engine::Analysis>,
+    analysis_lines: usize,
+    // text instances of the analysis' best line
+    analysis_label: std::ops::Range<usize>,
+    game_over_label: std::ops::Range<usize>,
+    move_list_label: std::ops::Range<usize>,
+    // the moves in the list with the clip space rectangles they cover, min x, min y, max x, max y
+    move_list: Vec<(usize, [f32; 4])>,
+    // what the opponent threatens is searched while this runs, restarted like `analysis`
+    threats: Option<engine::Analysis>,
     mouse_down: bool,
     holding_piece: bool,
+    modifiers: ModifiersState,
+    // in clip space
+    cursor: [f32; 2],
+    // square the right button went down on, a circle or an arrow is drawn from there
+    marking_from: Option<u32>,
     window: Arc<Window>,
 }
 
@@ -70,46 +145,47 @@ impl Vertex {
 struct GameInfo {
     time: f32,
     state: u32,
-    legal_moves_low: u32,
-    legal_moves_high: u32,
+    board_size: [u32; 2],
+    legal_moves: [u32; 4], // one bit per square, up to logic::MAX_SQUARES
+    visible: [u32; 4],     // same layout, cleared bits are hidden by fog of war
+    eval_bar: f32,         // white's share of the evaluation bar, negative hides it
+    _padding: [u32; 3],
 }
 
 impl GameInfo {
-    fn set_legal_moves(&mut self, moves: &[u32]) {
-        self.clear_legal_moves();
-        for &mv in moves.iter() {
-            if mv < 32 {
-                self.legal_moves_low |= 1 << mv;
-            } else {
-                self.legal_moves_high |= 1 << (mv - 32);
-            }
-        }
+    fn set_legal_mask(&mut self, mask: [u32; 4]) {
+        self.legal_moves = mask;
     }
     fn clear_legal_moves(&mut self) {
-        self.legal_moves_low = 0;
-        self.legal_moves_high = 0;
+        self.legal_moves = [0; 4];
+    }
+    fn set_visible(&mut self, squares: &[u32]) {
+        self.visible = [0; 4];
+        for &square in squares.iter() {
+            self.visible[square as usize / 32] |= 1 << (square % 32);
+        }
     }
     fn set_white_to_play(&mut self, white_to_play: bool) {
         self.state = (self.state & !0x1) | ((white_to_play as u32) & 0x1); // bit 0
     }
     fn set_hovered(&mut self, hovered: u32) {
-        self.state = (self.state & !(0x7F << 1)) | ((hovered & 0x7F) << 1); // bits 1-7
+        self.state = (self.state & !(0xFF << 1)) | ((hovered & 0xFF) << 1); // bits 1-8
     }
     fn hovered(&self) -> u32 {
-        (self.state >> 1) & 0x7F
+        (self.state >> 1) & 0xFF
     }
     fn set_selected(&mut self, selected: u32) {
-        self.state = (self.state & !(0x7F << 8)) | ((selected & 0x7F) << 8); // bits 8-14
+        self.state = (self.state & !(0xFF << 9)) | ((selected & 0xFF) << 9); // bits 9-16
     }
     fn selected(&self) -> u32 {
-        (self.state >> 8) & 0x7F
+        (self.state >> 9) & 0xFF
     }
     fn set_game_over(&mut self, game_over: u32) {
-        // bits 15-16 (00 = ongoing, 01 = white wins, 10 = black wins, 11 = draw)
-        self.state = (self.state & !(0x3 << 15)) | ((game_over & 0x3) << 15);
+        // bits 17-18 (00 = ongoing, 01 = white wins, 10 = black wins, 11 = draw)
+        self.state = (self.state & !(0x3 << 17)) | ((game_over & 0x3) << 17);
     }
     fn game_over(&self) -> u32 {
-        (self.state >> 15) & 0x3
+        (self.state >> 17) & 0x3
     }
 }
 
@@ -117,24 +193,24 @@ impl GameInfo {
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Instance {
     position: [f32; 3],
-    data: u32, // bit 0 = white, bits 1-4 = piece, bits 5-10 = index
+    data: u32, // bit 0 = white, bits 1-7 = piece, bits 8-15 = index
 }
 
 impl Instance {
     fn new(position: [f32; 3], piece: u32, white: u32, index: u32) -> Self {
         Self {
             position,
-            data: (white & 0x1) | ((piece & 0xF) << 1) | ((index & 0x3F) << 5),
+            data: (white & 0x1) | ((piece & 0x7F) << 1) | ((index & 0xFF) << 8),
         }
     }
     fn _white(&self) -> u32 {
         self.data & 0x1
     }
     fn _piece(&self) -> u32 {
-        (self.data >> 1) & 0xF
+        (self.data >> 1) & 0x7F
     }
     fn index(&self) -> u32 {
-        (self.data >> 5) & 0x3F
+        (self.data >> 8) & 0xFF
     }
 
     fn desc() -> wgpu::VertexBufferLayout<'static> {
@@ -187,6 +263,8 @@ impl State {
             })
             .await?;
 
+        let movegen = movegen::MoveGen::new(&adapter, &device);
+
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
             .formats
@@ -215,6 +293,11 @@ impl State {
             source: wgpu::ShaderSource::Wgsl(std::fs::read_to_string("assets/text.wgsl")?.into()),
         });
 
+        let arrows_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::fs::read_to_string("assets/arrows.wgsl")?.into()),
+        });
+
         let board_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader"),
             source: wgpu::ShaderSource::Wgsl(std::fs::read_to_string("assets/board.wgsl")?.into()),
@@ -243,26 +326,27 @@ impl State {
                 label: Some("texture_bind_group_layout"),
             });
 
-        let pieces_texture = texture::Texture::atlas_from(
-            &device,
-            &queue,
-            vec![
-                "pieces/wr.png",
-                "pieces/wn.png",
-                "pieces/wb.png",
-                "pieces/wq.png",
-                "pieces/wk.png",
-                "pieces/wp.png",
-                "pieces/br.png",
-                "pieces/bn.png",
-                "pieces/bb.png",
-                "pieces/bq.png",
-                "pieces/bk.png",
-                "pieces/bp.png",
-            ],
-            6,
-            false,
-        )?;
+        let mut piece_sprites = vec![
+            "pieces/wr.png",
+            "pieces/wn.png",
+            "pieces/wb.png",
+            "pieces/wq.png",
+            "pieces/wk.png",
+            "pieces/wp.png",
+            "pieces/br.png",
+            "pieces/bn.png",
+            "pieces/bb.png",
+            "pieces/bq.png",
+            "pieces/bk.png",
+            "pieces/bp.png",
+        ];
+        for def in pieces::fairy_pieces() {
+            piece_sprites.extend(def.sprites);
+        }
+        let atlas_rows = piece_sprites.len().div_ceil(6);
+
+        let pieces_texture =
+            texture::Texture::atlas_from(&device, &queue, piece_sprites, 6, false)?;
 
         let text_texture =
             texture::Texture::from_assets(&device, &queue, "minogram_6x10.png".into(), false)?;
@@ -310,11 +394,119 @@ impl State {
             label: None,
         });
 
+        let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+        let fog_of_war = args.iter().any(|arg| arg == "--fog");
+        args.retain(|arg| arg != "--fog");
+        let gpu_eval = args.iter().any(|arg| arg == "--gpu-eval");
+        args.retain(|arg| arg != "--gpu-eval");
+        let human_side = take_option(&mut args, "--play");
+        let depth = take_option(&mut args, "--depth");
+        let movetime = take_option(&mut args, "--movetime");
+        let engine_path = take_option(&mut args, "--engine");
+        let xboard_engine_path = take_option(&mut args, "--xboard-engine");
+        let book = take_option(&mut args, "--book")
+            .map(|path| book::Book::open(&path).map(Arc::new))
+            .transpose()?;
+        let tablebases = take_option(&mut args, "--syzygy")
+            .map(|path| syzygy::Tablebases::open(&path).map(Arc::new))
+            .transpose()?;
+        let analysis_lines = take_option(&mut args, "--multipv")
+            .map(|lines| lines.parse())
+            .transpose()?
+            .unwrap_or(3);
+        let threads = take_option(&mut args, "--threads")
+            .map(|threads| threads.parse::<usize>())
+            .transpose()?;
+        let hash = take_option(&mut args, "--hash")
+            .map(|mb| mb.parse::<usize>())
+            .transpose()?;
+        let network = take_option(&mut args, "--nnue")
+            .map(|path| nnue::Network::open(&path).map(Arc::new))
+            .transpose()?;
+        let pgn_path = take_option(&mut args, "--pgn");
+        let endgames = take_option(&mut args, "--tablebase")
+            .map(|dir| endgame::Endgames::open(std::path::Path::new(&dir)).map(Arc::new))
+            .transpose()?;
+        let fen = if !args.is_empty() {
+            let arg = args.join(" ");
+            logic::variant_fen(&arg).map_or(arg, str::to_owned)
+        } else {
+            logic::STARTING_FEN.to_owned()
+        };
+        let game = match pgn_path {
+            Some(path) => {
+                let text =
+                    std::fs::read_to_string(&path).map_err(|e| anyhow::anyhow!("{path}: {e}"))?;
+                pgn::read_tree(&text).map_err(|e| anyhow::anyhow!("{path}: {e}"))?
+            }
+            None => {
+                let mut board_state = logic::BoardState::from_fen(&fen)?;
+                board_state.fog_of_war = fog_of_war;
+                game_tree::GameTree::new(board_state)
+            }
+        };
+        let board_state = game.board();
+
+        let backend = match (engine_path, xboard_engine_path) {
+            (Some(path), _) => {
+                let mut engine = uci_client::UciEngine::spawn(&path)?;
+                if let Some(mb) = hash {
+                    engine.set_option("Hash", &mb.to_string())?;
+                }
+                if let Some(threads) = threads {
+                    engine.set_option("Threads", &threads.to_string())?;
+                }
+                engine.new_game()?;
+                engine::Backend::Uci(Arc::new(std::sync::Mutex::new(engine)))
+            }
+            (None, Some(path)) => engine::Backend::Xboard(Arc::new(std::sync::Mutex::new(
+                xboard_client::XboardEngine::spawn(&path)?,
+            ))),
+            (None, None) => engine::Backend::Internal {
+                tt: Arc::new(tt::TranspositionTable::new(hash.unwrap_or(tt::DEFAULT_MB))),
+                tablebases: tablebases.clone(),
+                endgames: endgames.clone(),
+                network,
+                threads: threads.unwrap_or(1),
+                evaluator: match (gpu_eval, &movegen) {
+                    (false, _) => None,
+                    (true, movegen::MoveGen::Gpu(_)) => Some(Arc::new(
+                        batch_eval::GpuEvaluator::new(device.clone(), queue.clone())?,
+                    )),
+                    (true, movegen::MoveGen::Cpu) => {
+                        return Err(anyhow::anyhow!("--gpu-eval needs compute shaders"));
+                    }
+                },
+            },
+        };
+        let engine = match human_side.as_deref() {
+            None => None,
+            Some(side @ ("white" | "black")) => {
+                let limits = search::Limits {
+                    depth: depth.as_deref().map(str::parse).transpose()?,
+                    nodes: None,
+                    // thinking for a second is the default unless only a depth is given
+                    time: match (&movetime, &depth) {
+                        (Some(ms), _) => Some(std::time::Duration::from_millis(ms.parse()?)),
+                        (None, Some(_)) => None,
+                        (None, None) => Some(std::time::Duration::from_secs(1)),
+                    },
+                };
+                let mut engine = engine::Engine::new(side == "black", limits, backend.clone());
+                engine.book = book.clone();
+                Some(engine)
+            }
+            Some(side) => return Err(anyhow::anyhow!("--play takes white or black, not {side}")),
+        };
+
         let game_info = GameInfo {
             time: 0.0,
             state: 1,
-            legal_moves_low: 0,
-            legal_moves_high: 0,
+            board_size: [board_state.width, board_state.height],
+            legal_moves: [0; 4],
+            visible: [u32::MAX; 4],
+            eval_bar: -1.0,
+            _padding: [0; 3],
         };
 
         let game_info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -327,7 +519,7 @@ impl State {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -362,7 +554,10 @@ impl State {
                     module: &pieces_shader,
                     entry_point: Some("vs_main"),
                     buffers: &[Vertex::desc(), Instance::desc()],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    compilation_options: wgpu::PipelineCompilationOptions {
+                        constants: &[("atlas_rows", atlas_rows as f64)],
+                        ..Default::default()
+                    },
                 },
                 fragment: Some(wgpu::FragmentState {
                     module: &pieces_shader,
@@ -490,43 +685,60 @@ impl State {
                 cache: None,
             });
 
-        let args = std::env::args().collect::<Vec<_>>();
-        let fen = if args.len() > 1 {
-            &args[1..].join(" ")
-        } else {
-            logic::STARTING_FEN
-        };
-        let board_state = logic::BoardState::from_fen(fen)?;
+        let arrows_render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[],
+                push_constant_ranges: &[],
+            });
 
-        let piece_instances = board_state
-            .pieces
-            .iter()
-            .enumerate()
-            .filter_map(|(index, piece)| {
-                let piece = match piece {
-                    Some(p) => p,
-                    None => return None,
-                };
+        let arrows_render_pipeline =
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Render Pipeline"),
+                layout: Some(&arrows_render_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &arrows_shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[Vertex::desc(), arrows::Arrow::desc()],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &arrows_shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: config.format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
 
-                let instance_position = [
-                    ((index as i32 % 8 - 4) as f32) * 0.125,
-                    ((index as i32 / 8 - 4) as f32) * 0.125,
-                    0.0,
-                ];
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    // arrows pointing left flip the quad over
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
 
-                Some(Instance::new(
-                    instance_position,
-                    piece.to_idx(),
-                    piece.white as u32,
-                    index as u32,
-                ))
-            })
-            .collect::<Vec<_>>();
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+                cache: None,
+            });
 
-        let piece_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        let piece_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&piece_instances),
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            size: (size_of::<Instance>() * logic::MAX_SQUARES as usize) as wgpu::BufferAddress,
+            mapped_at_creation: false,
         });
 
         let text_instances = Vec::new();
@@ -547,8 +759,15 @@ impl State {
             usage: wgpu::BufferUsages::VERTEX,
         });
 
-        let board_vertices = utils::Quad::from(Vec3::new(-0.5, -0.5, 0.0), Vec3::ONE)
-            .map(|pos| Vertex { position: pos });
+        // the board, then the evaluation bar on its left
+        let board_vertices = [
+            utils::Quad::from(Vec3::new(-0.5, -0.5, 0.0), Vec3::ONE),
+            utils::Quad::from(Vec3::new(-0.56, -0.5, 0.0), Vec3::new(0.04, 1.0, 0.0)),
+        ]
+        .concat()
+        .into_iter()
+        .map(|pos| Vertex { position: pos })
+        .collect::<Vec<_>>();
 
         let board_vb = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
@@ -556,6 +775,22 @@ impl State {
             usage: wgpu::BufferUsages::VERTEX,
         });
 
+        let arrow_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            size: (size_of::<arrows::Arrow>() * MAX_ARROWS) as wgpu::BufferAddress,
+            mapped_at_creation: false,
+        });
+
+        let arrow_vertices =
+            utils::Quad::from(Vec3::ZERO, Vec3::ONE).map(|pos| Vertex { position: pos });
+
+        let arrow_vb = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Arrow Vertex Buffer"),
+            contents: bytemuck::cast_slice(&arrow_vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
         let text_vertices =
             utils::Quad::from(Vec3::ZERO, Vec3::ONE * 0.1).map(|pos| Vertex { position: pos });
 
@@ -567,7 +802,7 @@ impl State {
 
         let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(&utils::Quad::generate_indices(4)),
+            contents: bytemuck::cast_slice(&utils::Quad::generate_indices(8)),
             usage: wgpu::BufferUsages::INDEX,
         });
 
@@ -580,7 +815,7 @@ impl State {
             board_vb,
             text_vb,
             index_buffer,
-            piece_instances,
+            piece_instances: Vec::new(),
             piece_instance_buffer,
             text_instances,
             text_instance_buffer,
@@ -588,24 +823,154 @@ impl State {
             pieces_render_pipeline,
             board_render_pipeline,
             text_render_pipeline,
+            arrows_render_pipeline,
+            arrow_vb,
+            arrow_instance_buffer,
+            arrows: arrows::Arrows::default(),
+            arrow_count: 0,
             pieces_texture_bind_group,
             text_texture_bind_group,
             game_info_bind_group,
             game_info_buffer,
             game_info,
+            movegen,
+            legal_masks: Vec::new(),
             last_time: std::time::Instant::now(),
-            board_state,
+            board_state: game.board().clone(),
+            game,
+            backend,
+            engine,
+            book,
+            tablebases,
+            endgames,
+            tablebase_label: 0..0,
+            analysis: None,
+            analysis_lines,
+            analysis_label: 0..0,
+            game_over_label: 0..0,
+            move_list_label: 0..0,
+            move_list: Vec::new(),
+            threats: None,
             mouse_down: false,
             holding_piece: false,
+            modifiers: ModifiersState::empty(),
+            cursor: [0.0; 2],
+            marking_from: None,
             window,
         };
 
+        // the labels come first, the text shader tells them apart by their instance index
         state.update_text_instances("WHITE", 0.0, -0.6);
         state.update_text_instances("BLACK", 0.0, 0.6);
+        state.update_instances();
 
         Ok(state)
     }
 
+    /// False while the computer opponent is on the move.
+    fn human_to_play(&self) -> bool {
+        self.engine
+            .as_ref()
+            .is_none_or(|engine| engine.white != self.board_state.white_to_play)
+    }
+
+    /// Prints the book moves for the current position and highlights their squares.
+    fn show_book_moves(&mut self) {
+        let Some(book) = &self.book else {
+            log::warn!("no book loaded, start with --book <path>");
+            return;
+        };
+        let moves = book.moves(&self.board_state);
+        if moves.is_empty() {
+            log::info!("out of book");
+        }
+        let most = moves
+            .iter()
+            .map(|&(_, weight)| weight)
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        let mut arrows = Vec::new();
+        for (mv, weight) in moves {
+            log::info!("book move {} weight {weight}", self.board_state.san(mv));
+            // popular moves stand out
+            let mut color = arrows::BOOK_COLOR;
+            color[3] *= 0.3 + 0.7 * weight as f32 / most as f32;
+            arrows.push((mv.from, mv.to, color));
+        }
+        self.arrows.set(arrows::Source::Book, arrows);
+        self.update_arrows();
+    }
+
+    /// Plays `mv` on the board and records it in the game.
+    fn play(&mut self, mv: logic::Move) {
+        // moves made with the mouse leave the promotion to `make`, which picks a queen
+        let Some(mv) = self.board_state.moves().into_iter().find(|legal| {
+            (legal.from, legal.to) == (mv.from, mv.to)
+                && (legal.promotion == mv.promotion
+                    || mv.promotion.is_none() && legal.promotion == Some(logic::PieceKind::Queen))
+        }) else {
+            log::warn!("illegal move {}", self.board_state.move_name(mv));
+            self.update_instances();
+            return;
+        };
+        self.game.play(mv);
+        self.board_state = self.game.board().clone();
+        self.update_instances();
+    }
+
+    /// Moves through the game with `step`, e.g. `GameTree::back`, and shows where it went.
+    fn navigate(&mut self, step: impl FnOnce(&mut game_tree::GameTree)) {
+        let current = self.game.current();
+        step(&mut self.game);
+        if self.game.current() == current {
+            return;
+        }
+        // a move the computer is still thinking about was meant for another position
+        if let Some(engine) = &mut self.engine {
+            engine.cancel();
+        }
+        self.board_state = self.game.board().clone();
+        self.game_info.set_selected(0);
+        self.game_info.clear_legal_moves();
+        self.update_instances();
+
+        let node = self.game.node(self.game.current());
+        if !node.comment.is_empty() {
+            log::info!("{}", node.comment);
+        }
+    }
+
+    /// Draws a circle on `from` when it is `to`, or an arrow between them, in the colour the
+    /// held modifiers pick. Drawing the same mark again removes it.
+    fn mark(&mut self, from: u32, to: u32) {
+        let color = annotations::MarkColor::from_modifiers(self.modifiers);
+        let current = self.game.current();
+        self.game
+            .node_mut(current)
+            .annotations
+            .toggle(from, to, color);
+        self.update_marks();
+    }
+
+    fn clear_marks(&mut self) {
+        let current = self.game.current();
+        self.game.node_mut(current).annotations = Default::default();
+        self.update_marks();
+    }
+
+    // shows the marks drawn on the current position
+    fn update_marks(&mut self) {
+        let marks = self
+            .game
+            .node(self.game.current())
+            .annotations
+            .shapes()
+            .collect::<Vec<_>>();
+        self.arrows.set(arrows::Source::User, marks);
+        self.update_arrows();
+    }
+
     pub fn update_instances(&mut self) {
         self.piece_instances = self
             .board_state
@@ -618,14 +983,8 @@ impl State {
                     None => return None,
                 };
 
-                let instance_position = [
-                    ((index as i32 % 8 - 4) as f32) * 0.125,
-                    ((index as i32 / 8 - 4) as f32) * 0.125,
-                    0.0,
-                ];
-
                 Some(Instance::new(
-                    instance_position,
+                    self.square_position(index as u32),
                     piece.to_idx(),
                     piece.white as u32,
                     index as u32,
@@ -638,6 +997,342 @@ impl State {
             0,
             bytemuck::cast_slice(&self.piece_instances),
         );
+        self.legal_masks = self
+            .movegen
+            .masks(&self.device, &self.queue, &self.board_state);
+
+        if self.board_state.fog_of_war && self.board_state.game_over == 0 {
+            // both players share the screen, so show what the side to move can see, unless
+            // the other side is the computer
+            let white = match &self.engine {
+                Some(engine) => !engine.white,
+                None => self.board_state.white_to_play,
+            };
+            let visible = self.board_state.visible_squares(white);
+            self.game_info.set_visible(&visible);
+        } else {
+            self.game_info.visible = [u32::MAX; 4];
+        }
+        self.update_tablebase_label();
+        if self.analysis.is_some() {
+            self.start_analysis();
+        }
+        if self.threats.is_some() {
+            self.start_threats();
+        }
+        self.arrows.clear(arrows::Source::Book);
+        self.update_marks();
+        self.update_move_list();
+    }
+
+    /// Writes the move list beside the board, scrolled to keep the current move in view.
+    fn update_move_list(&mut self) {
+        let entries = move_list::layout(&self.game);
+        let rows = entries.last().map_or(0, |entry| entry.row + 1);
+        let current = self.game.current();
+        let current_row = entries
+            .iter()
+            .find(|entry| entry.node == Some(current))
+            .map_or(0, |entry| entry.row);
+        let first_row = current_row
+            .saturating_sub(MOVE_LIST_ROWS / 2)
+            .min(rows.saturating_sub(MOVE_LIST_ROWS));
+
+        let [left, top] = MOVE_LIST_POSITION;
+        let mut instances = Vec::new();
+        self.move_list.clear();
+        for entry in entries {
+            if !(first_row..first_row + MOVE_LIST_ROWS).contains(&entry.row) {
+                continue;
+            }
+            let x = left + entry.column as f32 * MOVE_LIST_ADVANCE;
+            let y = top - (entry.row - first_row + 1) as f32 * MOVE_LIST_ROW_HEIGHT;
+            let style = if entry.node == Some(current) {
+                CURRENT_MOVE_STYLE
+            } else {
+                MOVE_LIST_STYLE
+            };
+            for (i, character) in entry.text.chars().enumerate() {
+                if let Some(char_index) = CHARACTERS.find(character) {
+                    instances.push(Instance {
+                        position: [x + i as f32 * MOVE_LIST_ADVANCE, y, 0.0],
+                        data: char_index as u32 | style,
+                    });
+                }
+            }
+            if let Some(node) = entry.node {
+                let width = entry.text.len() as f32 * MOVE_LIST_ADVANCE;
+                self.move_list
+                    .push((node, [x, y, x + width, y + MOVE_LIST_ROW_HEIGHT]));
+            }
+        }
+        self.move_list_label = self.replace_instances(self.move_list_label.clone(), instances);
+    }
+
+    /// The move of the list under the cursor.
+    fn move_under_cursor(&self) -> Option<usize> {
+        let [x, y] = self.cursor;
+        self.move_list
+            .iter()
+            .find(|(_, [min_x, min_y, max_x, max_y])| {
+                (*min_x..*max_x).contains(&x) && (*min_y..*max_y).contains(&y)
+            })
+            .map(|&(node, _)| node)
+    }
+
+    /// Shows or hides what the opponent threatens.
+    fn toggle_threats(&mut self) {
+        if self.threats.take().is_some() {
+            self.arrows.clear(arrows::Source::Threat);
+            self.update_arrows();
+        } else {
+            self.start_threats();
+        }
+    }
+
+    // searches the position as if the side to play passed
+    fn start_threats(&mut self) {
+        self.arrows.clear(arrows::Source::Threat);
+        // passing is no way out of check
+        if self.board_state.in_check() || self.board_state.game_over != 0 {
+            self.threats = Some(engine::Analysis::idle());
+            return;
+        }
+        let mut board = self.board_state.clone();
+        board.white_to_play = !board.white_to_play;
+        board.en_passant_square = None;
+        board.rehash();
+        let limits = search::Limits::time(std::time::Duration::from_millis(500));
+        self.threats = Some(engine::Analysis::start(&self.backend, &board, 1, limits));
+    }
+
+    /// Writes the arrows of every source to the instance buffer.
+    fn update_arrows(&mut self) {
+        let size = self.square_size();
+        let mut instances = self.arrows.instances(
+            |square| {
+                let [x, y, _] = self.square_position(square);
+                [x + size * 0.5, y + size * 0.5]
+            },
+            size,
+        );
+        instances.truncate(MAX_ARROWS);
+        self.queue.write_buffer(
+            &self.arrow_instance_buffer,
+            0,
+            bytemuck::cast_slice(&instances),
+        );
+        self.arrow_count = instances.len() as u32;
+    }
+
+    /// Turns the analysis mode on or off.
+    fn toggle_analysis(&mut self) {
+        if self.analysis.take().is_some() {
+            self.game_info.eval_bar = -1.0;
+            self.analysis_label = self.replace_text(self.analysis_label.clone(), "", 0.0, 0.0);
+            self.arrows.clear(arrows::Source::Analysis);
+            self.update_arrows();
+        } else {
+            self.start_analysis();
+        }
+    }
+
+    fn start_analysis(&mut self) {
+        // dropping the old analysis stops it, its line no longer fits the board
+        self.analysis = Some(engine::Analysis::start(
+            &self.backend,
+            &self.board_state,
+            self.analysis_lines,
+            search::Limits::default(),
+        ));
+        self.arrows.clear(arrows::Source::Analysis);
+        self.analysis_label = self.replace_text(self.analysis_label.clone(), "", 0.0, 0.0);
+    }
+
+    /// Shows the analysis' newest iteration: the evaluation bar and the best line above the
+    /// board, every line on stdout.
+    fn update_analysis(&mut self) {
+        let Some(result) = self.analysis.as_ref().and_then(|analysis| analysis.poll()) else {
+            return;
+        };
+        let white_to_play = self.board_state.white_to_play;
+        let score = if white_to_play {
+            result.score
+        } else {
+            -result.score
+        };
+        self.game_info.eval_bar = 1.0 / (1.0 + (-score as f32 / 400.0).exp());
+
+        println!("depth {}", result.depth);
+        let mut best = String::new();
+        for (index, line) in result.lines.iter().enumerate() {
+            let score = match line.mate_in() {
+                Some(moves) => format!("#{}", if white_to_play { moves } else { -moves }),
+                None if white_to_play => format!("{:+.2}", line.score as f32 / 100.0),
+                None => format!("{:+.2}", -line.score as f32 / 100.0),
+            };
+            let mut board = self.board_state.clone();
+            let mut text = score;
+            for &mv in &line.pv {
+                text += &format!(" {}", board.san(mv));
+                board.make(mv);
+            }
+            println!("{}  {text}", index + 1);
+            if index == 0 {
+                best = text;
+            }
+        }
+        // as many whole moves as fit across the window
+        let mut label = String::new();
+        for word in best.split(' ') {
+            if label.len() + word.len() + 1 > 20 {
+                break;
+            }
+            if !label.is_empty() {
+                label.push(' ');
+            }
+            label += word;
+        }
+        self.analysis_label = self.replace_text(self.analysis_label.clone(), &label, 0.0, 0.8);
+
+        // the best move stands out from the other lines'
+        let arrows = result.lines.iter().enumerate().filter_map(|(index, line)| {
+            let mv = line.pv.first()?;
+            let mut color = arrows::ANALYSIS_COLOR;
+            if index > 0 {
+                color[3] *= 0.5;
+            }
+            Some((mv.from, mv.to, color))
+        });
+        self.arrows.set(arrows::Source::Analysis, arrows);
+        self.update_arrows();
+    }
+
+    fn update_threats(&mut self) {
+        let Some(result) = self.threats.as_ref().and_then(|threats| threats.poll()) else {
+            return;
+        };
+        let arrows = result
+            .best_move
+            .map(|mv| (mv.from, mv.to, arrows::THREAT_COLOR));
+        self.arrows.set(arrows::Source::Threat, arrows);
+        self.update_arrows();
+    }
+
+    /// Swaps the text instances in `label` for `text`, returning where they went. Later labels
+    /// move down to fill the gap.
+    fn replace_text(
+        &mut self,
+        label: std::ops::Range<usize>,
+        text: &str,
+        base_x: f32,
+        base_y: f32,
+    ) -> std::ops::Range<usize> {
+        let start = self.text_instances.len();
+        self.update_text_instances(text, base_x, base_y);
+        let instances = self.text_instances.split_off(start);
+        self.replace_instances(label, instances)
+    }
+
+    /// Swaps the text instances in `label` for `instances`, as `replace_text` does.
+    fn replace_instances(
+        &mut self,
+        label: std::ops::Range<usize>,
+        instances: Vec<Instance>,
+    ) -> std::ops::Range<usize> {
+        self.text_instances.drain(label.clone());
+        for other in [
+            &mut self.tablebase_label,
+            &mut self.analysis_label,
+            &mut self.game_over_label,
+            &mut self.move_list_label,
+        ] {
+            if other.start >= label.end {
+                *other = other.start - label.len()..other.end - label.len();
+            }
+        }
+        let start = self.text_instances.len();
+        self.text_instances.extend(instances);
+        // an empty text writes nothing, so the buffer still needs the remaining instances
+        self.queue.write_buffer(
+            &self.text_instance_buffer,
+            0,
+            bytemuck::cast_slice(&self.text_instances),
+        );
+        start..self.text_instances.len()
+    }
+
+    /// Shows what the tablebases say about the position below the board.
+    fn update_tablebase_label(&mut self) {
+        // the distance to mate of our own tables says more than Syzygy's DTZ
+        let dtm = self
+            .endgames
+            .as_ref()
+            .filter(|_| self.board_state.game_over == 0)
+            .and_then(|endgames| endgames.probe(&self.board_state))
+            .map(|dtm| match dtm {
+                endgame::Dtm::Win(moves) => format!("TABLEBASE WIN\nMATE IN {moves}"),
+                endgame::Dtm::Draw => "TABLEBASE DRAW".to_owned(),
+                endgame::Dtm::Loss(moves) => format!("TABLEBASE LOSS\nMATED IN {moves}"),
+            });
+        let label = dtm.or_else(|| {
+            self.tablebases
+                .as_ref()
+                .filter(|_| self.board_state.game_over == 0)
+                .and_then(|tablebases| {
+                    let wdl = tablebases.probe_wdl(&self.board_state)?;
+                    let dtz = tablebases.probe_dtz(&self.board_state)?;
+                    Some(match wdl {
+                        syzygy::Wdl::Win => format!("TABLEBASE WIN\nDTZ {dtz}"),
+                        syzygy::Wdl::Loss => format!("TABLEBASE LOSS\nDTZ {}", -dtz),
+                        // won or lost, but not before the fifty move rule
+                        syzygy::Wdl::CursedWin | syzygy::Wdl::BlessedLoss => {
+                            format!("TABLEBASE DRAW\nDTZ {}", dtz.abs())
+                        }
+                        syzygy::Wdl::Draw => "TABLEBASE DRAW".to_owned(),
+                    })
+                })
+        });
+        self.tablebase_label = self.replace_text(
+            self.tablebase_label.clone(),
+            label.as_deref().unwrap_or_default(),
+            0.0,
+            -0.8,
+        );
+    }
+
+    /// Side of one square in clip space, the whole board spans at most one unit.
+    pub fn square_size(&self) -> f32 {
+        1.0 / self.board_state.width.max(self.board_state.height) as f32
+    }
+
+    /// Clip space position of the bottom left corner of `square`.
+    pub fn square_position(&self, square: u32) -> [f32; 3] {
+        let size = self.square_size();
+        [
+            (self.board_state.file_of(square) as f32 - self.board_state.width as f32 * 0.5) * size,
+            (self.board_state.rank_of(square) as f32 - self.board_state.height as f32 * 0.5) * size,
+            0.0,
+        ]
+    }
+
+    /// `position` in the window in clip space.
+    pub fn clip_position(&self, position: PhysicalPosition<f64>) -> [f32; 2] {
+        let size = self.window.inner_size();
+        [
+            position.x as f32 / size.width as f32 * 2.0 - 1.0,
+            1.0 - position.y as f32 / size.height as f32 * 2.0,
+        ]
+    }
+
+    /// Square under the cursor plus one, or 0 when the cursor is off the board.
+    pub fn square_under(&self, position: PhysicalPosition<f64>) -> u32 {
+        let [clip_x, clip_y] = self.clip_position(position);
+        let file = (clip_x / self.square_size() + self.board_state.width as f32 * 0.5).floor();
+        let rank = (clip_y / self.square_size() + self.board_state.height as f32 * 0.5).floor();
+        self.board_state
+            .square_at(rank as i32, file as i32)
+            .map_or(0, |square| square + 1)
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
@@ -650,8 +1345,6 @@ impl State {
     }
 
     pub fn update_text_instances(&mut self, text: &str, base_x: f32, base_y: f32) {
-        const CHARACTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+-=()[]{}<>/*:#%!?.,'\"@&$";
-
         let lines = text.lines().collect::<Vec<_>>();
         for (j, line) in lines.iter().enumerate() {
             for (i, character) in line.chars().enumerate() {
@@ -680,25 +1373,38 @@ impl State {
         self.game_info
             .set_white_to_play(self.board_state.white_to_play);
         if self.game_info.game_over() != self.board_state.game_over {
-            self.update_text_instances(
-                &format!(
-                    "GAME OVER\n{} WINS",
-                    if self.board_state.game_over == 1 {
-                        if !self.board_state.white_to_play {
-                            "WHITE"
-                        } else {
-                            "BLACK"
-                        }
-                    } else {
-                        "DRAW"
-                    }
-                ),
-                0.0,
-                0.0,
-            );
+            let text = match self.board_state.game_over {
+                0 => String::new(),
+                1 if self.board_state.white_to_play => "GAME OVER\nBLACK WINS".to_owned(),
+                1 => "GAME OVER\nWHITE WINS".to_owned(),
+                _ => "GAME OVER\nDRAW WINS".to_owned(),
+            };
+            self.game_over_label = self.replace_text(self.game_over_label.clone(), &text, 0.0, 0.0);
+            if let Some(engine) = &mut self.engine {
+                engine.game_over(&self.board_state);
+            }
         }
         self.game_info.set_game_over(self.board_state.game_over);
 
+        // stepping back through the game pauses the computer until a new move is made
+        if let Some(engine) = &mut self.engine
+            && engine.white == self.board_state.white_to_play
+            && self.board_state.game_over == 0
+            && self.game.at_end()
+        {
+            match engine.poll() {
+                Some(mv) => {
+                    self.play(mv);
+                    self.game_info.set_selected(0);
+                    self.game_info.clear_legal_moves();
+                }
+                None => engine.start(&self.board_state),
+            }
+        }
+
+        self.update_analysis();
+        self.update_threats();
+
         self.last_time = now;
         self.queue.write_buffer(
             &self.game_info_buffer,
@@ -752,7 +1458,7 @@ impl State {
             render_pass.set_bind_group(0, &self.game_info_bind_group, &[]);
             render_pass.set_vertex_buffer(0, self.board_vb.slice(..));
             render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            render_pass.draw_indexed(0..6, 0, 0..1);
+            render_pass.draw_indexed(0..12, 0, 0..1);
 
             // pieces
             render_pass.set_pipeline(&self.pieces_render_pipeline);
@@ -763,6 +1469,13 @@ impl State {
             render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
             render_pass.draw_indexed(0..6, 0, 0..self.piece_instances.len() as u32);
 
+            // arrows
+            render_pass.set_pipeline(&self.arrows_render_pipeline);
+            render_pass.set_vertex_buffer(0, self.arrow_vb.slice(..));
+            render_pass.set_vertex_buffer(1, self.arrow_instance_buffer.slice(..));
+            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..6, 0, 0..self.arrow_count);
+
             // text
             render_pass.set_pipeline(&self.text_render_pipeline);
             render_pass.set_bind_group(0, &self.text_texture_bind_group, &[]);
@@ -835,30 +1548,55 @@ impl ApplicationHandler<State> for App {
                         ..
                     },
                 ..
-            } => {
-                if key_state.is_pressed() {
-                    match code {
-                        KeyCode::Escape => event_loop.exit(),
-                        KeyCode::Space => {
-                            println!("{}", state.board_state.to_fen());
-                        }
-                        _ => {}
-                    }
+            } if key_state.is_pressed() => match code {
+                KeyCode::Escape => event_loop.exit(),
+                KeyCode::Space => {
+                    println!("{}", state.board_state.to_fen());
                 }
-            }
+                KeyCode::KeyE => {
+                    println!("{}", eval::Evaluation::new(&state.board_state));
+                }
+                KeyCode::KeyS => {
+                    // searched on a thread of its own so the window keeps drawing
+                    let limits = search::Limits::time(std::time::Duration::from_secs(2));
+                    let backend = state.backend.clone();
+                    let board = state.board_state.clone();
+                    std::thread::spawn(move || match backend.search(&board, limits) {
+                        Ok(result) => println!("{}", result.describe(&board)),
+                        Err(e) => log::error!("{e}"),
+                    });
+                }
+                KeyCode::KeyB => state.show_book_moves(),
+                KeyCode::KeyA => state.toggle_analysis(),
+                KeyCode::KeyT => state.toggle_threats(),
+                KeyCode::ArrowLeft => state.navigate(|game| {
+                    game.back();
+                }),
+                KeyCode::ArrowRight => state.navigate(|game| {
+                    game.forward();
+                }),
+                KeyCode::ArrowUp => state.navigate(|game| {
+                    game.switch_variation(-1);
+                }),
+                KeyCode::ArrowDown => state.navigate(|game| {
+                    game.switch_variation(1);
+                }),
+                KeyCode::Home => state.navigate(|game| game.go_to(game_tree::ROOT)),
+                KeyCode::End => state.navigate(game_tree::GameTree::end),
+                KeyCode::KeyP => {
+                    print!("{}", pgn::write(&state.game));
+                }
+                _ => {}
+            },
             WindowEvent::CursorMoved {
                 device_id: _,
                 position,
             } => {
                 state.holding_piece = state.mouse_down && state.game_info.selected() != 0;
                 let size = state.window.inner_size();
-                let x = ((position.x / size.width as f64 - 0.25) * 16.0).floor() as i32;
-                let y = ((1.0 - position.y / size.height as f64 - 0.25) * 16.0).floor() as i32;
-                let hovered = if !(0..8).contains(&x) || !(0..8).contains(&y) {
-                    0
-                } else {
-                    (y * 8 + x + 1) as u32
-                };
+                let hovered = state.square_under(position);
+                state.cursor = state.clip_position(position);
+                let grab_offset = state.square_size() * 0.4;
                 if hovered != state.game_info.hovered() {
                     state.game_info.set_hovered(hovered);
                 }
@@ -869,8 +1607,10 @@ impl ApplicationHandler<State> for App {
                         .find(|x| x.index() == state.game_info.selected() - 1);
                     if let Some(holding_piece) = holding_piece {
                         holding_piece.position = [
-                            (position.x as f32 / size.width as f32) * 2.0 - 1.05,
-                            (1.0 - position.y as f32 / size.height as f32) * 2.0 - 1.05,
+                            (position.x as f32 / size.width as f32) * 2.0 - 1.0 - grab_offset,
+                            (1.0 - position.y as f32 / size.height as f32) * 2.0
+                                - 1.0
+                                - grab_offset,
                             0.0,
                         ];
                     }
@@ -881,70 +1621,113 @@ impl ApplicationHandler<State> for App {
                     );
                 }
             }
+            WindowEvent::ModifiersChanged(modifiers) => state.modifiers = modifiers.state(),
             WindowEvent::MouseInput {
                 device_id: _,
                 state: button_state,
-                button,
+                button: MouseButton::Right,
             } => {
-                if button == MouseButton::Left {
-                    if button_state == ElementState::Pressed {
-                        state.mouse_down = true;
-                        if state.game_info.selected() == state.game_info.hovered() {
+                let hovered = state.game_info.hovered();
+                if button_state == ElementState::Pressed {
+                    state.marking_from = hovered.checked_sub(1);
+                } else if let Some(from) = state.marking_from.take()
+                    && hovered != 0
+                {
+                    state.mark(from, hovered - 1);
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if state.move_under_cursor().is_some() => {
+                if let Some(node) = state.move_under_cursor() {
+                    state.navigate(|game| game.go_to(node));
+                }
+            }
+            // marks go away on a left click even while the computer thinks
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !state.human_to_play() => state.clear_marks(),
+            // a piece picked up before the computer's turn began goes back where it was
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } if !state.human_to_play() => {
+                if state.holding_piece {
+                    state.update_instances();
+                    state.game_info.set_selected(0);
+                    state.game_info.clear_legal_moves();
+                }
+                state.mouse_down = false;
+                state.holding_piece = false;
+            }
+            WindowEvent::MouseInput {
+                device_id: _,
+                state: button_state,
+                button: MouseButton::Left,
+            } if state.human_to_play() => {
+                if button_state == ElementState::Pressed {
+                    state.clear_marks();
+                    state.mouse_down = true;
+                    if state.game_info.selected() == state.game_info.hovered() {
+                        state.game_info.set_selected(0);
+                        state.game_info.clear_legal_moves();
+                    } else if state.game_info.selected() != 0 && state.game_info.hovered() != 0 {
+                        let from = state.game_info.selected() - 1;
+                        let to = state.game_info.hovered() - 1;
+                        let mask = state.legal_masks[from as usize];
+                        state.game_info.set_legal_mask(mask);
+                        if movegen::contains(&mask, to) {
+                            // TODO  make promotion selectable
+                            state.play(logic::Move::new(from, to));
+
                             state.game_info.set_selected(0);
                             state.game_info.clear_legal_moves();
-                        } else if state.game_info.selected() != 0 && state.game_info.hovered() != 0
-                        {
-                            let from = state.game_info.selected() - 1;
-                            let to = state.game_info.hovered() - 1;
-                            let legal_moves = state.board_state.legal_moves(from);
-                            state.game_info.set_legal_moves(&legal_moves);
-                            if legal_moves.contains(&to) {
-                                state.board_state.make_move(from, to);
-                                state.update_instances();
-
-                                state.game_info.set_selected(0);
-                                state.game_info.clear_legal_moves();
-                            } else if let Some(piece) =
-                                &state.board_state.pieces[state.game_info.hovered() as usize - 1]
-                                && piece.white == state.board_state.white_to_play
-                            {
-                                state.game_info.set_selected(state.game_info.hovered());
-
-                                let legal_moves =
-                                    state.board_state.legal_moves(state.game_info.hovered() - 1);
-                                state.game_info.set_legal_moves(&legal_moves);
-                            } else {
-                                state.game_info.set_selected(0);
-                                state.game_info.clear_legal_moves();
-                            }
-                        } else if state.game_info.hovered() != 0
-                            && let Some(piece) =
-                                &state.board_state.pieces[state.game_info.hovered() as usize - 1]
+                        } else if let Some(piece) =
+                            &state.board_state.pieces[state.game_info.hovered() as usize - 1]
                             && piece.white == state.board_state.white_to_play
                         {
                             state.game_info.set_selected(state.game_info.hovered());
 
-                            let legal_moves =
-                                state.board_state.legal_moves(state.game_info.hovered() - 1);
-                            state.game_info.set_legal_moves(&legal_moves);
-                        }
-                    } else {
-                        if state.holding_piece && state.game_info.selected() != 0 {
-                            let from = state.game_info.selected() - 1;
-                            if state.game_info.hovered() != 0 {
-                                let to = state.game_info.hovered() - 1;
-                                if state.board_state.legal_moves(from).contains(&to) {
-                                    state.board_state.make_move(from, to);
-                                }
-                            }
-                            state.update_instances();
+                            let mask = state.legal_masks[state.game_info.hovered() as usize - 1];
+                            state.game_info.set_legal_mask(mask);
+                        } else {
                             state.game_info.set_selected(0);
                             state.game_info.clear_legal_moves();
                         }
-
-                        state.mouse_down = false;
-                        state.holding_piece = false;
+                    } else if state.game_info.hovered() != 0
+                        && let Some(piece) =
+                            &state.board_state.pieces[state.game_info.hovered() as usize - 1]
+                        && piece.white == state.board_state.white_to_play
+                    {
+                        state.game_info.set_selected(state.game_info.hovered());
+
+                        let mask = state.legal_masks[state.game_info.hovered() as usize - 1];
+                        state.game_info.set_legal_mask(mask);
+                    }
+                } else {
+                    if state.holding_piece && state.game_info.selected() != 0 {
+                        let from = state.game_info.selected() - 1;
+                        match state.game_info.hovered().checked_sub(1) {
+                            Some(to)
+                                if movegen::contains(&state.legal_masks[from as usize], to) =>
+                            {
+                                // TODO  make promotion selectable
+                                state.play(logic::Move::new(from, to));
+                            }
+                            // the piece goes back to its square
+                            _ => state.update_instances(),
+                        }
+                        state.game_info.set_selected(0);
+                        state.game_info.clear_legal_moves();
                     }
+
+                    state.mouse_down = false;
+                    state.holding_piece = false;
                 }
             }
             _ => {}
@@ -952,9 +1735,28 @@ impl ApplicationHandler<State> for App {
     }
 }
 
+/// Removes `name` and the value after it from `args`.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+    args.remove(index);
+    (index < args.len()).then(|| args.remove(index))
+}
+
 fn main() -> anyhow::Result<()> {
     env_logger::init();
 
+    match std::env::args().nth(1).as_deref() {
+        Some("uci") => return uci::run(),
+        Some("xboard") => return xboard::run(),
+        Some("make-book") => return book::make_book(std::env::args().skip(2).collect()),
+        Some("perft") => return perft::perft_command(std::env::args().skip(2).collect()),
+        Some("solve") => return solver::solve_command(std::env::args().skip(2).collect()),
+        Some("make-tablebase") => {
+            return endgame::make_tablebase(std::env::args().skip(2).collect());
+        }
+        _ => {}
+    }
+
     let event_loop = EventLoop::with_user_event().build()?;
     let mut app = App::default();
     event_loop.run_app(&mut app)?;
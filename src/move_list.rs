@@ -0,0 +1,150 @@
+use crate::game_tree::GameTree;
+
+// columns of the move number, white's move and black's move
+const NUMBER: usize = 0;
+const WHITE: usize = 4;
+const BLACK: usize = 12;
+
+/// A word of the move list on a grid of characters: a move number, or a move with its node.
+pub struct Entry {
+    pub node: Option<usize>,
+    pub row: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+/// The line the game is looked at in, from the start through the current move and on along
+/// the main line, a row per move number as in `12. Nf3 Nc6`.
+pub fn layout(game: &GameTree) -> Vec<Entry> {
+    let mut line = game.path(game.current());
+    let mut node = game.current();
+    while let Some(&child) = game.node(node).children.first() {
+        line.push(child);
+        node = child;
+    }
+
+    let mut entries = Vec::new();
+    let mut board = game.start().clone();
+    let mut row = 0;
+    for (index, node) in line.into_iter().enumerate() {
+        let mv = game.node(node).mv.unwrap();
+        if board.white_to_play || index == 0 {
+            if index > 0 {
+                row += 1;
+            }
+            let dots = if board.white_to_play { "." } else { "..." };
+            entries.push(Entry {
+                node: None,
+                row,
+                column: NUMBER,
+                text: format!("{}{dots}", board.fullmove_number),
+            });
+        }
+        entries.push(Entry {
+            node: Some(node),
+            row,
+            column: if board.white_to_play { WHITE } else { BLACK },
+            text: board.san(mv),
+        });
+        board.make(mv);
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_tree::ROOT;
+    use crate::logic::{BoardState, STARTING_FEN};
+
+    // plays `names` from the current position, returning their nodes
+    fn play(game: &mut GameTree, names: &[&str]) -> Vec<usize> {
+        names
+            .iter()
+            .map(|name| {
+                let mv = game.board().parse_san(name).unwrap();
+                game.play(mv);
+                game.current()
+            })
+            .collect()
+    }
+
+    fn cells(entries: &[Entry]) -> Vec<(usize, usize, &str)> {
+        entries
+            .iter()
+            .map(|entry| (entry.row, entry.column, entry.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn lays_out_a_row_per_move_number() {
+        let mut game = GameTree::new(BoardState::from_fen(STARTING_FEN).unwrap());
+        let nodes = play(&mut game, &["e4", "e5", "Nf3"]);
+        let entries = layout(&game);
+        assert_eq!(
+            cells(&entries),
+            [
+                (0, NUMBER, "1."),
+                (0, WHITE, "e4"),
+                (0, BLACK, "e5"),
+                (1, NUMBER, "2."),
+                (1, WHITE, "Nf3"),
+            ]
+        );
+        let moves = entries.iter().filter_map(|entry| entry.node);
+        assert_eq!(moves.collect::<Vec<_>>(), nodes);
+    }
+
+    #[test]
+    fn starts_with_black_to_move() {
+        let board =
+            BoardState::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 7")
+                .unwrap();
+        let mut game = GameTree::new(board);
+        play(&mut game, &["e5", "Nf3", "Nc6"]);
+        assert_eq!(
+            cells(&layout(&game)),
+            [
+                (0, NUMBER, "7..."),
+                (0, BLACK, "e5"),
+                (1, NUMBER, "8."),
+                (1, WHITE, "Nf3"),
+                (1, BLACK, "Nc6"),
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_the_line_past_the_current_move() {
+        let mut game = GameTree::new(BoardState::from_fen(STARTING_FEN).unwrap());
+        let nodes = play(&mut game, &["e4", "e5", "Nf3", "Nc6"]);
+        game.go_to(nodes[1]);
+        let moves = layout(&game).into_iter().filter_map(|entry| entry.node);
+        assert_eq!(moves.collect::<Vec<_>>(), nodes);
+        game.go_to(ROOT);
+        assert_eq!(layout(&game).len(), 6);
+    }
+
+    #[test]
+    fn lays_out_the_variation_it_is_in() {
+        let mut game = GameTree::new(BoardState::from_fen(STARTING_FEN).unwrap());
+        let main = play(&mut game, &["e4", "e5", "Nf3"]);
+        game.go_to(main[0]);
+        let variation = play(&mut game, &["c5", "Nf3", "d6"]);
+        game.go_to(variation[0]);
+        let entries = layout(&game);
+        assert_eq!(
+            cells(&entries),
+            [
+                (0, NUMBER, "1."),
+                (0, WHITE, "e4"),
+                (0, BLACK, "c5"),
+                (1, NUMBER, "2."),
+                (1, WHITE, "Nf3"),
+                (1, BLACK, "d6"),
+            ]
+        );
+        let moves = entries.iter().filter_map(|entry| entry.node).skip(1);
+        assert_eq!(moves.collect::<Vec<_>>(), variation);
+    }
+}
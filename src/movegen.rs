@@ -0,0 +1,336 @@
+use wgpu::util::DeviceExt;
+
+use crate::gpu;
+use crate::logic::{BoardState, MAX_SQUARES, PieceKind};
+
+/// Target squares of every square's piece, one bit per square like `GameInfo::legal_moves`.
+pub type Masks = Vec<[u32; 4]>;
+
+const MAX_KINDS: usize = 16;
+const MAX_DIRECTIONS: usize = 128;
+
+/// `BoardState` laid out for `assets/movegen.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PackedBoard {
+    width: u32,
+    height: u32,
+    pub flags: u32,
+    pub en_passant: u32,
+    promotions: [u32; 4],
+    pub squares: [u32; MAX_SQUARES as usize],
+    kinds: [[u32; 4]; MAX_KINDS],
+    directions: [[i32; 4]; MAX_DIRECTIONS],
+}
+
+impl PackedBoard {
+    /// `None` when the board holds more kinds of pieces or directions than the shader has
+    /// room for.
+    pub fn new(board: &BoardState) -> Option<Self> {
+        let mut packed = PackedBoard {
+            width: board.width,
+            height: board.height,
+            flags: board.white_to_play as u32
+                | (board.white_can_oo as u32) << 1
+                | (board.white_can_ooo as u32) << 2
+                | (board.black_can_oo as u32) << 3
+                | (board.black_can_ooo as u32) << 4
+                | (board.fog_of_war as u32) << 5,
+            en_passant: board.en_passant_square.unwrap_or(u32::MAX),
+            promotions: [0; 4],
+            squares: [0; MAX_SQUARES as usize],
+            kinds: [[0; 4]; MAX_KINDS],
+            directions: [[0; 4]; MAX_DIRECTIONS],
+        };
+        // kind 0 stays unused so that 0 can mean an empty square
+        let mut kinds = vec![None];
+        let mut directions = 0;
+        // pawns may promote to these without one being on the board
+        let promotions = [
+            PieceKind::Queen,
+            PieceKind::Rook,
+            PieceKind::Bishop,
+            PieceKind::Knight,
+        ];
+        for (i, kind) in promotions.into_iter().enumerate() {
+            packed.promotions[i] = packed.kind_index(&mut kinds, &mut directions, kind)?;
+        }
+        for (square, piece) in board.pieces.iter().enumerate() {
+            let Some(piece) = piece else { continue };
+            let index = packed.kind_index(&mut kinds, &mut directions, piece.kind)?;
+            packed.squares[square] = index | (piece.white as u32) << 4;
+        }
+        Some(packed)
+    }
+
+    // where `kind` is in the kind table, adding it if it is new
+    fn kind_index(
+        &mut self,
+        kinds: &mut Vec<Option<PieceKind>>,
+        directions: &mut usize,
+        kind: PieceKind,
+    ) -> Option<u32> {
+        if let Some(index) = kinds.iter().position(|&other| other == Some(kind)) {
+            return Some(index as u32);
+        }
+        if kinds.len() == MAX_KINDS {
+            return None;
+        }
+        let role = match kind {
+            PieceKind::Pawn => 1,
+            PieceKind::King => 2,
+            PieceKind::Rook => 3,
+            PieceKind::Fairy(_) => 4,
+            _ => 0,
+        };
+        let first = *directions;
+        for movement in kind.movement() {
+            for (dr, df) in movement.directions() {
+                if *directions == MAX_DIRECTIONS {
+                    return None;
+                }
+                self.directions[*directions] = [dr as i32, df as i32, movement.rides() as i32, 0];
+                *directions += 1;
+            }
+        }
+        self.kinds[kinds.len()] = [first as u32, (*directions - first) as u32, role, 0];
+        kinds.push(Some(kind));
+        Some(kinds.len() as u32 - 1)
+    }
+}
+
+pub fn contains(mask: &[u32; 4], square: u32) -> bool {
+    mask[square as usize / 32] & 1 << (square % 32) != 0
+}
+
+/// What `BoardState::legal_moves` says for every square.
+pub fn cpu_masks(board: &BoardState) -> Masks {
+    (0..board.pieces.len() as u32)
+        .map(|square| {
+            let mut mask = [0; 4];
+            for target in board.legal_moves(square) {
+                mask[target as usize / 32] |= 1 << (target % 32);
+            }
+            mask
+        })
+        .collect()
+}
+
+/// The compute pipeline of `assets/masks.wgsl`, with its buffers.
+pub struct GpuMoveGen {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    board_buffer: wgpu::Buffer,
+    mask_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+}
+
+impl GpuMoveGen {
+    pub fn new(device: &wgpu::Device) -> anyhow::Result<Self> {
+        let shader = gpu::shader(
+            device,
+            "Move Generation Shader",
+            &["movegen.wgsl", "masks.wgsl"],
+        )?;
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Move Generation Pipeline"),
+            layout: None,
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let board_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Packed Board Buffer"),
+            contents: bytemuck::bytes_of(&<PackedBoard as bytemuck::Zeroable>::zeroed()),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let mask_size = (MAX_SQUARES as usize * size_of::<[u32; 4]>()) as wgpu::BufferAddress;
+        let mask_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Legal Move Mask Buffer"),
+            size: mask_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Legal Move Readback Buffer"),
+            size: mask_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: board_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: mask_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("movegen_bind_group"),
+        });
+
+        Ok(Self {
+            pipeline,
+            bind_group,
+            board_buffer,
+            mask_buffer,
+            readback_buffer,
+        })
+    }
+
+    /// Runs the shader on `board` and waits for the result.
+    pub fn masks(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        board: &BoardState,
+    ) -> anyhow::Result<Masks> {
+        let packed = PackedBoard::new(board)
+            .ok_or_else(|| anyhow::anyhow!("too many kinds of pieces for the GPU"))?;
+        queue.write_buffer(&self.board_buffer, 0, bytemuck::cast_slice(&[packed]));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Move Generation Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Move Generation Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.dispatch_workgroups((board.pieces.len() as u32).div_ceil(64), 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.mask_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            self.mask_buffer.size(),
+        );
+        queue.submit([encoder.finish()]);
+
+        let slice = self.readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        receiver.recv()??;
+        let masks = bytemuck::cast_slice::<u8, [u32; 4]>(&slice.get_mapped_range())
+            [..board.pieces.len()]
+            .to_vec();
+        self.readback_buffer.unmap();
+        Ok(masks)
+    }
+}
+
+/// Where the window gets its legal moves from: the GPU if the adapter runs compute shaders,
+/// `BoardState::legal_moves` otherwise.
+pub enum MoveGen {
+    Gpu(GpuMoveGen),
+    Cpu,
+}
+
+impl MoveGen {
+    pub fn new(adapter: &wgpu::Adapter, device: &wgpu::Device) -> Self {
+        let compute = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        if !compute {
+            log::info!("adapter has no compute shaders, generating moves on the CPU");
+            return MoveGen::Cpu;
+        }
+        match GpuMoveGen::new(device) {
+            Ok(gpu) => MoveGen::Gpu(gpu),
+            Err(e) => {
+                log::warn!("{e}, generating moves on the CPU");
+                MoveGen::Cpu
+            }
+        }
+    }
+
+    pub fn masks(&self, device: &wgpu::Device, queue: &wgpu::Queue, board: &BoardState) -> Masks {
+        match self {
+            MoveGen::Gpu(gpu) => gpu.masks(device, queue, board).unwrap_or_else(|e| {
+                log::warn!("{e}, generating moves on the CPU");
+                cpu_masks(board)
+            }),
+            MoveGen::Cpu => cpu_masks(board),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::{CAPABLANCA_FEN, GOTHIC_FEN, STARTING_FEN};
+
+    const POSITIONS: &[&str] = &[
+        STARTING_FEN,
+        CAPABLANCA_FEN,
+        GOTHIC_FEN,
+        // kiwipete, castling both ways with pins and checks around
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        // en passant that would expose the king along the rank
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "8/8/8/KPp4r/8/8/8/7k w - c6 0 1",
+        // promotions, with captures
+        "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        // castling through and out of check
+        "r3k2r/8/8/8/8/8/8/R3K1rR w KQkq - 0 1",
+        "r3k2r/8/8/8/4r3/8/8/R3K2R w KQkq - 0 1",
+        // double check
+        "4k3/8/8/8/8/5n2/8/4K2r w - - 0 1",
+        // fairy pieces
+        "4k3/8/2z5/8/8/3A4/8/C3K3 w - - 0 1",
+        "z3k2a/8/8/8/8/8/8/R3K2R b KQ - 0 1",
+        // no king for the side to play
+        "4k3/8/8/8/8/8/8/R7 w - - 0 1",
+    ];
+
+    fn check(gpu: &GpuMoveGen, device: &wgpu::Device, queue: &wgpu::Queue, board: &BoardState) {
+        let masks = gpu.masks(device, queue, board).unwrap();
+        for (square, (gpu, cpu)) in masks.iter().zip(cpu_masks(board)).enumerate() {
+            assert_eq!(
+                *gpu,
+                cpu,
+                "{} on {}",
+                board.to_fen(),
+                board.square_name(square as u32)
+            );
+        }
+    }
+
+    #[test]
+    fn gpu_matches_cpu() {
+        let Some((device, queue)) = gpu::headless()
+            .inspect_err(|e| eprintln!("{e}, skipping"))
+            .ok()
+        else {
+            return;
+        };
+        let gpu = GpuMoveGen::new(&device).unwrap();
+        for fen in POSITIONS {
+            for fog_of_war in [false, true] {
+                let mut board = BoardState::from_fen(fen).unwrap();
+                board.fog_of_war = fog_of_war;
+                check(&gpu, &device, &queue, &board);
+                // and every position a move later
+                for mv in board.moves() {
+                    let undo = board.make(mv);
+                    check(&gpu, &device, &queue, &board);
+                    board.unmake(undo);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,494 @@
+use std::sync::Arc;
+
+use crate::logic::{BoardState, Piece, PieceKind, Undo};
+use crate::search::MAX_PLY;
+
+const MAGIC: &[u8; 4] = b"CGNN";
+const VERSION: u32 = 1;
+pub const QA: i32 = 255;
+pub const QB: i32 = 64;
+pub const SCALE: i32 = 400;
+// kinds of pieces the inputs know, fairy pieces aren't among them
+const KINDS: usize = 6;
+
+/// An efficiently updatable neural network: one input per (piece colour, piece kind, square)
+/// as each side sees it, a hidden layer per side that moves update a few weights of at a time,
+/// and a single output. Networks are read from this format, little endian:
+///
+/// ```text
+/// magic            4 bytes    "CGNN"
+/// version          u32        1
+/// width, height    u32, u32   board the network was trained for
+/// hidden           u32        hidden size H, a multiple of 16
+/// feature weights  i16 x I*H  H weights per input, input after input
+/// feature biases   i16 x H
+/// output weights   i16 x 2*H  the side to play's half first
+/// output bias      i32
+/// ```
+///
+/// There are I = 2 * 6 * width * height inputs, input `(colour * 6 + kind) * width * height +
+/// square`. Colour is 0 for the pieces of the side the hidden layer belongs to, kinds go pawn,
+/// knight, bishop, rook, queen, king from 0 and squares count from a1 along the ranks, with
+/// black seeing the board flipped so its a8 is square 0. Feature weights and biases are scaled
+/// by `QA`, output weights by `QB` and the output bias by both. The output is `bias +
+/// sum(clamp(us, 0, QA) * us_weights) + sum(clamp(them, 0, QA) * them_weights)`, and times
+/// `SCALE / (QA * QB)` it is centipawns for the side to play.
+pub struct Network {
+    width: u32,
+    height: u32,
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+impl Network {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path).map_err(|e| anyhow::anyhow!("{path}: {e}"))?;
+        Self::from_bytes(&bytes).map_err(|e| anyhow::anyhow!("{path}: {e}"))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = Reader(bytes);
+        if reader.take(4)? != MAGIC {
+            return Err(anyhow::anyhow!("not a network"));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(anyhow::anyhow!(
+                "network version {version} is not supported"
+            ));
+        }
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let hidden = reader.u32()? as usize;
+        if hidden == 0 || !hidden.is_multiple_of(16) {
+            return Err(anyhow::anyhow!(
+                "hidden size {hidden} is not a multiple of 16"
+            ));
+        }
+        let inputs = 2 * KINDS * (width * height) as usize;
+        let network = Self {
+            width,
+            height,
+            hidden,
+            feature_weights: reader.i16s(inputs * hidden)?,
+            feature_biases: reader.i16s(hidden)?,
+            output_weights: reader.i16s(2 * hidden)?,
+            output_bias: reader.u32()? as i32,
+        };
+        if !reader.0.is_empty() {
+            return Err(anyhow::anyhow!("{} bytes too many", reader.0.len()));
+        }
+        Ok(network)
+    }
+
+    /// Whether positions of `board`'s size can be evaluated.
+    pub fn fits(&self, board: &BoardState) -> bool {
+        (board.width, board.height) == (self.width, self.height)
+    }
+
+    /// Evaluates `board` from scratch, from the side to play's point of view. `None` when the
+    /// board doesn't fit or has pieces the network doesn't know.
+    pub fn evaluate(&self, board: &BoardState) -> Option<i32> {
+        if !self.fits(board) {
+            return None;
+        }
+        Accumulator::new(self, board).evaluate(self, board.white_to_play)
+    }
+
+    // weights of the input for `piece` on `square` as `white` sees it
+    fn weights(&self, white: bool, square: u32, piece: Piece) -> Option<&[i16]> {
+        let kind = match piece.kind {
+            PieceKind::Pawn => 0,
+            PieceKind::Knight => 1,
+            PieceKind::Bishop => 2,
+            PieceKind::Rook => 3,
+            PieceKind::Queen => 4,
+            PieceKind::King => 5,
+            PieceKind::Fairy(_) => return None,
+        };
+        let square = if white {
+            square
+        } else {
+            (self.height - 1 - square / self.width) * self.width + square % self.width
+        };
+        let colour = (piece.white != white) as usize;
+        let input = (colour * KINDS + kind) * (self.width * self.height) as usize + square as usize;
+        Some(&self.feature_weights[input * self.hidden..(input + 1) * self.hidden])
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(anyhow::anyhow!("network ends early"));
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i16s(&mut self, n: usize) -> anyhow::Result<Vec<i16>> {
+        Ok(self
+            .take(n * 2)?
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect())
+    }
+}
+
+/// The hidden layer of one position, for both sides.
+#[derive(Clone)]
+struct Accumulator {
+    white: Vec<i16>,
+    black: Vec<i16>,
+    // pieces on the board without inputs, the network can't judge the position while there are any
+    unknown: u32,
+}
+
+impl Accumulator {
+    fn new(network: &Network, board: &BoardState) -> Self {
+        let mut accumulator = Self {
+            white: network.feature_biases.clone(),
+            black: network.feature_biases.clone(),
+            unknown: 0,
+        };
+        for (square, piece) in board.pieces.iter().enumerate() {
+            if let Some(piece) = *piece {
+                accumulator.update(network, square as u32, piece, true);
+            }
+        }
+        accumulator
+    }
+
+    fn update(&mut self, network: &Network, square: u32, piece: Piece, add: bool) {
+        match (
+            network.weights(true, square, piece),
+            network.weights(false, square, piece),
+        ) {
+            (Some(white), Some(black)) => {
+                simd::update(&mut self.white, white, add);
+                simd::update(&mut self.black, black, add);
+            }
+            _ if add => self.unknown += 1,
+            _ => self.unknown -= 1,
+        }
+    }
+
+    fn evaluate(&self, network: &Network, white_to_play: bool) -> Option<i32> {
+        if self.unknown > 0 {
+            return None;
+        }
+        let (us, them) = if white_to_play {
+            (&self.white, &self.black)
+        } else {
+            (&self.black, &self.white)
+        };
+        let (us_weights, them_weights) = network.output_weights.split_at(network.hidden);
+        let output =
+            network.output_bias + simd::dot(us, us_weights) + simd::dot(them, them_weights);
+        Some((output as i64 * SCALE as i64 / (QA * QB) as i64) as i32)
+    }
+}
+
+/// The accumulators along the line the search is on, one per ply.
+pub struct NnueState {
+    network: Arc<Network>,
+    stack: Vec<Accumulator>,
+    ply: usize,
+}
+
+impl NnueState {
+    /// Starts at `board`, which has to fit the network.
+    pub fn new(network: Arc<Network>, board: &BoardState) -> Self {
+        let root = Accumulator::new(&network, board);
+        Self {
+            stack: vec![root; MAX_PLY + 1],
+            network,
+            ply: 0,
+        }
+    }
+
+    /// Follows `board.make`, with the `undo` it returned. `BoardState::make_with` calls it.
+    pub fn make(&mut self, board: &BoardState, undo: &Undo) {
+        let (done, rest) = self.stack.split_at_mut(self.ply + 1);
+        let next = &mut rest[0];
+        let previous = &done[self.ply];
+        next.white.copy_from_slice(&previous.white);
+        next.black.copy_from_slice(&previous.black);
+        next.unknown = previous.unknown;
+        for (square, before) in undo.changed() {
+            if let Some(piece) = before {
+                next.update(&self.network, square, piece, false);
+            }
+            if let Some(piece) = board.pieces[square as usize] {
+                next.update(&self.network, square, piece, true);
+            }
+        }
+        self.ply += 1;
+    }
+
+    /// Follows `board.unmake`.
+    pub fn unmake(&mut self) {
+        self.ply -= 1;
+    }
+
+    /// Centipawns for the side to play, `None` while there are pieces the network doesn't know.
+    pub fn evaluate(&self, white_to_play: bool) -> Option<i32> {
+        self.stack[self.ply].evaluate(&self.network, white_to_play)
+    }
+}
+
+/// Vector code for the accumulators and the output layer, AVX2 where the CPU has it. Other
+/// targets get loops the compiler vectorises for their baseline, NEON on aarch64.
+mod simd {
+    use super::QA;
+
+    /// Adds `weights` to `accumulator`, or subtracts them.
+    pub fn update(accumulator: &mut [i16], weights: &[i16], add: bool) {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: the CPU has AVX2
+            return unsafe { update_avx2(accumulator, weights, add) };
+        }
+        update_scalar(accumulator, weights, add);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2")]
+    fn update_avx2(accumulator: &mut [i16], weights: &[i16], add: bool) {
+        use std::arch::x86_64::*;
+
+        for (values, weights) in accumulator
+            .chunks_exact_mut(16)
+            .zip(weights.chunks_exact(16))
+        {
+            // SAFETY: both chunks are 16 i16s long, unaligned loads and stores are fine
+            unsafe {
+                let value = _mm256_loadu_si256(values.as_ptr().cast());
+                let weight = _mm256_loadu_si256(weights.as_ptr().cast());
+                let value = if add {
+                    _mm256_add_epi16(value, weight)
+                } else {
+                    _mm256_sub_epi16(value, weight)
+                };
+                _mm256_storeu_si256(values.as_mut_ptr().cast(), value);
+            }
+        }
+    }
+
+    fn update_scalar(accumulator: &mut [i16], weights: &[i16], add: bool) {
+        if add {
+            for (value, weight) in accumulator.iter_mut().zip(weights) {
+                *value = value.wrapping_add(*weight);
+            }
+        } else {
+            for (value, weight) in accumulator.iter_mut().zip(weights) {
+                *value = value.wrapping_sub(*weight);
+            }
+        }
+    }
+
+    /// `sum(clamp(input, 0, QA) * weights)`.
+    pub fn dot(input: &[i16], weights: &[i16]) -> i32 {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: the CPU has AVX2
+            return unsafe { dot_avx2(input, weights) };
+        }
+        dot_scalar(input, weights)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2")]
+    fn dot_avx2(input: &[i16], weights: &[i16]) -> i32 {
+        use std::arch::x86_64::*;
+
+        let zero = _mm256_setzero_si256();
+        let qa = _mm256_set1_epi16(QA as i16);
+        let mut sum = _mm256_setzero_si256();
+        // the hidden size is a multiple of 16, so nothing is left over
+        for (input, weights) in input.chunks_exact(16).zip(weights.chunks_exact(16)) {
+            // SAFETY: both chunks are 16 i16s long, unaligned loads are fine
+            let (input, weights) = unsafe {
+                (
+                    _mm256_loadu_si256(input.as_ptr().cast()),
+                    _mm256_loadu_si256(weights.as_ptr().cast()),
+                )
+            };
+            let clamped = _mm256_min_epi16(_mm256_max_epi16(input, zero), qa);
+            sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clamped, weights));
+        }
+        let mut lanes = [0i32; 8];
+        // SAFETY: `lanes` holds 8 i32s
+        unsafe { _mm256_storeu_si256(lanes.as_mut_ptr().cast(), sum) };
+        lanes.iter().sum()
+    }
+
+    fn dot_scalar(input: &[i16], weights: &[i16]) -> i32 {
+        input
+            .iter()
+            .zip(weights)
+            .map(|(&input, &weight)| (input as i32).clamp(0, QA) * weight as i32)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a network in the documented format with made up weights
+    fn network_bytes(hidden: u32) -> Vec<u8> {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut weight = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % 255) as i16 - 127
+        };
+        let mut bytes = MAGIC.to_vec();
+        for value in [VERSION, 8, 8, hidden] {
+            bytes.extend(value.to_le_bytes());
+        }
+        let inputs = 2 * KINDS as u32 * 64;
+        for _ in 0..(inputs + 1 + 2) * hidden {
+            bytes.extend(weight().to_le_bytes());
+        }
+        bytes.extend(1234i32.to_le_bytes());
+        bytes
+    }
+
+    // the hidden layer summed up input by input, without the vector code
+    fn reference(network: &Network, board: &BoardState, white: bool) -> Vec<i16> {
+        let mut values = network.feature_biases.clone();
+        for (square, piece) in board.pieces.iter().enumerate() {
+            let Some(piece) = *piece else {
+                continue;
+            };
+            let weights = network.weights(white, square as u32, piece).unwrap();
+            for (value, weight) in values.iter_mut().zip(weights) {
+                *value = value.wrapping_add(*weight);
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn loads_the_documented_format() {
+        let network = Network::from_bytes(&network_bytes(32)).unwrap();
+        assert_eq!((network.width, network.height, network.hidden), (8, 8, 32));
+        assert_eq!(network.feature_weights.len(), 2 * 6 * 64 * 32);
+        assert_eq!(network.output_bias, 1234);
+
+        // the start position looks the same to both sides
+        let white = BoardState::from_fen(crate::logic::STARTING_FEN).unwrap();
+        let mut black = white.clone();
+        black.white_to_play = false;
+        black.rehash();
+        assert!(network.evaluate(&white).is_some());
+        assert_eq!(network.evaluate(&white), network.evaluate(&black));
+
+        let board = BoardState::from_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        )
+        .unwrap();
+        let accumulator = Accumulator::new(&network, &board);
+        assert_eq!(accumulator.white, reference(&network, &board, true));
+        assert_eq!(accumulator.black, reference(&network, &board, false));
+        let clamp = |values: &[i16], weights: &[i16]| -> i32 {
+            values
+                .iter()
+                .zip(weights)
+                .map(|(&value, &weight)| (value as i32).clamp(0, QA) * weight as i32)
+                .sum()
+        };
+        let (us, them) = network.output_weights.split_at(32);
+        let output =
+            network.output_bias + clamp(&accumulator.white, us) + clamp(&accumulator.black, them);
+        assert_eq!(
+            network.evaluate(&board),
+            Some((output as i64 * SCALE as i64 / (QA * QB) as i64) as i32)
+        );
+    }
+
+    #[test]
+    fn rejects_broken_networks() {
+        let bytes = network_bytes(16);
+        let error = |bytes: &[u8]| Network::from_bytes(bytes).err().unwrap().to_string();
+        assert_eq!(error(&bytes[..bytes.len() - 1]), "network ends early");
+        assert_eq!(error(&bytes[..20]), "network ends early");
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(error(&longer), "1 bytes too many");
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert_eq!(error(&magic), "not a network");
+        let mut version = bytes.clone();
+        version[4] = 2;
+        assert_eq!(error(&version), "network version 2 is not supported");
+        let mut hidden = bytes;
+        hidden[16] = 24;
+        assert_eq!(error(&hidden), "hidden size 24 is not a multiple of 16");
+    }
+
+    #[test]
+    fn incremental_accumulators_match_a_refresh() {
+        let network = Arc::new(Network::from_bytes(&network_bytes(32)).unwrap());
+        let fens = [
+            // castling both ways for both sides
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            // en passant
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            // promotions, with and without a capture
+            "r3k3/1P4P1/8/8/8/8/1p4p1/R3K2R b KQq - 0 1",
+        ];
+        fn walk(board: &mut BoardState, nnue: &mut NnueState, depth: u32, seen: &mut [bool; 3]) {
+            let current = &nnue.stack[nnue.ply];
+            let refreshed = Accumulator::new(&nnue.network, board);
+            assert_eq!(current.white, refreshed.white);
+            assert_eq!(current.black, refreshed.black);
+            assert_eq!(
+                nnue.evaluate(board.white_to_play),
+                nnue.network.evaluate(board)
+            );
+            if depth == 0 {
+                return;
+            }
+            for mv in board.moves() {
+                let piece = board.pieces[mv.from as usize].unwrap();
+                let sideways = board.file_of(mv.from) != board.file_of(mv.to);
+                seen[0] |= piece.kind == PieceKind::King
+                    && board.file_of(mv.from).abs_diff(board.file_of(mv.to)) == 2;
+                seen[1] |= piece.kind == PieceKind::Pawn
+                    && sideways
+                    && board.pieces[mv.to as usize].is_none();
+                seen[2] |= mv.promotion.is_some();
+                let key = board.hash();
+                let undo = board.make_with(mv, Some(nnue));
+                walk(board, nnue, depth - 1, seen);
+                board.unmake_with(undo, Some(nnue));
+                assert_eq!(board.hash(), key);
+            }
+        }
+        let mut seen = [false; 3];
+        for fen in fens {
+            let mut board = BoardState::from_fen(fen).unwrap();
+            let mut nnue = NnueState::new(network.clone(), &board);
+            walk(&mut board, &mut nnue, 2, &mut seen);
+            assert_eq!(nnue.ply, 0);
+        }
+        assert_eq!(
+            seen, [true; 3],
+            "castling, en passant and promotions were played"
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use wgpu::util::DeviceExt;
+
+use crate::gpu;
+use crate::logic::{BoardState, STARTING_FEN};
+use crate::movegen::PackedBoard;
+
+/// How many positions one level of the tree holds on the GPU, bigger levels are expanded a
+/// chunk at a time.
+pub const DEFAULT_CAPACITY: usize = 1 << 17;
+
+/// A position as `assets/perft.wgsl` stores it, the squares a byte each.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Position {
+    squares: [u32; 32],
+    flags: u32,
+    en_passant: u32,
+}
+
+impl Position {
+    fn new(board: &PackedBoard) -> Self {
+        let mut squares = [0; 32];
+        for (square, piece) in board.squares.iter().enumerate() {
+            squares[square / 4] |= piece << (square % 4 * 8);
+        }
+        Self {
+            squares,
+            flags: board.flags,
+            en_passant: board.en_passant,
+        }
+    }
+}
+
+/// The part of `positions` a dispatch works on.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    first: u32,
+    count: u32,
+}
+
+// the buffers of one ply of the tree
+struct Level {
+    positions: wgpu::Buffer,
+    counts: wgpu::Buffer,
+    offsets: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    params: wgpu::Buffer,
+}
+
+/// Perft on the GPU: the tree is expanded breadth first, a ply per pair of dispatches, and
+/// the last ply is only counted.
+pub struct GpuPerft {
+    count_pipeline: wgpu::ComputePipeline,
+    expand_pipeline: wgpu::ComputePipeline,
+    capacity: usize,
+}
+
+impl GpuPerft {
+    pub fn new(device: &wgpu::Device) -> anyhow::Result<Self> {
+        let shader = gpu::shader(device, "Perft Shader", &["movegen.wgsl", "perft.wgsl"])?;
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: None,
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        Ok(Self {
+            count_pipeline: pipeline("count_moves"),
+            expand_pipeline: pipeline("expand"),
+            capacity: DEFAULT_CAPACITY,
+        })
+    }
+
+    /// Positions per ply held on the GPU at once, at least as many as a position has moves.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Counts the leaf nodes of the legal move tree, like `BoardState::perft`.
+    pub fn perft(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        board: &BoardState,
+        depth: u32,
+    ) -> anyhow::Result<u64> {
+        if depth == 0 {
+            return Ok(1);
+        }
+        let packed = PackedBoard::new(board)
+            .ok_or_else(|| anyhow::anyhow!("too many kinds of pieces for the GPU"))?;
+        let board_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Perft Board Buffer"),
+            contents: bytemuck::bytes_of(&packed),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let levels = (0..depth).map(|_| self.level(device)).collect::<Vec<_>>();
+        queue.write_buffer(
+            &levels[0].positions,
+            0,
+            bytemuck::bytes_of(&Position::new(&packed)),
+        );
+        let run = Run {
+            device,
+            queue,
+            board_buffer: &board_buffer,
+            levels: &levels,
+        };
+        self.count(&run, 0, 1)
+    }
+
+    fn level(&self, device: &wgpu::Device) -> Level {
+        let buffer = |label, size, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as wgpu::BufferAddress,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let storage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+        Level {
+            positions: buffer(
+                "Perft Positions",
+                self.capacity * size_of::<Position>(),
+                storage,
+            ),
+            counts: buffer(
+                "Perft Counts",
+                self.capacity * 4,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            ),
+            offsets: buffer("Perft Offsets", self.capacity * 4, storage),
+            readback: buffer(
+                "Perft Readback",
+                self.capacity * 4,
+                wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            ),
+            params: buffer(
+                "Perft Params",
+                size_of::<Params>(),
+                wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            ),
+        }
+    }
+
+    // leaf nodes below the first `count` positions of ply `ply`
+    fn count(&self, run: &Run, ply: usize, count: usize) -> anyhow::Result<u64> {
+        let level = &run.levels[ply];
+        // counting shares its code with expanding, so it wants somewhere for children it never
+        // writes
+        let bind_group = run.bind_group(
+            &self.count_pipeline,
+            ply,
+            &[(3, &level.counts), (5, &level.offsets)],
+        );
+        run.dispatch(&self.count_pipeline, &bind_group, ply, 0, count);
+        let counts = run.read_counts(ply, count)?;
+        if ply + 1 == run.levels.len() {
+            return Ok(counts.iter().map(|&moves| moves as u64).sum());
+        }
+
+        // as many positions as have children that fit into the next ply, a chunk at a time
+        let bind_group = run.bind_group(
+            &self.expand_pipeline,
+            ply,
+            &[(4, &level.offsets), (5, &run.levels[ply + 1].positions)],
+        );
+        let mut nodes = 0;
+        let mut start = 0;
+        while start < count {
+            let mut offsets = Vec::new();
+            let mut children = 0;
+            for &moves in &counts[start..] {
+                if children + moves as usize > self.capacity {
+                    break;
+                }
+                offsets.push(children as u32);
+                children += moves as usize;
+            }
+            if offsets.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "a position has more moves than the perft capacity"
+                ));
+            }
+            run.queue.write_buffer(
+                &level.offsets,
+                (start * 4) as wgpu::BufferAddress,
+                bytemuck::cast_slice(&offsets),
+            );
+            run.dispatch(
+                &self.expand_pipeline,
+                &bind_group,
+                ply,
+                start,
+                offsets.len(),
+            );
+            nodes += self.count(run, ply + 1, children)?;
+            start += offsets.len();
+        }
+        Ok(nodes)
+    }
+}
+
+// what a single `GpuPerft::perft` works with
+struct Run<'a> {
+    device: &'a wgpu::Device,
+    queue: &'a wgpu::Queue,
+    board_buffer: &'a wgpu::Buffer,
+    levels: &'a [Level],
+}
+
+impl Run<'_> {
+    fn bind_group(
+        &self,
+        pipeline: &wgpu::ComputePipeline,
+        ply: usize,
+        buffers: &[(u32, &wgpu::Buffer)],
+    ) -> wgpu::BindGroup {
+        let level = &self.levels[ply];
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: self.board_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: level.params.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: level.positions.as_entire_binding(),
+            },
+        ];
+        for &(binding, buffer) in buffers {
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: buffer.as_entire_binding(),
+            });
+        }
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+            label: Some("perft_bind_group"),
+        })
+    }
+
+    fn dispatch(
+        &self,
+        pipeline: &wgpu::ComputePipeline,
+        bind_group: &wgpu::BindGroup,
+        ply: usize,
+        first: usize,
+        count: usize,
+    ) {
+        let params = Params {
+            first: first as u32,
+            count: count as u32,
+        };
+        self.queue
+            .write_buffer(&self.levels[ply].params, 0, bytemuck::bytes_of(&params));
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Perft Encoder"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Perft Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups((count as u32).div_ceil(64), 1, 1);
+        }
+        self.queue.submit([encoder.finish()]);
+    }
+
+    fn read_counts(&self, ply: usize, count: usize) -> anyhow::Result<Vec<u32>> {
+        let level = &self.levels[ply];
+        let size = (count * 4) as wgpu::BufferAddress;
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Perft Readback Encoder"),
+            });
+        encoder.copy_buffer_to_buffer(&level.counts, 0, &level.readback, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        let slice = level.readback.slice(..size);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        receiver.recv()??;
+        let counts = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        level.readback.unmap();
+        Ok(counts)
+    }
+}
+
+/// `perft <depth> [fen]`: counts the move tree on the GPU and checks it against the CPU.
+pub fn perft_command(args: Vec<String>) -> anyhow::Result<()> {
+    let usage = || anyhow::anyhow!("usage: perft <depth> [fen]");
+    let (depth, fen) = args.split_first().ok_or_else(usage)?;
+    let depth = depth.parse::<u32>().map_err(|_| usage())?;
+    let fen = if fen.is_empty() {
+        STARTING_FEN.to_owned()
+    } else {
+        fen.join(" ")
+    };
+    let board = BoardState::from_fen(&fen)?;
+
+    let (device, queue) = gpu::headless()?;
+    let gpu = GpuPerft::new(&device)?;
+    let start = std::time::Instant::now();
+    let gpu_nodes = gpu.perft(&device, &queue, &board, depth)?;
+    let gpu_time = start.elapsed();
+    println!(
+        "gpu {gpu_nodes} nodes in {gpu_time:.2?} ({:.0} nodes/s)",
+        gpu_nodes as f64 / gpu_time.as_secs_f64()
+    );
+
+    let start = std::time::Instant::now();
+    let cpu_nodes = board.perft(depth);
+    let cpu_time = start.elapsed();
+    println!(
+        "cpu {cpu_nodes} nodes in {cpu_time:.2?} ({:.0} nodes/s)",
+        cpu_nodes as f64 / cpu_time.as_secs_f64()
+    );
+    if gpu_nodes != cpu_nodes {
+        return Err(anyhow::anyhow!("the GPU and CPU disagree"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::CAPABLANCA_FEN;
+
+    const POSITIONS: &[(&str, u32)] = &[
+        (STARTING_FEN, 4),
+        (CAPABLANCA_FEN, 3),
+        (
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            3,
+        ),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4),
+        (
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            3,
+        ),
+        ("n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1", 3),
+        // promoting to fairy pieces while they are on the board
+        ("4k3/1P6/8/8/8/8/6p1/1a2K2C w - - 0 1", 3),
+    ];
+
+    #[test]
+    fn gpu_perft_matches_cpu() {
+        let Some((device, queue)) = gpu::headless()
+            .inspect_err(|e| eprintln!("{e}, skipping"))
+            .ok()
+        else {
+            return;
+        };
+        let perft = GpuPerft::new(&device).unwrap();
+        for &(fen, depth) in POSITIONS {
+            for fog_of_war in [false, true] {
+                let mut board = BoardState::from_fen(fen).unwrap();
+                board.fog_of_war = fog_of_war;
+                assert_eq!(
+                    perft.perft(&device, &queue, &board, depth).unwrap(),
+                    board.perft(depth),
+                    "{fen}, fog of war {fog_of_war}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn gpu_perft_in_chunks() {
+        let Some((device, queue)) = gpu::headless()
+            .inspect_err(|e| eprintln!("{e}, skipping"))
+            .ok()
+        else {
+            return;
+        };
+        let perft = GpuPerft::new(&device).unwrap().with_capacity(300);
+        let board = BoardState::from_fen(POSITIONS[2].0).unwrap();
+        assert_eq!(perft.perft(&device, &queue, &board, 3).unwrap(), 97862);
+    }
+}
@@ -0,0 +1,472 @@
+use crate::annotations::Annotations;
+use crate::game_tree::{GameTree, Node, ROOT};
+use crate::logic::{BoardState, Move, STARTING_FEN};
+
+/// One game of a PGN file: its tags and the main line, variations and comments are skipped.
+#[derive(Clone, Debug)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub start: BoardState,
+    pub moves: Vec<Move>,
+    /// `1-0`, `0-1`, `1/2-1/2` or `*`.
+    pub result: String,
+}
+
+impl Game {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether white won, `None` for draws and unfinished games.
+    pub fn white_won(&self) -> Option<bool> {
+        match self.result.as_str() {
+            "1-0" => Some(true),
+            "0-1" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// Games of a PGN database in order. A game with an illegal move comes out as an error without
+/// stopping the ones after it.
+pub fn games(text: &str) -> Games<'_> {
+    Games { text }
+}
+
+pub struct Games<'a> {
+    // what is left to read
+    text: &'a str,
+}
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+impl Iterator for Games<'_> {
+    type Item = anyhow::Result<Game>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut tags = Vec::new();
+        let mut words = Vec::new();
+        let mut result = None;
+        // nesting of variations, whose moves are not part of the game
+        let mut depth = 0;
+        loop {
+            self.text = self.text.trim_start();
+            // a tag after movetext starts the next game, even if the result was left out
+            if self.text.is_empty() || (self.text.starts_with('[') && !words.is_empty()) {
+                break;
+            }
+            let mut chars = self.text.chars();
+            match chars.next() {
+                Some('[') => {
+                    let (tag, rest) = read_tag(self.text);
+                    self.text = rest;
+                    tags.extend(tag);
+                }
+                Some('{') => self.text = self.text.split_once('}').map_or("", |(_, rest)| rest),
+                Some(';') => self.text = self.text.split_once('\n').map_or("", |(_, rest)| rest),
+                Some('(') => {
+                    depth += 1;
+                    self.text = chars.as_str();
+                }
+                Some(')') => {
+                    depth -= 1;
+                    self.text = chars.as_str();
+                }
+                _ => {
+                    let end = self
+                        .text
+                        .find(|c: char| c.is_whitespace() || "[]{}();".contains(c))
+                        .unwrap_or(self.text.len());
+                    let word = &self.text[..end];
+                    self.text = &self.text[end..];
+                    if depth > 0 || word.starts_with('$') {
+                        continue;
+                    }
+                    if RESULTS.contains(&word) {
+                        result = Some(word.to_owned());
+                        break;
+                    }
+                    let word = strip_move_number(word);
+                    if word.is_empty() {
+                        continue;
+                    }
+                    words.push(word);
+                }
+            }
+        }
+        if tags.is_empty() && words.is_empty() && result.is_none() {
+            return None;
+        }
+        Some(game(tags, &words, result))
+    }
+}
+
+// the tag `text` starts with, and the text after it
+fn read_tag(text: &str) -> (Option<(String, String)>, &str) {
+    let (tag, rest) = text[1..].split_once(']').unwrap_or((&text[1..], ""));
+    let tag = tag
+        .trim()
+        .split_once(char::is_whitespace)
+        .map(|(name, value)| {
+            let value = value.trim().trim_matches('"').replace("\\\"", "\"");
+            (name.to_owned(), value)
+        });
+    (tag, rest)
+}
+
+// move numbers, `12.`, `12...` or glued on as in `12.e4`, but not the zeros of `0-0`
+fn strip_move_number(word: &str) -> &str {
+    if word.bytes().all(|b| b.is_ascii_digit()) {
+        return "";
+    }
+    match word.split_once('.') {
+        Some((number, rest)) if number.bytes().all(|b| b.is_ascii_digit()) => {
+            rest.trim_start_matches('.')
+        }
+        _ => word,
+    }
+}
+
+fn game(
+    tags: Vec<(String, String)>,
+    words: &[&str],
+    result: Option<String>,
+) -> anyhow::Result<Game> {
+    let tag = |name: &str| {
+        tags.iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let start = BoardState::from_fen(tag("FEN").unwrap_or(STARTING_FEN))?;
+    let result = tag("Result")
+        .filter(|result| RESULTS.contains(result))
+        .map(str::to_owned)
+        .or(result)
+        .unwrap_or_else(|| "*".to_owned());
+
+    let mut board = start.clone();
+    let mut moves = Vec::new();
+    for word in words {
+        let mv = board
+            .parse_san(word)
+            .ok_or_else(|| anyhow::anyhow!("illegal move {word} at ply {}", moves.len() + 1))?;
+        moves.push(mv);
+        board.make(mv);
+    }
+    Ok(Game {
+        tags,
+        start,
+        moves,
+        result,
+    })
+}
+
+// numeric annotation glyphs of the move suffixes
+const GLYPHS: [(&str, u8); 6] = [
+    ("!", 1),
+    ("?", 2),
+    ("!!", 3),
+    ("??", 4),
+    ("!?", 5),
+    ("?!", 6),
+];
+
+/// Reads the first game of `text` with its variations, comments, NAGs and `[%csl]`/`[%cal]`
+/// marks. The tree is left at the start position.
+pub fn read_tree(text: &str) -> anyhow::Result<GameTree> {
+    let mut text = text.trim_start();
+    let mut tags = Vec::new();
+    while text.starts_with('[') {
+        let (tag, rest) = read_tag(text);
+        tags.extend(tag);
+        text = rest.trim_start();
+    }
+    let fen = tags
+        .iter()
+        .find(|(name, _)| name == "FEN")
+        .map_or(STARTING_FEN, |(_, value)| value.as_str());
+    let start = BoardState::from_fen(fen)?;
+    let mut tree = GameTree::new(start.clone());
+
+    // the node moves are played from with its position, and the same before the last move,
+    // where a variation branches off
+    let mut node = ROOT;
+    let mut board = start.clone();
+    let mut before = (ROOT, start);
+    let mut variations = Vec::new();
+    loop {
+        text = text.trim_start();
+        let mut chars = text.chars();
+        match chars.next() {
+            // the next game
+            None | Some('[') => break,
+            Some(delimiter @ ('{' | ';')) => {
+                let end = if delimiter == '{' { '}' } else { '\n' };
+                let (comment, rest) = chars
+                    .as_str()
+                    .split_once(end)
+                    .unwrap_or((chars.as_str(), ""));
+                text = rest;
+                let (annotations, comment) = Annotations::from_comment(comment, &board);
+                let node = tree.node_mut(node);
+                node.annotations.circles.extend(annotations.circles);
+                node.annotations.arrows.extend(annotations.arrows);
+                if !comment.is_empty() {
+                    if !node.comment.is_empty() {
+                        node.comment.push(' ');
+                    }
+                    node.comment += &comment;
+                }
+            }
+            Some('(') => {
+                text = chars.as_str();
+                variations.push((node, board.clone(), before.clone()));
+                (node, board) = before.clone();
+            }
+            Some(')') => {
+                text = chars.as_str();
+                (node, board, before) = variations
+                    .pop()
+                    .ok_or_else(|| anyhow::anyhow!("unbalanced parenthesis"))?;
+            }
+            _ => {
+                let end = text
+                    .find(|c: char| c.is_whitespace() || "[]{}();".contains(c))
+                    .unwrap_or(text.len());
+                let word = &text[..end];
+                text = &text[end..];
+                if let Some(nag) = word.strip_prefix('$') {
+                    let nag = nag
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("bad annotation glyph {word}"))?;
+                    tree.node_mut(node).nags.push(nag);
+                    continue;
+                }
+                if RESULTS.contains(&word) {
+                    if variations.is_empty() {
+                        break;
+                    }
+                    continue;
+                }
+                let word = strip_move_number(word);
+                if word.is_empty() {
+                    continue;
+                }
+                let san = word.trim_end_matches(['!', '?']);
+                let mv = board
+                    .parse_san(san)
+                    .ok_or_else(|| anyhow::anyhow!("illegal move {word}"))?;
+                before = (node, board.clone());
+                board.make(mv);
+                node = tree.add(node, mv);
+                if let Some(&(_, nag)) = GLYPHS
+                    .iter()
+                    .find(|(glyph, _)| *glyph == &word[san.len()..])
+                {
+                    tree.node_mut(node).nags.push(nag);
+                }
+            }
+        }
+    }
+    if !variations.is_empty() {
+        return Err(anyhow::anyhow!("unbalanced parenthesis"));
+    }
+    Ok(tree)
+}
+
+/// Writes `tree` as PGN with its variations, NAGs and comments, marks going into the comments
+/// as `[%csl]` and `[%cal]` commands.
+pub fn write(tree: &GameTree) -> String {
+    let mut movetext = Vec::new();
+    movetext.extend(comment(tree.node(ROOT), tree.start()));
+    write_line(tree, tree.start(), ROOT, false, &mut movetext);
+
+    let mut board = tree.start().clone();
+    let mut node = ROOT;
+    while let Some(&child) = tree.node(node).children.first() {
+        board.make(tree.node(child).mv.unwrap());
+        node = child;
+    }
+    let result = match board.is_game_over() {
+        1 if board.white_to_play => "0-1",
+        1 => "1-0",
+        0 => "*",
+        _ => "1/2-1/2",
+    };
+    movetext.push(result.to_owned());
+
+    let mut pgn = String::new();
+    for (name, value) in [
+        ("Event", "?"),
+        ("Site", "?"),
+        ("Date", "????.??.??"),
+        ("Round", "?"),
+        ("White", "?"),
+        ("Black", "?"),
+        ("Result", result),
+    ] {
+        pgn += &format!("[{name} \"{value}\"]\n");
+    }
+    let fen = tree.start().to_fen();
+    if fen != STARTING_FEN {
+        pgn += &format!("[SetUp \"1\"]\n[FEN \"{fen}\"]\n");
+    }
+    pgn.push('\n');
+    // lines of at most 80 characters, as the export format asks, parentheses hugging what
+    // they enclose
+    let mut line = String::new();
+    for word in movetext {
+        let glued = word == ")" || line.ends_with('(');
+        if !glued && !line.is_empty() && line.len() + word.len() + 1 > 80 {
+            pgn += &line;
+            pgn.push('\n');
+            line.clear();
+        }
+        if !glued && !line.is_empty() {
+            line.push(' ');
+        }
+        line += &word;
+    }
+    pgn += &line;
+    pgn.push('\n');
+    pgn
+}
+
+// the moves after `node`, whose position is `board`: its main line with the variations in
+// parentheses. `number` asks for the move number even before black's move.
+fn write_line(
+    tree: &GameTree,
+    board: &BoardState,
+    node: usize,
+    number: bool,
+    movetext: &mut Vec<String>,
+) {
+    let children = &tree.node(node).children;
+    let Some(&main) = children.first() else {
+        return;
+    };
+    let (after, commented) = write_move(tree, board, main, number, movetext);
+    for &variation in &children[1..] {
+        movetext.push("(".to_owned());
+        let (after, commented) = write_move(tree, board, variation, true, movetext);
+        write_line(tree, &after, variation, commented, movetext);
+        movetext.push(")".to_owned());
+    }
+    write_line(
+        tree,
+        &after,
+        main,
+        commented || children.len() > 1,
+        movetext,
+    );
+}
+
+// the move to `node` from `board` with its glyphs and comment, returns the position after it
+// and whether a comment followed
+fn write_move(
+    tree: &GameTree,
+    board: &BoardState,
+    node: usize,
+    number: bool,
+    movetext: &mut Vec<String>,
+) -> (BoardState, bool) {
+    let node = tree.node(node);
+    let mv = node.mv.unwrap();
+    // numbers stay on the line of their move
+    let san = board.san(mv);
+    movetext.push(if board.white_to_play {
+        format!("{}. {san}", board.fullmove_number)
+    } else if number {
+        format!("{}... {san}", board.fullmove_number)
+    } else {
+        san
+    });
+    movetext.extend(node.nags.iter().map(|nag| format!("${nag}")));
+    let mut after = board.clone();
+    after.make(mv);
+    let comment = comment(node, &after);
+    let commented = comment.is_some();
+    movetext.extend(comment);
+    (after, commented)
+}
+
+// the comment on `node` with its marks, `board` being its position
+fn comment(node: &Node, board: &BoardState) -> Option<String> {
+    let mut text = node.annotations.commands(board);
+    if !node.comment.is_empty() {
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text += &node.comment;
+    }
+    (!text.is_empty()).then(|| format!("{{{text}}}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_move_numbers() {
+        for (word, stripped) in [
+            ("12.", ""),
+            ("12...", ""),
+            ("12", ""),
+            ("12.e4", "e4"),
+            ("3...Nf6", "Nf6"),
+            ("0-0", "0-0"),
+            ("0-0-0", "0-0-0"),
+            ("5.0-0", "0-0"),
+            ("Nf3", "Nf3"),
+        ] {
+            assert_eq!(strip_move_number(word), stripped, "{word}");
+        }
+    }
+
+    #[test]
+    fn reads_castling_with_zeros() {
+        let text = "1.e4 e5 2.Nf3 Nc6 3.Bc4 Bc5 4.0-0 Nf6 5.d3 0-0 *";
+        let castled = "r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/3P1N2/PPP2PPP/RNBQ1RK1 w - - 1 6";
+        let game = games(text).next().unwrap().unwrap();
+        let mut board = game.start.clone();
+        for &mv in &game.moves {
+            board.make(mv);
+        }
+        assert_eq!(board.to_fen(), castled);
+
+        let mut tree = read_tree(text).unwrap();
+        tree.end();
+        assert_eq!(tree.board().to_fen(), castled);
+    }
+
+    #[test]
+    fn round_trips_trees() {
+        let movetext = "{Notes} 1. e4 $1 {[%csl Ge4][%cal Gd2d4] Best by test} 1... e5 (1... c5 2. \
+                        Nf3 (2. Nc3 Nc6 (2... e6) 3. g3) 2... d6 $6) 2. Nf3 Nc6 3. Bc4 Bc5 4. O-O! \
+                        Nf6 *";
+        let tree = read_tree(movetext).unwrap();
+        assert_eq!(tree.node(ROOT).comment, "Notes");
+        let e4 = tree.node(ROOT).children[0];
+        assert_eq!(tree.node(e4).nags, [1]);
+        assert_eq!(tree.node(e4).comment, "Best by test");
+        assert_eq!(tree.node(e4).annotations.circles.len(), 1);
+        assert_eq!(tree.node(e4).annotations.arrows.len(), 1);
+        // 1... c5 branches off 1... e5, 2. Nc3 off 2. Nf3 and 2... e6 off 2... Nc6
+        let [_, c5] = tree.node(e4).children[..] else {
+            panic!("expected 1... c5");
+        };
+        let [_, nc3] = tree.node(c5).children[..] else {
+            panic!("expected 2. Nc3");
+        };
+        assert_eq!(tree.node(nc3).children.len(), 2);
+
+        let pgn = write(&tree);
+        let written = pgn.split("\n\n").nth(1).unwrap().replace('\n', " ");
+        assert_eq!(written.trim_end(), movetext.replace("O-O!", "O-O $1"));
+        let again = read_tree(&pgn).unwrap();
+        assert_eq!(write(&again), pgn);
+    }
+}
@@ -0,0 +1,191 @@
+use std::sync::{LazyLock, RwLock};
+
+/// One component of a piece's movement, mirrored into all of its symmetric directions.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Movement {
+    /// Jumps exactly `(ranks, files)` away, e.g. `Leap(1, 2)` is the knight.
+    Leap(i8, i8),
+    /// Slides `(ranks, files)` steps at a time until blocked, e.g. `Ride(1, 1)` is the bishop.
+    Ride(i8, i8),
+}
+
+impl Movement {
+    pub fn rides(&self) -> bool {
+        matches!(self, Movement::Ride(..))
+    }
+
+    pub fn directions(&self) -> impl Iterator<Item = (i8, i8)> {
+        let (Movement::Leap(a, b) | Movement::Ride(a, b)) = *self;
+        let all = [
+            (a, b),
+            (a, -b),
+            (-a, b),
+            (-a, -b),
+            (b, a),
+            (b, -a),
+            (-b, a),
+            (-b, -a),
+        ];
+        (0..all.len())
+            .filter(move |&i| all[i] != (0, 0) && !all[..i].contains(&all[i]))
+            .map(move |i| all[i])
+    }
+}
+
+pub const KNIGHT: &[Movement] = &[Movement::Leap(1, 2)];
+pub const BISHOP: &[Movement] = &[Movement::Ride(1, 1)];
+pub const ROOK: &[Movement] = &[Movement::Ride(0, 1)];
+pub const QUEEN: &[Movement] = &[Movement::Ride(0, 1), Movement::Ride(1, 1)];
+pub const KING: &[Movement] = &[Movement::Leap(0, 1), Movement::Leap(1, 1)];
+
+/// A piece defined purely by how it moves, used for everything outside the standard six.
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub struct PieceDef {
+    pub name: &'static str,
+    /// Uppercase FEN letter, lowercase is used for black.
+    pub symbol: char,
+    pub movement: &'static [Movement],
+    /// Worth in centipawns, for the evaluation.
+    pub value: i32,
+    /// White and black sprite, relative to `assets`.
+    pub sprites: [&'static str; 2],
+}
+
+pub static ARCHBISHOP: PieceDef = PieceDef {
+    name: "Archbishop",
+    symbol: 'A',
+    movement: &[Movement::Ride(1, 1), Movement::Leap(1, 2)],
+    value: 825,
+    sprites: ["pieces/wa.png", "pieces/ba.png"],
+};
+
+pub static CHANCELLOR: PieceDef = PieceDef {
+    name: "Chancellor",
+    symbol: 'C',
+    movement: &[Movement::Ride(0, 1), Movement::Leap(1, 2)],
+    value: 875,
+    sprites: ["pieces/wc.png", "pieces/bc.png"],
+};
+
+pub static AMAZON: PieceDef = PieceDef {
+    name: "Amazon",
+    symbol: 'Z',
+    movement: &[
+        Movement::Ride(0, 1),
+        Movement::Ride(1, 1),
+        Movement::Leap(1, 2),
+    ],
+    value: 1250,
+    sprites: ["pieces/wz.png", "pieces/bz.png"],
+};
+
+static FAIRY_PIECES: LazyLock<RwLock<Vec<&'static PieceDef>>> =
+    LazyLock::new(|| RwLock::new(vec![&ARCHBISHOP, &CHANCELLOR, &AMAZON]));
+
+/// Makes a piece available to FEN parsing and the sprite atlas. Must happen before `State::new`.
+pub fn register(def: &'static PieceDef) -> anyhow::Result<()> {
+    if !def.symbol.is_ascii_uppercase() {
+        return Err(anyhow::anyhow!(
+            "piece symbol {:?} is not an uppercase letter",
+            def.symbol
+        ));
+    }
+    if "PNBRQK".contains(def.symbol) || find(def.symbol).is_some() {
+        return Err(anyhow::anyhow!(
+            "piece symbol {} is already taken",
+            def.symbol
+        ));
+    }
+    FAIRY_PIECES.write().unwrap().push(def);
+    Ok(())
+}
+
+pub fn fairy_pieces() -> Vec<&'static PieceDef> {
+    FAIRY_PIECES.read().unwrap().clone()
+}
+
+pub fn find(symbol: char) -> Option<&'static PieceDef> {
+    FAIRY_PIECES
+        .read()
+        .unwrap()
+        .iter()
+        .find(|def| def.symbol == symbol)
+        .copied()
+}
+
+/// Position of a registered piece, which decides where its sprites land in the atlas.
+pub fn index_of(def: &PieceDef) -> Option<usize> {
+    FAIRY_PIECES
+        .read()
+        .unwrap()
+        .iter()
+        .position(|other| *other == def)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::BoardState;
+
+    // a leaper and a rider from outside the built in set
+    static CAMEL: PieceDef = PieceDef {
+        name: "Camel",
+        symbol: 'L',
+        movement: &[Movement::Leap(1, 3)],
+        value: 250,
+        sprites: ["pieces/wl.png", "pieces/bl.png"],
+    };
+
+    static NIGHTRIDER: PieceDef = PieceDef {
+        name: "Nightrider",
+        symbol: 'H',
+        movement: &[Movement::Ride(1, 2)],
+        value: 500,
+        sprites: ["pieces/wh.png", "pieces/bh.png"],
+    };
+
+    #[test]
+    fn registered_pieces_move_as_defined() {
+        register(&CAMEL).unwrap();
+        register(&NIGHTRIDER).unwrap();
+        let board = BoardState::from_fen("4k3/8/8/2p5/3L4/8/8/H3K3 w - - 0 1").unwrap();
+        let targets = |from: &str| {
+            let mut targets = board
+                .legal_moves(board.parse_square(from).unwrap())
+                .into_iter()
+                .map(|square| board.square_name(square))
+                .collect::<Vec<_>>();
+            targets.sort();
+            targets
+        };
+        // the camel's e1 is taken by its own king
+        assert_eq!(targets("d4"), ["a3", "a5", "c1", "c7", "e7", "g3", "g5"]);
+        // the nightrider stops at the pawn it takes on c5
+        assert_eq!(targets("a1"), ["b3", "c2", "c5", "e3", "g4"]);
+        // and five king moves
+        assert_eq!(board.perft(1), 17);
+        assert_eq!(board.to_fen(), "4k3/8/8/2p5/3L4/8/8/H3K3 w - - 0 1");
+    }
+
+    const fn with_symbol(symbol: char) -> PieceDef {
+        PieceDef {
+            name: "Camel",
+            symbol,
+            movement: &[Movement::Leap(1, 3)],
+            value: 250,
+            sprites: ["pieces/wl.png", "pieces/bl.png"],
+        }
+    }
+
+    #[test]
+    fn register_rejects_bad_symbols() {
+        static LOWERCASE: PieceDef = with_symbol('x');
+        static DIGIT: PieceDef = with_symbol('1');
+        static KNIGHT_CLASH: PieceDef = with_symbol('N');
+        static AMAZON_CLASH: PieceDef = with_symbol('Z');
+        for def in [&LOWERCASE, &DIGIT, &KNIGHT_CLASH, &AMAZON_CLASH] {
+            assert!(register(def).is_err(), "{:?}", def.symbol);
+        }
+        assert!(fairy_pieces().iter().all(|def| def.symbol != 'x'));
+    }
+}
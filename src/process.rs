@@ -0,0 +1,133 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Child process talked to line by line, the transport under the UCI and CECP clients.
+pub struct EngineProcess {
+    pub name: String,
+    child: Child,
+    // shared with `Interrupt`s
+    stdin: Arc<Mutex<ChildStdin>>,
+    // stdout is read on its own thread so waiting can time out
+    lines: mpsc::Receiver<String>,
+    // sent on drop, asking the engine to exit
+    quit: &'static str,
+}
+
+impl EngineProcess {
+    pub fn spawn(path: &str, quit: &'static str) -> anyhow::Result<Self> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("unable to start engine {path:?}: {e}"))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            name: path.to_owned(),
+            child,
+            stdin: Arc::new(Mutex::new(stdin)),
+            lines,
+            quit,
+        })
+    }
+
+    pub fn send(&mut self, command: &str) -> anyhow::Result<()> {
+        write_line(&self.name, &self.stdin, command)
+    }
+
+    /// A handle sending `command` from another thread, e.g. while one waits for a search.
+    pub fn interrupt(&self, command: &'static str) -> Interrupt {
+        Interrupt {
+            name: self.name.clone(),
+            stdin: self.stdin.clone(),
+            command,
+        }
+    }
+
+    pub fn read_line(&mut self, timeout: Duration) -> anyhow::Result<String> {
+        let line = match self.lines.recv_timeout(timeout) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(anyhow::anyhow!("{} is not responding", self.name));
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(anyhow::anyhow!("{} exited", self.name));
+            }
+        };
+        log::debug!("{} > {line}", self.name);
+        Ok(line)
+    }
+
+    pub fn exited(&mut self) -> bool {
+        !matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+impl Drop for EngineProcess {
+    fn drop(&mut self) {
+        let _ = self.send(self.quit);
+        let start = Instant::now();
+        while !self.exited() {
+            if start.elapsed() > Duration::from_secs(1) {
+                let _ = self.child.kill();
+                let _ = self.child.wait();
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Sends one command to an engine process without holding the client, to end a search.
+#[derive(Clone)]
+pub struct Interrupt {
+    name: String,
+    stdin: Arc<Mutex<ChildStdin>>,
+    command: &'static str,
+}
+
+impl Interrupt {
+    pub fn send(&self) -> anyhow::Result<()> {
+        write_line(&self.name, &self.stdin, self.command)
+    }
+}
+
+fn write_line(name: &str, stdin: &Mutex<ChildStdin>, command: &str) -> anyhow::Result<()> {
+    log::debug!("{name} < {command}");
+    let mut stdin = stdin.lock().unwrap();
+    writeln!(stdin, "{command}")?;
+    stdin.flush()?;
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+pub mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    /// Writes a shell script engine for the client tests, logging every command it gets to
+    /// the returned path with `.log` appended.
+    pub fn stand_in(name: &str, body: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let log = path.with_extension("log");
+        let script = format!(
+            "#!/bin/sh\nwhile read -r line; do\necho \"$line\" >> {}\n{body}\ndone\n",
+            log.display()
+        );
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let _ = std::fs::remove_file(log);
+        path
+    }
+}
@@ -0,0 +1,91 @@
+use crate::logic::{BoardState, Move, Piece, PieceKind};
+
+impl BoardState {
+    /// Standard algebraic notation of a legal move, e.g. `Nbd7`, `exd5`, `e8=Q+` or `O-O`.
+    pub fn san(&self, mv: Move) -> String {
+        let mut san = self.san_without_check(mv, &self.moves());
+        let mut after = self.clone();
+        after.make(mv);
+        if after.in_check() {
+            san.push(if after.moves().is_empty() { '#' } else { '+' });
+        }
+        san
+    }
+
+    /// Finds the legal move written in algebraic notation, forgiving a missing `x`, `=` or check
+    /// mark and castling written with zeros.
+    pub fn parse_san(&self, text: &str) -> Option<Move> {
+        let text = if text.starts_with("0-0") {
+            normalize(&text.replace('0', "O"))
+        } else {
+            normalize(text)
+        };
+        let moves = self.moves();
+        moves
+            .iter()
+            .copied()
+            .find(|&mv| normalize(&self.san_without_check(mv, &moves)) == text)
+    }
+
+    // checks are the expensive part, as they need the move played; `moves` are all legal moves
+    fn san_without_check(&self, mv: Move, moves: &[Move]) -> String {
+        let Some(piece) = self.pieces[mv.from as usize] else {
+            return self.move_name(mv);
+        };
+        let mut san = String::new();
+
+        if piece.kind == PieceKind::King
+            && self.rank_of(mv.from) == self.rank_of(mv.to)
+            && self.file_of(mv.from).abs_diff(self.file_of(mv.to)) >= 2
+        {
+            san.push_str(if mv.to > mv.from { "O-O" } else { "O-O-O" });
+        } else {
+            let capture = self.is_capture(mv);
+            if piece.kind == PieceKind::Pawn {
+                if capture {
+                    san.push_str(&self.square_name(mv.from)[..1]);
+                }
+            } else {
+                san.push(piece.to_char().to_ascii_uppercase());
+                // name as little of the origin as tells it apart from the same piece elsewhere
+                let others = moves
+                    .iter()
+                    .filter(|other| {
+                        other.to == mv.to
+                            && other.from != mv.from
+                            && self.pieces[other.from as usize] == Some(piece)
+                    })
+                    .collect::<Vec<_>>();
+                let from = self.square_name(mv.from);
+                if !others.is_empty() {
+                    if others
+                        .iter()
+                        .all(|other| self.file_of(other.from) != self.file_of(mv.from))
+                    {
+                        san.push_str(&from[..1]);
+                    } else if others
+                        .iter()
+                        .all(|other| self.rank_of(other.from) != self.rank_of(mv.from))
+                    {
+                        san.push_str(&from[1..]);
+                    } else {
+                        san.push_str(&from);
+                    }
+                }
+            }
+            if capture {
+                san.push('x');
+            }
+            san.push_str(&self.square_name(mv.to));
+            if let Some(kind) = mv.promotion {
+                san.push('=');
+                san.push(Piece { kind, white: true }.to_char());
+            }
+        }
+        san
+    }
+}
+
+fn normalize(san: &str) -> String {
+    san.chars().filter(|c| !"x=+#!?".contains(*c)).collect()
+}
@@ -0,0 +1,828 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::batch_eval::GpuEvaluator;
+use crate::endgame::{Dtm, Endgames};
+use crate::eval::{self, Evaluation};
+use crate::logic::{BoardState, Move, PieceKind, Undo};
+use crate::nnue::{Network, NnueState};
+use crate::syzygy::{Tablebases, Wdl};
+use crate::tt::{Bound, TranspositionTable};
+
+/// Score of delivering mate right now, mates further away score a ply less each.
+pub const MATE: i32 = 30_000;
+pub const INFINITY: i32 = 32_000;
+pub const MAX_PLY: usize = 128;
+/// Score of a tablebase win, below any mate the search finds itself.
+pub const TB_WIN: i32 = MATE - 2 * MAX_PLY as i32;
+
+/// When to stop thinking. Limits that are `None` don't apply.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+impl Limits {
+    pub fn depth(depth: u32) -> Self {
+        Self {
+            depth: Some(depth),
+            ..Default::default()
+        }
+    }
+
+    pub fn time(time: Duration) -> Self {
+        Self {
+            time: Some(time),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    /// Centipawns from the side to play's point of view.
+    pub score: i32,
+    pub depth: u32,
+    pub nodes: u64,
+    pub time: Duration,
+    pub pv: Vec<Move>,
+    /// The best lines with different first moves, best first, as many as `with_multipv` asks
+    /// for. Empty from external engines.
+    pub lines: Vec<Line>,
+}
+
+/// A principal variation with its score, one of several in MultiPV mode.
+#[derive(Clone, Debug, Default)]
+pub struct Line {
+    /// Centipawns from the side to play's point of view.
+    pub score: i32,
+    pub pv: Vec<Move>,
+}
+
+impl Line {
+    pub fn mate_in(&self) -> Option<i32> {
+        mate_in(self.score)
+    }
+}
+
+/// Full moves until mate with `score`, negative when the side to play is getting mated.
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score.abs() > MATE - MAX_PLY as i32 {
+        let plies = MATE - score.abs();
+        Some(score.signum() * (plies + 1) / 2)
+    } else {
+        None
+    }
+}
+
+impl SearchResult {
+    /// Full moves until mate, negative when the side to play is getting mated.
+    pub fn mate_in(&self) -> Option<i32> {
+        mate_in(self.score)
+    }
+
+    /// One line summary in the style of a UCI `info` line.
+    pub fn describe(&self, board: &BoardState) -> String {
+        let score = match self.mate_in() {
+            Some(moves) => format!("mate {moves}"),
+            None => format!("cp {}", self.score),
+        };
+        let pv = self
+            .pv
+            .iter()
+            .map(|&mv| board.move_name(mv))
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!(
+            "depth {} score {} nodes {} time {} pv {}",
+            self.depth,
+            score,
+            self.nodes,
+            self.time.as_millis(),
+            pv
+        )
+    }
+}
+
+/// Negamax alpha-beta with iterative deepening and quiescence search.
+pub struct Searcher {
+    limits: Limits,
+    start: Instant,
+    nodes: u64,
+    stopped: bool,
+    killers: Vec<[Option<Move>; 2]>,
+    // indexed by from * squares + to
+    history: Vec<i32>,
+    squares: usize,
+    tt: Arc<TranspositionTable>,
+    // set from outside to abort, e.g. by UCI `stop`
+    stop: Arc<AtomicBool>,
+    tablebases: Option<Arc<Tablebases>>,
+    endgames: Option<Arc<Endgames>>,
+    // what the tablebases leave to choose from at the root
+    root_moves: Option<Vec<Move>>,
+    evaluator: Option<Arc<GpuEvaluator>>,
+    // stays set if the evaluator fails, so the scores don't change meaning halfway
+    placement_only: bool,
+    // scores the evaluator returned, by hash
+    evaluations: HashMap<u64, i32>,
+    network: Option<Arc<Network>>,
+    // the network's accumulators, kept in step with the board by `make_with` and `unmake_with`
+    nnue: Option<NnueState>,
+    threads: usize,
+    multipv: usize,
+    // root moves of the lines found so far in this iteration, left out of the next line
+    excluded: Vec<Move>,
+}
+
+impl Searcher {
+    pub fn new(limits: Limits) -> Self {
+        Self::with_table(
+            limits,
+            Arc::new(TranspositionTable::new(crate::tt::DEFAULT_MB)),
+        )
+    }
+
+    /// Searches with a table that outlives the search, possibly shared with other searchers.
+    pub fn with_table(limits: Limits, tt: Arc<TranspositionTable>) -> Self {
+        Self {
+            limits,
+            start: Instant::now(),
+            nodes: 0,
+            stopped: false,
+            killers: vec![[None; 2]; MAX_PLY],
+            history: Vec::new(),
+            squares: 0,
+            tt,
+            stop: Arc::new(AtomicBool::new(false)),
+            tablebases: None,
+            endgames: None,
+            root_moves: None,
+            evaluator: None,
+            placement_only: false,
+            evaluations: HashMap::new(),
+            network: None,
+            nnue: None,
+            threads: 1,
+            multipv: 1,
+            excluded: Vec::new(),
+        }
+    }
+
+    /// Probes `tablebases` at the root and after captures and pawn moves.
+    pub fn with_tablebases(mut self, tablebases: Option<Arc<Tablebases>>) -> Self {
+        self.tablebases = tablebases;
+        self
+    }
+
+    /// Looks up positions with few enough pieces in `endgames` instead of searching them.
+    pub fn with_endgames(mut self, endgames: Option<Arc<Endgames>>) -> Self {
+        self.endgames = endgames;
+        self
+    }
+
+    /// Evaluates with `evaluator`, the children of frontier nodes a batch at a time. Only
+    /// material and piece squares count then, also for positions the CPU evaluates.
+    pub fn with_evaluator(mut self, evaluator: Option<Arc<GpuEvaluator>>) -> Self {
+        self.placement_only = evaluator.is_some();
+        self.evaluator = evaluator;
+        self
+    }
+
+    /// Evaluates with `network` wherever it knows the board and pieces.
+    pub fn with_network(mut self, network: Option<Arc<Network>>) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Searches on `threads` threads that share the table (Lazy SMP). The extra threads search
+    /// the same position at staggered depths and only help by filling the table, the result
+    /// comes from the calling thread. With one thread, the default, searches are repeatable.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Finds the best `lines` lines with different first moves instead of just one, each
+    /// iteration searching the root again without the moves of the lines before.
+    pub fn with_multipv(mut self, lines: usize) -> Self {
+        self.multipv = lines.max(1);
+        self
+    }
+
+    /// Flag that ends the search as if a limit was hit once set, from any thread.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn search(&mut self, board: &BoardState) -> SearchResult {
+        self.search_with_report(board, |_| {})
+    }
+
+    /// Like `search`, calling `report` after every finished iteration.
+    pub fn search_with_report(
+        &mut self,
+        board: &BoardState,
+        mut report: impl FnMut(&SearchResult),
+    ) -> SearchResult {
+        self.prepare(board);
+        self.tt.new_search();
+
+        let mut board = board.clone();
+        let mut result = SearchResult::default();
+        self.root_moves = None;
+        if let Some(root_moves) = self
+            .tablebases
+            .as_ref()
+            .and_then(|tablebases| tablebases.root_moves(&board))
+            && let Some(best) = root_moves.first()
+        {
+            // a won or lost position is played out by the tables, a drawn one is searched
+            // among the moves that hold the draw
+            if best.wdl != Wdl::Draw {
+                result = SearchResult {
+                    best_move: Some(best.mv),
+                    score: tablebase_score(best.wdl, 0),
+                    depth: 1,
+                    nodes: 0,
+                    time: self.start.elapsed(),
+                    pv: vec![best.mv],
+                    lines: vec![Line {
+                        score: tablebase_score(best.wdl, 0),
+                        pv: vec![best.mv],
+                    }],
+                };
+                report(&result);
+                return result;
+            }
+            self.root_moves = Some(
+                root_moves
+                    .iter()
+                    .filter(|root| root.wdl == Wdl::Draw)
+                    .map(|root| root.mv)
+                    .collect(),
+            );
+        }
+        let max_depth = self
+            .limits
+            .depth
+            .unwrap_or(MAX_PLY as u32)
+            .clamp(1, MAX_PLY as u32 - 1);
+        let line_count = board
+            .moves()
+            .into_iter()
+            .filter(|mv| {
+                self.root_moves
+                    .as_ref()
+                    .is_none_or(|root| root.contains(mv))
+            })
+            .count()
+            .clamp(1, self.multipv);
+
+        let helpers_stop = Arc::new(AtomicBool::new(false));
+        let helper_nodes = std::thread::scope(|scope| {
+            let helpers = (1..self.threads)
+                .map(|index| {
+                    let mut helper = self.helper(helpers_stop.clone());
+                    let board = board.clone();
+                    scope.spawn(move || helper.help(board, index, max_depth))
+                })
+                .collect::<Vec<_>>();
+
+            for depth in 1..=max_depth {
+                self.excluded.clear();
+                let mut lines = Vec::new();
+                while lines.len() < line_count {
+                    let mut pv = Vec::new();
+                    let score = self.negamax(&mut board, depth, 0, -INFINITY, INFINITY, &mut pv);
+                    if self.stopped && (result.best_move.is_some() || !lines.is_empty()) {
+                        break;
+                    }
+                    self.extend_pv(&mut board, &mut pv);
+                    self.excluded.extend(pv.first().copied());
+                    lines.push(Line { score, pv });
+                }
+                // an unfinished iteration is only trusted when there is nothing better
+                if self.stopped && result.best_move.is_some() {
+                    break;
+                }
+                let Some(best) = lines.first().cloned() else {
+                    break;
+                };
+                result = SearchResult {
+                    best_move: best.pv.first().copied(),
+                    score: best.score,
+                    depth,
+                    nodes: self.nodes,
+                    time: self.start.elapsed(),
+                    pv: best.pv,
+                    lines,
+                };
+                report(&result);
+                if self.stopped || result.mate_in().is_some() {
+                    break;
+                }
+            }
+
+            helpers_stop.store(true, Ordering::Relaxed);
+            helpers
+                .into_iter()
+                .map(|helper| helper.join().unwrap())
+                .sum::<u64>()
+        });
+
+        if result.best_move.is_none() {
+            result.best_move = board.moves().first().copied();
+        }
+        result.nodes = self.nodes + helper_nodes;
+        result.time = self.start.elapsed();
+        result
+    }
+
+    // resets what one search learns about the tree
+    fn prepare(&mut self, board: &BoardState) {
+        self.start = Instant::now();
+        self.nodes = 0;
+        self.stopped = false;
+        self.killers = vec![[None; 2]; MAX_PLY];
+        self.squares = board.pieces.len();
+        self.history = vec![0; self.squares * self.squares];
+        self.nnue = self
+            .network
+            .clone()
+            .filter(|network| network.fits(board))
+            .map(|network| NnueState::new(network, board));
+    }
+
+    // a searcher for another thread, sharing the table and stopped by `stop`
+    fn helper(&self, stop: Arc<AtomicBool>) -> Searcher {
+        let mut helper = Searcher::with_table(Limits::default(), self.tt.clone())
+            .with_tablebases(self.tablebases.clone())
+            .with_endgames(self.endgames.clone())
+            .with_network(self.network.clone())
+            .with_evaluator(self.evaluator.clone());
+        helper.stop = stop;
+        helper.placement_only = self.placement_only;
+        helper.root_moves = self.root_moves.clone();
+        helper
+    }
+
+    /// Iterative deepening for the table's sake, returning the nodes searched. Every other
+    /// helper starts a ply deeper so the threads don't all search the same depth at once.
+    fn help(&mut self, mut board: BoardState, index: usize, max_depth: u32) -> u64 {
+        self.prepare(&board);
+        for depth in (1 + index as u32 % 2)..=max_depth {
+            let mut pv = Vec::new();
+            self.negamax(&mut board, depth, 0, -INFINITY, INFINITY, &mut pv);
+            if self.stopped {
+                break;
+            }
+        }
+        self.nodes
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if !self.stopped
+            && (self.limits.nodes.is_some_and(|nodes| self.nodes >= nodes)
+                || (self.nodes.is_multiple_of(1024)
+                    && (self.stop.load(Ordering::Relaxed)
+                        || self
+                            .limits
+                            .time
+                            .is_some_and(|time| self.start.elapsed() >= time))))
+        {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    fn negamax(
+        &mut self,
+        board: &mut BoardState,
+        depth: u32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        pv.clear();
+        if self.should_stop() {
+            return 0;
+        }
+        if board.fog_of_war && board.king_square(board.white_to_play).is_none() {
+            return -MATE + ply as i32;
+        }
+        if ply > 0 && board.halfmove_clock >= 100 {
+            return 0;
+        }
+
+        let hash = board.hash();
+        let entry = self.tt.probe(hash);
+        if let Some(entry) = entry
+            && ply > 0
+            && entry.depth >= depth
+        {
+            let score = score_from_tt(entry.score, ply);
+            match entry.bound {
+                Bound::Exact => return score,
+                Bound::Lower if score >= beta => return score,
+                Bound::Upper if score <= alpha => return score,
+                _ => {}
+            }
+        }
+
+        if ply > 0
+            && let Some(dtm) = self
+                .endgames
+                .as_ref()
+                .and_then(|endgames| endgames.probe(board))
+        {
+            let score = match dtm {
+                Dtm::Win(moves) => MATE - ply as i32 - (2 * moves as i32 - 1),
+                Dtm::Draw => 0,
+                Dtm::Loss(moves) => -MATE + ply as i32 + 2 * moves as i32,
+            };
+            self.tt
+                .store(hash, depth, score_to_tt(score, ply), Bound::Exact, None);
+            return score;
+        }
+
+        // after a capture or pawn move the tables know the outcome for certain
+        if ply > 0
+            && board.halfmove_clock == 0
+            && let Some(wdl) = self
+                .tablebases
+                .as_ref()
+                .and_then(|tablebases| tablebases.probe_wdl(board))
+        {
+            let score = tablebase_score(wdl, ply);
+            self.tt
+                .store(hash, depth, score_to_tt(score, ply), Bound::Exact, None);
+            return score;
+        }
+
+        let in_check = board.in_check();
+        // look one ply further when in check so mates are not cut off by the horizon
+        let depth = if in_check { depth + 1 } else { depth };
+        if depth == 0 || ply >= MAX_PLY - 1 {
+            return self.quiescence(board, ply, alpha, beta);
+        }
+        self.nodes += 1;
+
+        let mut moves = board.moves();
+        if moves.is_empty() {
+            return if in_check { -MATE + ply as i32 } else { 0 };
+        }
+        if ply == 0 {
+            if let Some(root_moves) = &self.root_moves {
+                moves.retain(|mv| root_moves.contains(mv));
+            }
+            moves.retain(|mv| !self.excluded.contains(mv));
+        }
+        self.order_moves(
+            board,
+            &mut moves,
+            ply,
+            entry.and_then(|entry| entry.best_move),
+        );
+        if depth == 1 {
+            self.evaluate_children(board, &moves);
+        }
+
+        let original_alpha = alpha;
+        let mut best = -INFINITY;
+        let mut child_pv = Vec::new();
+        for mv in moves {
+            let capture = board.is_capture(mv);
+            let undo = self.make(board, mv);
+            let score = -self.negamax(board, depth - 1, ply + 1, -beta, -alpha, &mut child_pv);
+            self.unmake(board, undo);
+            if self.stopped {
+                return best.max(alpha);
+            }
+
+            best = best.max(score);
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(mv);
+                pv.extend_from_slice(&child_pv);
+
+                if score >= beta {
+                    if !capture {
+                        let killers = &mut self.killers[ply];
+                        if killers[0] != Some(mv) {
+                            killers[1] = killers[0];
+                            killers[0] = Some(mv);
+                        }
+                        let entry =
+                            &mut self.history[mv.from as usize * self.squares + mv.to as usize];
+                        *entry = (*entry + (depth * depth) as i32).min(HISTORY_MAX);
+                    }
+                    break;
+                }
+            }
+        }
+
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(
+            hash,
+            depth,
+            score_to_tt(best, ply),
+            bound,
+            pv.first().copied(),
+        );
+        best
+    }
+
+    // lines cut short by table hits are continued with the moves the table remembers
+    fn extend_pv(&self, board: &mut BoardState, pv: &mut Vec<Move>) {
+        let mut undos = pv.iter().map(|&mv| board.make(mv)).collect::<Vec<_>>();
+        let mut seen = vec![board.hash()];
+        while pv.len() < MAX_PLY {
+            let Some(mv) = self
+                .tt
+                .probe(board.hash())
+                .and_then(|entry| entry.best_move)
+            else {
+                break;
+            };
+            if !board.moves().contains(&mv) {
+                break;
+            }
+            pv.push(mv);
+            undos.push(board.make(mv));
+            // the table would happily walk around a repetition forever
+            if seen.contains(&board.hash()) {
+                break;
+            }
+            seen.push(board.hash());
+        }
+        for undo in undos.into_iter().rev() {
+            board.unmake(undo);
+        }
+    }
+
+    fn quiescence(&mut self, board: &mut BoardState, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        if self.should_stop() {
+            return 0;
+        }
+        self.nodes += 1;
+        if board.fog_of_war && board.king_square(board.white_to_play).is_none() {
+            return -MATE + ply as i32;
+        }
+
+        let stand_pat = self.evaluate(board);
+        if stand_pat >= beta || ply >= MAX_PLY - 1 {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut moves = board
+            .moves()
+            .into_iter()
+            .filter(|&mv| board.is_capture(mv) || mv.promotion.is_some())
+            .collect::<Vec<_>>();
+        self.order_moves(board, &mut moves, ply, None);
+
+        for mv in moves {
+            let undo = self.make(board, mv);
+            let score = -self.quiescence(board, ply + 1, -beta, -alpha);
+            self.unmake(board, undo);
+            if self.stopped {
+                return alpha;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+
+    fn make(&mut self, board: &mut BoardState, mv: Move) -> Undo {
+        board.make_with(mv, self.nnue.as_mut())
+    }
+
+    fn unmake(&mut self, board: &mut BoardState, undo: Undo) {
+        board.unmake_with(undo, self.nnue.as_mut());
+    }
+
+    /// Sends the positions after `moves` to the GPU in one batch, they are all about to be
+    /// evaluated by the quiescence search.
+    fn evaluate_children(&mut self, board: &mut BoardState, moves: &[Move]) {
+        let Some(evaluator) = &self.evaluator else {
+            return;
+        };
+        let mut children = Vec::with_capacity(moves.len());
+        for &mv in moves {
+            let undo = board.make(mv);
+            children.push(board.clone());
+            board.unmake(undo);
+        }
+        match evaluator.evaluate(&children) {
+            Ok(scores) => {
+                if self.evaluations.len() > EVALUATIONS_MAX {
+                    self.evaluations.clear();
+                }
+                self.evaluations
+                    .extend(children.iter().map(BoardState::hash).zip(scores));
+            }
+            Err(e) => {
+                // the CPU computes the same terms
+                log::warn!("GPU evaluation failed, continuing on the CPU: {e}");
+                self.evaluator = None;
+            }
+        }
+    }
+
+    /// Static evaluation from the side to play's point of view.
+    fn evaluate(&self, board: &BoardState) -> i32 {
+        if let Some(score) = self
+            .nnue
+            .as_ref()
+            .and_then(|nnue| nnue.evaluate(board.white_to_play))
+        {
+            // a network's wild guess is still no tablebase win
+            return score.clamp(-TB_WIN + 1, TB_WIN - 1);
+        }
+        let score = if self.placement_only {
+            match self.evaluations.get(&board.hash()) {
+                Some(&score) => score,
+                None => Evaluation::placement(board).total(),
+            }
+        } else {
+            eval::evaluate(board)
+        };
+        if board.white_to_play { score } else { -score }
+    }
+
+    fn order_moves(&self, board: &BoardState, moves: &mut [Move], ply: usize, best: Option<Move>) {
+        moves.sort_by_cached_key(|&mv| -self.move_score(board, mv, ply, best));
+    }
+
+    fn move_score(&self, board: &BoardState, mv: Move, ply: usize, best: Option<Move>) -> i32 {
+        if Some(mv) == best {
+            return 1_000_000;
+        }
+        if board.is_capture(mv) {
+            // most valuable victim first, cheapest attacker breaking ties
+            let victim = board.pieces[mv.to as usize].map_or(PieceKind::Pawn, |piece| piece.kind);
+            let attacker =
+                board.pieces[mv.from as usize].map_or(PieceKind::Pawn, |piece| piece.kind);
+            return 100_000 + eval::piece_value(&victim).mg * 10 - eval::piece_value(&attacker).mg;
+        }
+        if let Some(kind) = mv.promotion {
+            return 90_000 + eval::piece_value(&kind).mg;
+        }
+        if self.killers[ply][0] == Some(mv) {
+            return 80_000;
+        }
+        if self.killers[ply][1] == Some(mv) {
+            return 79_000;
+        }
+        self.history[mv.from as usize * self.squares + mv.to as usize]
+    }
+}
+
+// cursed wins and blessed losses are draws, but a hair better or worse than a real one
+fn tablebase_score(wdl: Wdl, ply: usize) -> i32 {
+    match wdl {
+        Wdl::Win => TB_WIN - ply as i32,
+        Wdl::CursedWin => 1,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -1,
+        Wdl::Loss => -TB_WIN + ply as i32,
+    }
+}
+
+// mate and tablebase scores are stored relative to the position, not the root
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score > TB_WIN - MAX_PLY as i32 {
+        score + ply as i32
+    } else if score < -TB_WIN + MAX_PLY as i32 {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score > TB_WIN - MAX_PLY as i32 {
+        score - ply as i32
+    } else if score < -TB_WIN + MAX_PLY as i32 {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+// keeps quiet moves below the killers
+const HISTORY_MAX: i32 = 70_000;
+
+// scores cached from the GPU evaluator before they are dropped
+const EVALUATIONS_MAX: usize = 1 << 16;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::STARTING_FEN;
+
+    fn board(fen: &str) -> BoardState {
+        BoardState::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let board = board("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1");
+        let result = Searcher::new(Limits::depth(3)).search(&board);
+        assert_eq!(result.best_move, board.parse_san("Rd8#"));
+        assert_eq!(result.mate_in(), Some(1));
+    }
+
+    #[test]
+    fn finds_mate_in_two() {
+        // Morphy: 1. Ra6 bxa6 2. b7#
+        let board = board("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1");
+        let result = Searcher::new(Limits::depth(5)).search(&board);
+        assert_eq!(result.best_move, board.parse_san("Ra6"));
+        assert_eq!(result.mate_in(), Some(2));
+    }
+
+    #[test]
+    fn avoids_stalemate() {
+        // Qb6 and Qc7 stalemate the king, the win has to go around them
+        let board = board("k7/8/2Q5/8/8/8/8/6K1 w - - 0 1");
+        let result = Searcher::new(Limits::depth(5)).search(&board);
+        let mut after = board.clone();
+        after.make(result.best_move.unwrap());
+        assert!(!after.moves().is_empty() || after.in_check());
+        assert!(result.score > 500);
+    }
+
+    #[test]
+    fn limited_searches_return_a_legal_move_and_its_line() {
+        let board = board(STARTING_FEN);
+        let nodes = Limits {
+            nodes: Some(2000),
+            ..Default::default()
+        };
+        for limits in [Limits::depth(4), nodes] {
+            let result = Searcher::new(limits).search(&board);
+            let best = result.best_move.unwrap();
+            assert!(board.moves().contains(&best));
+            assert_eq!(result.pv.first(), Some(&best));
+            let mut line = board.clone();
+            for &mv in &result.pv {
+                assert!(line.moves().contains(&mv));
+                line.make(mv);
+            }
+        }
+    }
+
+    #[test]
+    fn one_thread_searches_repeat_exactly() {
+        let board = board("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let search = || {
+            Searcher::new(Limits::depth(4))
+                .with_threads(1)
+                .search(&board)
+        };
+        let (first, second) = (search(), search());
+        assert!(first.best_move.is_some());
+        assert_eq!(first.best_move, second.best_move);
+        assert_eq!(first.pv, second.pv);
+        assert_eq!(first.score, second.score);
+        assert_eq!(first.nodes, second.nodes);
+    }
+
+    #[test]
+    fn threads_share_the_table_and_return_a_legal_move() {
+        let board = board(STARTING_FEN);
+        let tt = Arc::new(TranspositionTable::new(1));
+        let mut searcher = Searcher::with_table(Limits::depth(4), tt.clone()).with_threads(3);
+        let result = searcher.search(&board);
+        let best = result.best_move.unwrap();
+        assert!(board.moves().contains(&best));
+        assert_eq!(result.pv.first(), Some(&best));
+
+        // what a helper finds ends up in the table the caller handed in
+        let mut helper = searcher.helper(Arc::new(AtomicBool::new(false)));
+        assert!(Arc::ptr_eq(&helper.tt, &tt));
+        tt.clear();
+        assert!(tt.probe(board.hash()).is_none());
+        assert!(helper.help(board.clone(), 1, 3) > 0);
+        assert!(tt.probe(board.hash()).is_some());
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use crate::logic::{BoardState, Move};
+
+/// What a problem asks for, written as in problem books: `#2`, `h#3` or `s#2`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stipulation {
+    /// The side to play mates in `n` moves against any defence.
+    Mate(u32),
+    /// The side to play starts and helps the other side mate it on its `n`th move.
+    Helpmate(u32),
+    /// The side to play forces the other side to mate it in `n` moves.
+    Selfmate(u32),
+}
+
+impl std::str::FromStr for Stipulation {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::anyhow!("{text:?} is not a stipulation like #2, h#3 or s#2");
+        let (kind, moves) = text.split_once('#').ok_or_else(invalid)?;
+        let moves = moves.parse::<u32>().map_err(|_| invalid())?;
+        if moves == 0 {
+            return Err(invalid());
+        }
+        match kind {
+            "" => Ok(Stipulation::Mate(moves)),
+            "h" => Ok(Stipulation::Helpmate(moves)),
+            "s" => Ok(Stipulation::Selfmate(moves)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A move of a solution and what may follow it: all defences after the side to play's moves,
+/// the quickest continuations after a defence.
+#[derive(Clone, Debug)]
+pub struct Line {
+    pub mv: Move,
+    pub replies: Vec<Line>,
+}
+
+/// Every solution of the problem on `board`, empty when there is none. More than one
+/// first move (or helpmate line) means the problem is cooked.
+pub fn solve(board: &BoardState, stipulation: Stipulation) -> Vec<Line> {
+    let mut solver = Solver {
+        stipulation,
+        known: HashMap::new(),
+    };
+    let mut board = board.clone();
+    match stipulation {
+        Stipulation::Mate(n) | Stipulation::Selfmate(n) => solver.keys(&mut board, n),
+        Stipulation::Helpmate(n) => solver.helpmates(&mut board, 2 * n),
+    }
+}
+
+struct Solver {
+    stipulation: Stipulation,
+    // whether the side to play reaches the goal with that many moves (plies for helpmates)
+    // left, so transpositions are only solved once
+    known: HashMap<(u64, u32), bool>,
+}
+
+fn is_mate(board: &BoardState) -> bool {
+    board.in_check() && board.moves().is_empty()
+}
+
+impl Solver {
+    /// Whether the side to play, the attacker, reaches the goal within `n` moves.
+    fn attack(&mut self, board: &mut BoardState, n: u32) -> bool {
+        let key = (board.hash(), n);
+        if let Some(&known) = self.known.get(&key) {
+            return known;
+        }
+        let solved = board.moves().into_iter().any(|mv| {
+            let undo = board.make(mv);
+            let solved = self.defend(board, n);
+            board.unmake(undo);
+            solved
+        });
+        self.known.insert(key, solved);
+        solved
+    }
+
+    /// Whether the attacker, having just played the first of its `n` moves, gets there
+    /// whatever the defender does.
+    fn defend(&mut self, board: &mut BoardState, n: u32) -> bool {
+        let moves = board.moves();
+        match self.stipulation {
+            Stipulation::Mate(_) => {
+                if moves.is_empty() {
+                    return board.in_check();
+                }
+                n > 1
+                    && moves.into_iter().all(|mv| {
+                        let undo = board.make(mv);
+                        let solved = self.attack(board, n - 1);
+                        board.unmake(undo);
+                        solved
+                    })
+            }
+            // the defender has to be left nothing but mating moves in the end
+            _ => {
+                !moves.is_empty()
+                    && moves.into_iter().all(|mv| {
+                        let undo = board.make(mv);
+                        let solved = is_mate(board) || (n > 1 && self.attack(board, n - 1));
+                        board.unmake(undo);
+                        solved
+                    })
+            }
+        }
+    }
+
+    // the attacker's moves that get there within `n` moves, with all defences to each
+    fn keys(&mut self, board: &mut BoardState, n: u32) -> Vec<Line> {
+        let mut keys = Vec::new();
+        for mv in board.moves() {
+            let undo = board.make(mv);
+            if self.defend(board, n) {
+                keys.push(Line {
+                    mv,
+                    replies: self.defences(board, n),
+                });
+            }
+            board.unmake(undo);
+        }
+        keys
+    }
+
+    fn defences(&mut self, board: &mut BoardState, n: u32) -> Vec<Line> {
+        let mut defences = Vec::new();
+        for mv in board.moves() {
+            let undo = board.make(mv);
+            // a selfmate ends with the defender's mate, otherwise the shortest way on is shown
+            let replies = if is_mate(board) {
+                Vec::new()
+            } else {
+                (1..n)
+                    .find(|&k| self.attack(board, k))
+                    .map_or_else(Vec::new, |k| self.keys(board, k))
+            };
+            board.unmake(undo);
+            defences.push(Line { mv, replies });
+        }
+        defences
+    }
+
+    // every sequence of `plies` moves ending with the side to play mated
+    fn helpmates(&mut self, board: &mut BoardState, plies: u32) -> Vec<Line> {
+        let key = (board.hash(), plies);
+        if self.known.get(&key) == Some(&false) {
+            return Vec::new();
+        }
+        let mut lines = Vec::new();
+        for mv in board.moves() {
+            let undo = board.make(mv);
+            if plies == 1 {
+                if is_mate(board) {
+                    lines.push(Line {
+                        mv,
+                        replies: Vec::new(),
+                    });
+                }
+            } else {
+                let replies = self.helpmates(board, plies - 1);
+                if !replies.is_empty() {
+                    lines.push(Line { mv, replies });
+                }
+            }
+            board.unmake(undo);
+        }
+        self.known.insert(key, !lines.is_empty());
+        lines
+    }
+}
+
+// One move per line, indented by ply: `1.Qg4!`, then `  1...Kf6` and `    2.Qf5#`. Moves are
+// numbered from the problem's start.
+fn write_tree(board: &mut BoardState, lines: &[Line], ply: usize, out: &mut String) {
+    for line in lines {
+        let number = ply / 2 + 1;
+        let dots = if ply.is_multiple_of(2) { "." } else { "..." };
+        let key = if ply == 0 { "!" } else { "" };
+        let san = board.san(line.mv);
+        out.push_str(&format!("{}{number}{dots}{san}{key}\n", "  ".repeat(ply)));
+        let undo = board.make(line.mv);
+        write_tree(board, &line.replies, ply + 1, out);
+        board.unmake(undo);
+    }
+}
+
+// helpmates read best as one line each: `1.Kd4 Qe5 2.Ke3 Qd4#`
+fn write_lines(board: &mut BoardState, lines: &[Line], ply: usize, prefix: &str, out: &mut String) {
+    for line in lines {
+        let san = board.san(line.mv);
+        let text = match ply {
+            0 => format!("1.{san}"),
+            _ if ply.is_multiple_of(2) => format!("{prefix} {}.{san}", ply / 2 + 1),
+            _ => format!("{prefix} {san}"),
+        };
+        if line.replies.is_empty() {
+            out.push_str(&text);
+            out.push('\n');
+        }
+        let undo = board.make(line.mv);
+        write_lines(board, &line.replies, ply + 1, &text, out);
+        board.unmake(undo);
+    }
+}
+
+/// `solve <stipulation> <fen>`: prints every solution of a problem, e.g. `solve #2 <fen>`.
+pub fn solve_command(args: Vec<String>) -> anyhow::Result<()> {
+    let usage = || anyhow::anyhow!("usage: solve <#n|h#n|s#n> <fen>");
+    let (stipulation, fen) = args.split_first().ok_or_else(usage)?;
+    let stipulation = stipulation.parse::<Stipulation>()?;
+    let mut fen = fen.to_vec();
+    if fen.is_empty() {
+        return Err(usage());
+    }
+    // problem databases usually leave out the move counters
+    if fen.len() == 4 {
+        fen.extend(["0".to_owned(), "1".to_owned()]);
+    }
+    let mut board = BoardState::from_fen(&fen.join(" "))?;
+
+    let start = std::time::Instant::now();
+    let solutions = solve(&board, stipulation);
+    let mut out = String::new();
+    match stipulation {
+        Stipulation::Helpmate(_) => write_lines(&mut board, &solutions, 0, "", &mut out),
+        _ => write_tree(&mut board, &solutions, 0, &mut out),
+    }
+    print!("{out}");
+    let count = match stipulation {
+        Stipulation::Helpmate(_) => out.lines().count(),
+        _ => solutions.len(),
+    };
+    match count {
+        0 => println!("no solution"),
+        1 => println!("1 solution"),
+        n => println!("{n} solutions, the problem is cooked"),
+    }
+    println!("solved in {:.2?}", start.elapsed());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solved(fen: &str, stipulation: &str) -> String {
+        let mut board = BoardState::from_fen(fen).unwrap();
+        let stipulation = stipulation.parse().unwrap();
+        let solutions = solve(&board, stipulation);
+        let mut out = String::new();
+        match stipulation {
+            Stipulation::Helpmate(_) => write_lines(&mut board, &solutions, 0, "", &mut out),
+            _ => write_tree(&mut board, &solutions, 0, &mut out),
+        }
+        out
+    }
+
+    #[test]
+    fn parses_stipulations() {
+        assert_eq!("#2".parse::<Stipulation>().unwrap(), Stipulation::Mate(2));
+        assert_eq!(
+            "h#3".parse::<Stipulation>().unwrap(),
+            Stipulation::Helpmate(3)
+        );
+        assert_eq!(
+            "s#1".parse::<Stipulation>().unwrap(),
+            Stipulation::Selfmate(1)
+        );
+        for invalid in ["#0", "2", "x#2", "#", "h#two"] {
+            assert!(invalid.parse::<Stipulation>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn solves_a_mate_in_two() {
+        // Morphy's problem: the key gives up the rook
+        let tree = solved("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1", "#2");
+        let mut expected = "1.Ra6!\n  1...bxa6\n    2.b7#\n".to_owned();
+        for bishop in ["Bc7", "Bd6", "Be5", "Bf4", "Bg3", "Bh2"] {
+            expected.push_str(&format!("  1...{bishop}\n    2.Rxa7#\n"));
+        }
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn finds_every_key_of_a_cooked_problem() {
+        let tree = solved("6k1/5ppp/8/8/8/8/5PPP/R2R2K1 w - - 0 1", "#2");
+        assert_eq!(tree, "1.Ra8#!\n1.Rd8#!\n");
+    }
+
+    #[test]
+    fn solves_a_helpmate_in_two() {
+        // the bishop makes way for the knight and the rook smothers its own king
+        let lines = solved("5rbk/6pp/8/8/8/7N/8/K7 b - - 0 1", "h#2");
+        assert_eq!(lines, "1.Bf7 Ng5 2.Rg8 Nxf7#\n");
+    }
+
+    #[test]
+    fn solves_a_selfmate_in_two() {
+        let tree = solved("3Rq3/6Q1/8/7k/1B6/3N4/6PP/7K w - - 0 1", "s#2");
+        assert_eq!(tree, "1.Nf4+!\n  1...Kh4\n    2.Be1+\n      2...Qxe1#\n");
+    }
+
+    #[test]
+    fn refuted_problems_have_no_solution() {
+        // the rook is a file too far from a6
+        let board = BoardState::from_fen("kbK5/pp6/1P6/8/8/8/8/1R6 w - - 0 1").unwrap();
+        assert!(solve(&board, Stipulation::Mate(2)).is_empty());
+        let board = BoardState::from_fen("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1").unwrap();
+        assert!(solve(&board, Stipulation::Mate(1)).is_empty());
+        let board = BoardState::from_fen("3Rq3/6Q1/8/7k/1B6/3N4/6PP/7K w - - 0 1").unwrap();
+        assert!(solve(&board, Stipulation::Selfmate(1)).is_empty());
+    }
+}
@@ -0,0 +1,1108 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::logic::{BoardState, Move, Piece, PieceKind};
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// flags in front of every sub-table
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+/// Outcome with perfect play for the side to move. Cursed wins and blessed losses are wins and
+/// losses the fifty move rule turns into draws.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Self {
+        match value {
+            ..=-2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+}
+
+/// A legal move at the root with what the tables say about it, `dtz` counting plies from the
+/// root.
+#[derive(Clone, Copy, Debug)]
+pub struct RootMove {
+    pub mv: Move,
+    pub wdl: Wdl,
+    pub dtz: i32,
+}
+
+/// Syzygy WDL and DTZ tables in one or more directories, each file read on first use.
+pub struct Tablebases {
+    dirs: Vec<PathBuf>,
+    max_pieces: u32,
+    // by material like `KRvK`, `None` once a table turned out missing or broken
+    wdl: Mutex<HashMap<String, Option<Arc<Table>>>>,
+    dtz: Mutex<HashMap<String, Option<Arc<Table>>>>,
+}
+
+impl Tablebases {
+    /// Finds the tables in `paths`, directories separated by `:` (`;` on Windows) as in the
+    /// UCI `SyzygyPath` option.
+    pub fn open(paths: &str) -> anyhow::Result<Self> {
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        let dirs = paths
+            .split(separator)
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let mut max_pieces = 0;
+        for dir in &dirs {
+            let entries = std::fs::read_dir(dir)
+                .map_err(|e| anyhow::anyhow!("unable to read tablebase directory {dir:?}: {e}"))?;
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if let Some(material) = name.strip_suffix(".rtbw") {
+                    let pieces = material.chars().filter(|&c| c != 'v').count() as u32;
+                    max_pieces = max_pieces.max(pieces);
+                }
+            }
+        }
+        if max_pieces == 0 {
+            return Err(anyhow::anyhow!("no Syzygy tables found in {paths:?}"));
+        }
+        Ok(Self {
+            dirs,
+            max_pieces,
+            wdl: Mutex::new(HashMap::new()),
+            dtz: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Pieces, kings included, of the largest tables found.
+    pub fn max_pieces(&self) -> u32 {
+        self.max_pieces
+    }
+
+    /// Whether `board` is small enough and of a kind the tables describe: standard pieces on
+    /// an 8x8 board without castling rights.
+    pub fn covers(&self, board: &BoardState) -> bool {
+        if board.width != 8
+            || board.height != 8
+            || board.fog_of_war
+            || board.white_can_oo
+            || board.white_can_ooo
+            || board.black_can_oo
+            || board.black_can_ooo
+        {
+            return false;
+        }
+        let mut count = 0;
+        for piece in board.pieces.iter().flatten() {
+            if matches!(piece.kind, PieceKind::Fairy(_)) {
+                return false;
+            }
+            count += 1;
+        }
+        count <= self.max_pieces
+    }
+
+    /// Win, draw or loss for the side to move, `None` when the needed tables are missing.
+    pub fn probe_wdl(&self, board: &BoardState) -> Option<Wdl> {
+        if !self.covers(board) {
+            return None;
+        }
+        let (wdl, _) = self.search(&mut board.clone(), false)?;
+        Some(Wdl::from_value(wdl))
+    }
+
+    /// Plies to the next capture or pawn move that keeps the result, negative when losing and
+    /// 0 for draws. A DTZ past 100 means the fifty move rule gets there first.
+    pub fn probe_dtz(&self, board: &BoardState) -> Option<i32> {
+        if !self.covers(board) {
+            return None;
+        }
+        self.dtz(&mut board.clone())
+    }
+
+    /// The legal moves of `board`, best first: wins fastest, losses slowest.
+    pub fn root_moves(&self, board: &BoardState) -> Option<Vec<RootMove>> {
+        if !self.covers(board) {
+            return None;
+        }
+        let mut board = board.clone();
+        let halfmove_clock = board.halfmove_clock as i32;
+        let mut moves = Vec::new();
+        for mv in board.moves() {
+            let undo = board.make(mv);
+            let dtz = if board.halfmove_clock == 0 {
+                self.search(&mut board, false)
+                    .map(|(wdl, _)| dtz_before_zeroing(-wdl))
+            } else {
+                // one ply further from the root than from the position after the move
+                self.dtz(&mut board).map(|dtz| -dtz - dtz.signum())
+            };
+            let mated = board.in_check() && board.moves().is_empty();
+            board.unmake(undo);
+            let dtz = match dtz? {
+                2 if mated => 1,
+                dtz => dtz,
+            };
+            let wdl = match dtz {
+                0 => Wdl::Draw,
+                _ if dtz.abs() + halfmove_clock > 100 => Wdl::from_value(dtz.signum()),
+                _ => Wdl::from_value(2 * dtz.signum()),
+            };
+            moves.push(RootMove { mv, wdl, dtz });
+        }
+        // a smaller DTZ is better when winning, a larger one when losing
+        moves.sort_by_key(|root| std::cmp::Reverse((root.wdl, -root.dtz)));
+        Some(moves)
+    }
+
+    fn table(&self, name: &str, dtz: bool) -> Option<Arc<Table>> {
+        let tables = if dtz { &self.dtz } else { &self.wdl };
+        let mut tables = tables.lock().unwrap();
+        tables
+            .entry(name.to_owned())
+            .or_insert_with(|| {
+                let extension = if dtz { "rtbz" } else { "rtbw" };
+                let path = self
+                    .dirs
+                    .iter()
+                    .map(|dir| dir.join(format!("{name}.{extension}")))
+                    .find(|path| path.exists())?;
+                match Table::load(&path, name, dtz) {
+                    Ok(table) => Some(Arc::new(table)),
+                    Err(e) => {
+                        log::error!("{}: {e}", path.display());
+                        None
+                    }
+                }
+            })
+            .clone()
+    }
+
+    // `Some(None)` when a DTZ table only has the other side to move
+    fn probe_table(&self, board: &BoardState, dtz: bool, wdl: i32) -> Option<Option<i32>> {
+        let name = material(board, true);
+        if name == "KvK" {
+            return Some(Some(0));
+        }
+        // tables are named with the stronger side first and meant for it being white
+        let (table, black_stronger) = match self.table(&name, dtz) {
+            Some(table) => (table, false),
+            None => (self.table(&material(board, false), dtz)?, true),
+        };
+        Some(table.probe(board, black_stronger, wdl))
+    }
+
+    // Captures may be stored as "don't care" values to help compression, so they are
+    // searched as well and the better of both is the result. With `zeroing`, pawn moves are
+    // too, as DTZ tables can't be trusted on them. The flag tells if a zeroing move is best.
+    fn search(&self, board: &mut BoardState, zeroing: bool) -> Option<(i32, bool)> {
+        let moves = board.moves();
+        let mut best = -2;
+        let mut searched = 0;
+        for &mv in &moves {
+            if !(board.is_capture(mv) || (zeroing && is_pawn_move(board, mv))) {
+                continue;
+            }
+            searched += 1;
+            let undo = board.make(mv);
+            let value = self.search(board, false);
+            board.unmake(undo);
+            let value = -value?.0;
+            if value > best {
+                best = value;
+                if value >= 2 {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        // the table can be wrong where everything was searched, e.g. with en passant
+        let no_more_moves = searched > 0 && searched == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            self.probe_table(board, false, 0)??
+        };
+        if best >= value {
+            Some((best, best > 0 || no_more_moves))
+        } else {
+            Some((value, false))
+        }
+    }
+
+    fn dtz(&self, board: &mut BoardState) -> Option<i32> {
+        let (wdl, zeroing_best) = self.search(board, true)?;
+        if wdl == 0 {
+            return Some(0);
+        }
+        if zeroing_best {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        if let Some(dtz) = self.probe_table(board, true, wdl)? {
+            let cursed = wdl.abs() == 1;
+            return Some((dtz + if cursed { 100 } else { 0 }) * wdl.signum());
+        }
+
+        // the table is for the other side to move, so take the best of the moves from here
+        let mut min_dtz = i32::MAX;
+        for mv in board.moves() {
+            let zeroing = board.is_capture(mv) || is_pawn_move(board, mv);
+            let undo = board.make(mv);
+            let dtz = if zeroing {
+                self.search(board, false)
+                    .map(|(wdl, _)| -dtz_before_zeroing(wdl))
+            } else {
+                self.dtz(board).map(|dtz| -dtz)
+            };
+            let mates = board.in_check() && board.moves().is_empty();
+            board.unmake(undo);
+            let mut dtz = dtz?;
+            if dtz == 1 && mates {
+                min_dtz = 1;
+            }
+            // zeroing moves already count from before the move
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+    }
+}
+
+// what a DTZ table would hold right before a capture or pawn move with this outcome
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0,
+    }
+}
+
+fn is_pawn_move(board: &BoardState, mv: Move) -> bool {
+    matches!(board.pieces[mv.from as usize], Some(piece) if piece.kind == PieceKind::Pawn)
+}
+
+// `KRPvKR`, one side before the other, strongest piece first
+fn material(board: &BoardState, white_first: bool) -> String {
+    let side = |white: bool| {
+        let mut side = String::new();
+        for kind in [
+            PieceKind::King,
+            PieceKind::Queen,
+            PieceKind::Rook,
+            PieceKind::Bishop,
+            PieceKind::Knight,
+            PieceKind::Pawn,
+        ] {
+            let piece = Piece { kind, white };
+            for _ in board.pieces.iter().filter(|&&p| p == Some(piece)) {
+                side.push(piece.to_char().to_ascii_uppercase());
+            }
+        }
+        side
+    };
+    format!("{}v{}", side(white_first), side(!white_first))
+}
+
+// the numbering the tables use: pawn to king as 1 to 6, plus 8 for black
+fn piece_code(piece: Piece) -> u8 {
+    let kind = match piece.kind {
+        PieceKind::Pawn => 1,
+        PieceKind::Knight => 2,
+        PieceKind::Bishop => 3,
+        PieceKind::Rook => 4,
+        PieceKind::Queen => 5,
+        _ => 6,
+    };
+    if piece.white { kind } else { kind + 8 }
+}
+
+/// One `.rtbw` or `.rtbz` file.
+struct Table {
+    data: Vec<u8>,
+    dtz: bool,
+    // both colours have the same pieces, only white to move is stored then
+    symmetric: bool,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    // leading colour first, which is the one with fewer pawns
+    pawn_count: [usize; 2],
+    // [side to move][file of the leading pawn]
+    items: Vec<Vec<PairsData>>,
+    // start of the DTZ value maps
+    map: usize,
+}
+
+/// Index encoding and compressed values of one side to move and leading pawn file.
+#[derive(Clone, Debug, Default)]
+struct PairsData {
+    flags: u8,
+    min_sym_len: u8,
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    block_length_size: usize,
+    sparse_index_size: usize,
+    // offsets into the file
+    lowest_sym: usize,
+    btree: usize,
+    sparse_index: usize,
+    block_length: usize,
+    data: usize,
+    base64: Vec<u64>,
+    // how many values minus one each symbol expands to
+    symlen: Vec<u8>,
+    // the order pieces are encoded in, which sets the groups
+    pieces: [u8; 7],
+    group_idx: [u64; 8],
+    group_len: [usize; 8],
+    map_idx: [u16; 4],
+}
+
+impl Table {
+    fn load(path: &std::path::Path, name: &str, dtz: bool) -> anyhow::Result<Self> {
+        // tables of up to five pieces take a few hundred megabytes at most
+        let data = std::fs::read(path)?;
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if data.len() < 5 || data[..4] != magic {
+            return Err(anyhow::anyhow!("not a Syzygy table"));
+        }
+        let (white, black) = name
+            .split_once('v')
+            .ok_or_else(|| anyhow::anyhow!("no material in the file name"))?;
+        let count = |side: &str, c: char| side.chars().filter(|&x| x == c).count();
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let lead_white = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        let mut table = Table {
+            data: Vec::new(),
+            dtz,
+            symmetric: white == black,
+            piece_count: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: [white, black]
+                .iter()
+                .any(|side| "QRBNP".chars().any(|c| count(side, c) == 1)),
+            pawn_count: if lead_white {
+                [white_pawns, black_pawns]
+            } else {
+                [black_pawns, white_pawns]
+            },
+            items: Vec::new(),
+            map: 0,
+        };
+        if table.piece_count > 7 || (data[4] & 2 != 0) != table.has_pawns {
+            return Err(anyhow::anyhow!("table does not match its file name"));
+        }
+
+        let sides = if !dtz && !table.symmetric { 2 } else { 1 };
+        let files = if table.has_pawns { 4 } else { 1 };
+        let both_pawns = table.has_pawns && table.pawn_count[1] > 0;
+        let mut items = vec![vec![PairsData::default(); files]; sides];
+        let mut pos = 5;
+        for file in 0..files {
+            let second = if both_pawns { data[pos + 1] } else { 0xFF };
+            let order = [
+                [data[pos] & 0xF, second & 0xF],
+                [data[pos] >> 4, second >> 4],
+            ];
+            pos += 1 + both_pawns as usize;
+            for k in 0..table.piece_count {
+                for (side, item) in items.iter_mut().enumerate() {
+                    item[file].pieces[k] = if side == 1 {
+                        data[pos] >> 4
+                    } else {
+                        data[pos] & 0xF
+                    };
+                }
+                pos += 1;
+            }
+            for (side, item) in items.iter_mut().enumerate() {
+                table.set_groups(&mut item[file], order[side], file);
+            }
+        }
+        pos += pos & 1;
+
+        for file in 0..files {
+            for item in items.iter_mut() {
+                pos = set_sizes(&mut item[file], &data, pos);
+            }
+        }
+        if dtz {
+            table.map = pos;
+            for pairs in items[0].iter_mut() {
+                if pairs.flags & MAPPED == 0 {
+                    continue;
+                }
+                // where the map of each outcome starts, counted in entries from `map` plus one
+                for i in 0..4 {
+                    if pairs.flags & WIDE != 0 {
+                        pos += pos & 1;
+                        pairs.map_idx[i] = ((pos - table.map) / 2 + 1) as u16;
+                        pos += 2 * le16(&data, pos) as usize + 2;
+                    } else {
+                        pairs.map_idx[i] = (pos - table.map + 1) as u16;
+                        pos += data[pos] as usize + 1;
+                    }
+                }
+            }
+            pos += pos & 1;
+        }
+        for file in 0..files {
+            for item in items.iter_mut() {
+                item[file].sparse_index = pos;
+                pos += item[file].sparse_index_size * 6;
+            }
+        }
+        for file in 0..files {
+            for item in items.iter_mut() {
+                item[file].block_length = pos;
+                pos += item[file].block_length_size * 2;
+            }
+        }
+        for file in 0..files {
+            for item in items.iter_mut() {
+                pos = (pos + 0x3F) & !0x3F;
+                item[file].data = pos;
+                pos += item[file].num_blocks * item[file].block_size;
+            }
+        }
+        if pos > data.len() {
+            return Err(anyhow::anyhow!("table is truncated"));
+        }
+        table.items = items;
+        table.data = data;
+        Ok(table)
+    }
+
+    // Groups are runs of pieces encoded together: the leading kings and pieces (or pawns),
+    // then every set of like pieces. `order` says in which order groups make up the index.
+    fn set_groups(&self, pairs: &mut PairsData, order: [u8; 2], file: usize) {
+        let maps = maps();
+        let mut n = 0;
+        let mut first_len: i32 = if self.has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        pairs.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || pairs.pieces[i] == pairs.pieces[i - 1] {
+                pairs.group_len[n] += 1;
+            } else {
+                n += 1;
+                pairs.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        pairs.group_len[n] = 0;
+
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free_squares =
+            64 - pairs.group_len[0] - if both_pawns { pairs.group_len[1] } else { 0 };
+        let mut idx = 1u64;
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                pairs.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    maps.lead_pawns_size[pairs.group_len[0]][file]
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                pairs.group_idx[1] = idx;
+                idx *= maps.binomial[pairs.group_len[1]][48 - pairs.group_len[0]];
+            } else {
+                pairs.group_idx[next] = idx;
+                idx *= maps.binomial[pairs.group_len[next]][free_squares];
+                free_squares -= pairs.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        pairs.group_idx[n] = idx;
+    }
+
+    fn get(&self, stm: usize, file: usize) -> &PairsData {
+        let side = &self.items[stm % self.items.len()];
+        &side[if self.has_pawns { file } else { 0 }]
+    }
+
+    // `None` when this DTZ table only stores the other side to move
+    fn probe(&self, board: &BoardState, black_stronger: bool, wdl: i32) -> Option<i32> {
+        let (stm, file, idx) = self.index(board, black_stronger);
+        if self.dtz
+            && (self.get(stm, file).flags & STM) as usize != stm
+            && (!self.symmetric || self.has_pawns)
+        {
+            return None;
+        }
+        let value = self.decompress(self.get(stm, file), idx);
+        Some(self.map_score(file, value, wdl))
+    }
+
+    // which sub-table holds `board` and where, as side to move, leading pawn file and index
+    fn index(&self, board: &BoardState, black_stronger: bool) -> (usize, usize, u64) {
+        let maps = maps();
+        // stored from the stronger side's view, with white to move for symmetric material
+        let flip = black_stronger || (self.symmetric && !board.white_to_play);
+        let flip_colour = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip == board.white_to_play) as usize;
+
+        let mut squares = [0usize; 7];
+        let mut pieces = [0u8; 7];
+        let mut size = 0;
+        let mut lead_pawns = 0;
+        let mut file = 0;
+        let on_board = board
+            .pieces
+            .iter()
+            .enumerate()
+            .filter_map(|(square, piece)| Some((square, piece_code((*piece)?))))
+            .collect::<Vec<_>>();
+        if self.has_pawns {
+            // the leading pawns' colour is that of the first piece of the encoding
+            let lead = self.get(0, 0).pieces[0] ^ flip_colour;
+            for &(square, _) in on_board.iter().filter(|&&(_, code)| code == lead) {
+                squares[size] = square ^ flip_squares;
+                size += 1;
+            }
+            lead_pawns = size;
+            // the leading pawn is the one closest to the edge, then lowest
+            let leader = (0..lead_pawns)
+                .max_by_key(|&i| maps.pawns[squares[i]])
+                .unwrap_or(0);
+            squares.swap(0, leader);
+            file = squares[0] % 8;
+            if file > 3 {
+                file = 7 - file;
+            }
+        }
+
+        let lead = if self.has_pawns {
+            self.get(0, 0).pieces[0] ^ flip_colour
+        } else {
+            0
+        };
+        for &(square, code) in &on_board {
+            if self.has_pawns && code == lead {
+                continue;
+            }
+            squares[size] = square ^ flip_squares;
+            pieces[size] = code ^ flip_colour;
+            size += 1;
+        }
+
+        let pairs = self.get(stm, file);
+        // put the pieces in the order the table encodes them in
+        for i in lead_pawns..size.saturating_sub(1) {
+            if let Some(j) = (i + 1..size).find(|&j| pairs.pieces[i] == pieces[j]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        // mirror so the leading piece is on files a to d
+        if squares[0] % 8 > 3 {
+            for square in &mut squares[..size] {
+                *square ^= 7;
+            }
+        }
+
+        let mut idx: u64;
+        if self.has_pawns {
+            idx = maps.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|&square| maps.pawns[square]);
+            for (i, &square) in squares[..lead_pawns].iter().enumerate().skip(1) {
+                idx += maps.binomial[i][maps.pawns[square]];
+            }
+        } else {
+            // without pawns the board can be mirrored vertically and along a1-h8 as well
+            if squares[0] / 8 > 3 {
+                for square in &mut squares[..size] {
+                    *square ^= 56;
+                }
+            }
+            for i in 0..pairs.group_len[0] {
+                if off_diagonal(squares[i]) == 0 {
+                    continue;
+                }
+                if off_diagonal(squares[i]) > 0 {
+                    for square in &mut squares[i..size] {
+                        *square = ((*square >> 3) | (*square << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            let [s0, s1, s2] = [squares[0], squares[1], squares[2]];
+            idx = if self.has_unique_pieces {
+                let adjust1 = (s1 > s0) as usize;
+                let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+                if off_diagonal(s0) != 0 {
+                    (maps.a1d1d4[s0] * 63 + s1 - adjust1) * 62 + s2 - adjust2
+                } else if off_diagonal(s1) != 0 {
+                    (6 * 63 + (s0 / 8) * 28 + maps.b1h1h7[s1]) * 62 + s2 - adjust2
+                } else if off_diagonal(s2) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + (s0 / 8) * 7 * 28
+                        + (s1 / 8 - adjust1) * 28
+                        + maps.b1h1h7[s2]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + (s0 / 8) * 7 * 6
+                        + (s1 / 8 - adjust1) * 6
+                        + (s2 / 8 - adjust2)
+                }
+            } else {
+                maps.kk[maps.a1d1d4[s0]][s1]
+            } as u64;
+        }
+
+        // the other groups, each as a combination of the squares the earlier ones left free
+        idx *= pairs.group_idx[0];
+        let mut start = pairs.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while pairs.group_len[next] != 0 {
+            let len = pairs.group_len[next];
+            squares[start..start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let square = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&s| square > s).count();
+                n += maps.binomial[i + 1][square - adjust - 8 * remaining_pawns as usize];
+            }
+            remaining_pawns = false;
+            idx += n * pairs.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        (stm, file, idx)
+    }
+
+    fn decompress(&self, pairs: &PairsData, idx: u64) -> i32 {
+        if pairs.flags & SINGLE_VALUE != 0 {
+            return pairs.min_sym_len as i32;
+        }
+        let data = &self.data;
+
+        // every `span` values the sparse index says which block holds the one in the middle
+        let k = (idx / pairs.span) as usize;
+        let entry = pairs.sparse_index + 6 * k;
+        let mut block = le32(data, entry) as usize;
+        let mut offset = le16(data, entry + 4) as i64;
+        offset += (idx % pairs.span) as i64 - (pairs.span / 2) as i64;
+        let block_length = |block: usize| le16(data, pairs.block_length + 2 * block) as i64;
+        while offset < 0 {
+            block -= 1;
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+        }
+
+        // walk the canonical Huffman symbols of the block until the one covering `offset`
+        let mut ptr = pairs.data + block * pairs.block_size;
+        let mut buf64 = be64(data, ptr);
+        ptr += 8;
+        let mut buf64_size = 64;
+        let min_sym_len = pairs.min_sym_len as usize;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf64 < pairs.base64[len] {
+                len += 1;
+            }
+            sym = ((buf64 - pairs.base64[len]) >> (64 - len - min_sym_len)) as usize;
+            sym += le16(data, pairs.lowest_sym + 2 * len) as usize;
+            if offset < pairs.symlen[sym] as i64 + 1 {
+                break;
+            }
+            offset -= pairs.symlen[sym] as i64 + 1;
+            let len = len + min_sym_len;
+            buf64 <<= len;
+            buf64_size -= len;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= (be32(data, ptr) as u64) << (64 - buf64_size);
+                ptr += 4;
+            }
+        }
+
+        // symbols stand for pairs of symbols, down to single values
+        while pairs.symlen[sym] != 0 {
+            let (left, right) = btree(data, pairs.btree, sym);
+            if offset < pairs.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= pairs.symlen[left] as i64 + 1;
+                sym = right;
+            }
+        }
+        btree(data, pairs.btree, sym).0 as i32
+    }
+
+    fn map_score(&self, file: usize, value: i32, wdl: i32) -> i32 {
+        if !self.dtz {
+            return value - 2;
+        }
+        let pairs = self.get(0, file);
+        let mut value = value;
+        if pairs.flags & MAPPED != 0 {
+            const OUTCOME: [usize; 5] = [1, 3, 0, 2, 0];
+            let idx = pairs.map_idx[OUTCOME[(wdl + 2) as usize]] as usize + value as usize;
+            value = if pairs.flags & WIDE != 0 {
+                le16(&self.data, self.map + 2 * idx) as i32
+            } else {
+                self.data.get(self.map + idx).copied().unwrap_or(0) as i32
+            };
+        }
+        // stored in moves unless flagged as plies, but always returned in plies
+        if (wdl == 2 && pairs.flags & WIN_PLIES == 0)
+            || (wdl == -2 && pairs.flags & LOSS_PLIES == 0)
+            || wdl.abs() == 1
+        {
+            value *= 2;
+        }
+        value + 1
+    }
+}
+
+// reads the Huffman table header, returning where the next one starts
+fn set_sizes(pairs: &mut PairsData, data: &[u8], mut pos: usize) -> usize {
+    pairs.flags = data[pos];
+    pos += 1;
+    if pairs.flags & SINGLE_VALUE != 0 {
+        // the one value every position has
+        pairs.min_sym_len = data[pos];
+        return pos + 1;
+    }
+
+    let groups = pairs
+        .group_len
+        .iter()
+        .position(|&len| len == 0)
+        .unwrap_or(7);
+    let size = pairs.group_idx[groups];
+    pairs.block_size = 1 << data[pos];
+    pairs.span = 1 << data[pos + 1];
+    pairs.sparse_index_size = size.div_ceil(pairs.span) as usize;
+    let padding = data[pos + 2] as usize;
+    pairs.num_blocks = le32(data, pos + 3) as usize;
+    pairs.block_length_size = pairs.num_blocks + padding;
+    let max_sym_len = data[pos + 7] as usize;
+    pairs.min_sym_len = data[pos + 8];
+    pos += 9;
+    pairs.lowest_sym = pos;
+
+    // lowest code of each length padded to 64 bits, longer codes having lower values
+    let lengths = max_sym_len + 1 - pairs.min_sym_len as usize;
+    pairs.base64 = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        pairs.base64[i] = pairs.base64[i + 1]
+            .wrapping_add(le16(data, pos + 2 * i) as u64)
+            .wrapping_sub(le16(data, pos + 2 * i + 2) as u64)
+            / 2;
+    }
+    for (i, base) in pairs.base64.iter_mut().enumerate() {
+        *base = base
+            .checked_shl((64 - i - pairs.min_sym_len as usize) as u32)
+            .unwrap_or(0);
+    }
+    pos += lengths * 2;
+
+    let symbols = le16(data, pos) as usize;
+    pos += 2;
+    pairs.btree = pos;
+    pairs.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            pairs.symlen[sym] = symlen(data, pairs, sym, &mut visited);
+        }
+    }
+    pos + symbols * 3 + (symbols & 1)
+}
+
+fn symlen(data: &[u8], pairs: &mut PairsData, sym: usize, visited: &mut [bool]) -> u8 {
+    visited[sym] = true;
+    let (left, right) = btree(data, pairs.btree, sym);
+    if right == 0xFFF {
+        return 0;
+    }
+    for child in [left, right] {
+        if !visited[child] {
+            pairs.symlen[child] = symlen(data, pairs, child, visited);
+        }
+    }
+    pairs.symlen[left]
+        .wrapping_add(pairs.symlen[right])
+        .wrapping_add(1)
+}
+
+// the two symbols `sym` expands to, 12 bits each; a leaf has its value on the left
+fn btree(data: &[u8], btree: usize, sym: usize) -> (usize, usize) {
+    let at = btree + 3 * sym;
+    let byte = |i: usize| data.get(at + i).copied().unwrap_or(0) as usize;
+    (
+        ((byte(1) & 0xF) << 8) | byte(0),
+        (byte(2) << 4) | (byte(1) >> 4),
+    )
+}
+
+fn le16(data: &[u8], at: usize) -> u16 {
+    data.get(at..at + 2)
+        .map_or(0, |bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn le32(data: &[u8], at: usize) -> u32 {
+    data.get(at..at + 4)
+        .map_or(0, |bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn be32(data: &[u8], at: usize) -> u32 {
+    data.get(at..at + 4)
+        .map_or(0, |bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn be64(data: &[u8], at: usize) -> u64 {
+    data.get(at..at + 8)
+        .map_or(0, |bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+// ranks above the a1-h8 diagonal are positive
+fn off_diagonal(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+/// Square numberings and combination counts of the index encoding.
+struct Maps {
+    // a2 to h7 as 0..47, higher for squares closer to the edge and lower
+    pawns: [usize; 64],
+    // squares below the a1-h8 diagonal as 0..27
+    b1h1h7: [usize; 64],
+    // the a1-d1-d4 triangle as 0..9, diagonal last
+    a1d1d4: [usize; 64],
+    // the 462 ways to place two kings with the first in the triangle
+    kk: [[usize; 64]; 10],
+    binomial: [[u64; 64]; 6],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+fn maps() -> &'static Maps {
+    static MAPS: OnceLock<Maps> = OnceLock::new();
+    MAPS.get_or_init(|| {
+        let mut maps = Maps {
+            pawns: [0; 64],
+            b1h1h7: [0; 64],
+            a1d1d4: [0; 64],
+            kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 6],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        let mut code = 0;
+        for square in 0..64 {
+            if off_diagonal(square) < 0 {
+                maps.b1h1h7[square] = code;
+                code += 1;
+            }
+        }
+
+        let mut code = 0;
+        let mut diagonal = Vec::new();
+        for square in 0..=27 {
+            if square % 8 > 3 {
+                continue;
+            }
+            if off_diagonal(square) < 0 {
+                maps.a1d1d4[square] = code;
+                code += 1;
+            } else if off_diagonal(square) == 0 {
+                diagonal.push(square);
+            }
+        }
+        for square in diagonal {
+            maps.a1d1d4[square] = code;
+            code += 1;
+        }
+
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        for idx in 0..10 {
+            // b1 is the only square numbered 0, the others default to it
+            for s1 in (0..=27).filter(|&s1| maps.a1d1d4[s1] == idx && (idx > 0 || s1 == 1)) {
+                for s2 in 0..64 {
+                    let touching = (s1 / 8).abs_diff(s2 / 8) <= 1 && (s1 % 8).abs_diff(s2 % 8) <= 1;
+                    if touching || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        maps.kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            maps.kk[idx][s2] = code;
+            code += 1;
+        }
+
+        maps.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                maps.binomial[k][n] = if k > 0 {
+                    maps.binomial[k - 1][n - 1]
+                } else {
+                    0
+                } + if k < n { maps.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        let mut available = 48;
+        for lead_pawns in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..=6 {
+                    let square = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        available -= 2;
+                        maps.pawns[square] = available + 1;
+                        maps.pawns[square ^ 7] = available;
+                    }
+                    maps.lead_pawn_idx[lead_pawns][square] = idx;
+                    idx += maps.binomial[lead_pawns - 1][maps.pawns[square]];
+                }
+                maps.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        maps
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // solved from scratch by tests/fixtures/syzygy/generate.py
+    fn tablebases() -> Tablebases {
+        Tablebases::open(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/syzygy"
+        ))
+        .unwrap()
+    }
+
+    fn board(fen: &str) -> BoardState {
+        BoardState::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn opens_a_directory_of_tables() {
+        let tablebases = tablebases();
+        assert_eq!(tablebases.max_pieces(), 3);
+        assert!(!tablebases.covers(&board("8/8/8/8/8/2k5/1R6/RK6 w - - 0 1")));
+        assert!(Tablebases::open(concat!(env!("CARGO_MANIFEST_DIR"), "/src")).is_err());
+    }
+
+    #[test]
+    fn probes_wdl() {
+        let tablebases = tablebases();
+        for (fen, wdl) in [
+            ("7k/8/6K1/8/8/8/8/R7 w - - 0 1", Wdl::Win),
+            ("7k/8/6K1/8/8/8/8/Q7 b - - 0 1", Wdl::Loss),
+            // the king takes the queen
+            ("8/8/8/8/8/8/6Q1/K6k b - - 0 1", Wdl::Draw),
+            ("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", Wdl::Draw),
+            // the stronger side is black
+            ("k7/8/8/8/8/8/8/1q4K1 w - - 0 1", Wdl::Loss),
+            // a king in front of its pawn on the sixth rank wins whoever moves
+            ("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1", Wdl::Win),
+            ("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1", Wdl::Loss),
+            ("4k3/8/3KP3/8/8/8/8/8 w - - 0 1", Wdl::Win),
+            // unless the defender has the opposition
+            ("4k3/8/3KP3/8/8/8/8/8 b - - 0 1", Wdl::Draw),
+            ("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1", Wdl::Draw),
+            ("7k/8/8/8/8/8/7P/7K w - - 0 1", Wdl::Draw),
+            ("8/4p3/4k3/8/4K3/8/8/8 w - - 0 1", Wdl::Loss),
+        ] {
+            assert_eq!(tablebases.probe_wdl(&board(fen)), Some(wdl), "{fen}");
+        }
+    }
+
+    #[test]
+    fn probes_dtz() {
+        let tablebases = tablebases();
+        for (fen, dtz) in [
+            ("7k/8/6K1/8/8/8/8/R7 w - - 0 1", 1),
+            ("7k/8/6K1/8/8/8/8/R7 b - - 0 1", -2),
+            // the longest mates with a queen and with a rook, 10 and 16 moves
+            ("8/8/8/5k2/8/8/1Q6/K7 w - - 0 1", 19),
+            ("8/8/8/8/8/2k5/1R6/K7 w - - 0 1", 31),
+            ("k7/8/8/8/8/8/8/1q4K1 w - - 0 1", -18),
+            // pawn moves count from zero again
+            ("4k3/8/3KP3/8/8/8/8/8 w - - 0 1", 1),
+            ("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1", 3),
+            ("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1", -4),
+            ("8/8/8/4k3/8/4K3/4P3/8 b - - 0 1", -6),
+            ("8/4p3/4k3/8/4K3/8/8/8 w - - 0 1", -6),
+            ("8/8/8/1k6/8/8/K5P1/8 w - - 0 1", 17),
+            ("4k3/8/3KP3/8/8/8/8/8 b - - 0 1", 0),
+        ] {
+            assert_eq!(tablebases.probe_dtz(&board(fen)), Some(dtz), "{fen}");
+        }
+    }
+
+    #[test]
+    fn root_moves_put_the_fastest_win_first() {
+        let board = board("7k/8/6K1/8/8/8/8/R7 w - - 0 1");
+        let moves = tablebases().root_moves(&board).unwrap();
+        assert_eq!(moves.len(), board.moves().len());
+        assert_eq!(Some(moves[0].mv), board.parse_san("Ra8#"));
+        assert_eq!((moves[0].wdl, moves[0].dtz), (Wdl::Win, 1));
+        assert!(
+            moves[1..]
+                .iter()
+                .all(|root| root.dtz > 1 || root.wdl < Wdl::Win)
+        );
+    }
+}
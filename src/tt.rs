@@ -0,0 +1,277 @@
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+
+use crate::logic::{Move, PieceKind};
+use crate::pieces;
+
+pub const DEFAULT_MB: usize = 16;
+
+/// How the stored score relates to the real one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Bound {
+    Exact,
+    /// The search failed high, the score is at least this.
+    Lower,
+    /// The search failed low, the score is at most this.
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub depth: u32,
+    pub score: i32,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+}
+
+// key ^ data and data, so a slot torn by two threads writing at once fails the key check
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+const BUCKET: usize = 4;
+
+/// Fixed size hash table of search results, shared between search threads without locking.
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    pub fn new(mb: usize) -> Self {
+        let buckets = (mb * 1024 * 1024 / (BUCKET * size_of::<Slot>())).max(1);
+        Self {
+            slots: (0..buckets * BUCKET).map(|_| Slot::default()).collect(),
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    pub fn size_mb(&self) -> usize {
+        self.slots.len() * size_of::<Slot>() / (1024 * 1024)
+    }
+
+    /// Forgets everything, e.g. between games.
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    /// Called at the start of every search so older entries get replaced first.
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<Entry> {
+        self.bucket(hash).iter().find_map(|slot| {
+            let data = slot.data.load(Ordering::Relaxed);
+            (data != 0 && slot.key.load(Ordering::Relaxed) ^ data == hash)
+                .then(|| unpack(data))
+                .flatten()
+        })
+    }
+
+    pub fn store(&self, hash: u64, depth: u32, score: i32, bound: Bound, best_move: Option<Move>) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let bucket = self.bucket(hash);
+        let same = bucket.iter().find(|slot| {
+            let data = slot.data.load(Ordering::Relaxed);
+            slot.key.load(Ordering::Relaxed) ^ data == hash
+        });
+        // otherwise evict the shallowest entry, counting each search since it was written as
+        // several plies of depth lost
+        let slot = same.unwrap_or_else(|| {
+            bucket
+                .iter()
+                .min_by_key(|slot| {
+                    let data = slot.data.load(Ordering::Relaxed);
+                    let age = generation.wrapping_sub((data >> 56) as u8);
+                    ((data >> 40) & 0xFF) as i32 - 8 * age as i32
+                })
+                .expect("buckets are not empty")
+        });
+
+        let old = slot.data.load(Ordering::Relaxed);
+        // a search that didn't find a better move shouldn't erase the one we knew
+        let best_move = match (best_move, same.and_then(|_| unpack(old))) {
+            (None, Some(entry)) => entry.best_move,
+            _ => best_move,
+        };
+        let data = pack_move(best_move)
+            | (score as i16 as u16 as u64) << 24
+            | (depth.min(255) as u64) << 40
+            | (bound as u64 + 1) << 48
+            | (generation as u64) << 56;
+        slot.key.store(hash ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+
+    /// Permille of sampled slots written during the current search, as reported by UCI.
+    pub fn hashfull(&self) -> u32 {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let sample = &self.slots[..self.slots.len().min(1000)];
+        let used = sample
+            .iter()
+            .filter(|slot| {
+                let data = slot.data.load(Ordering::Relaxed);
+                data != 0 && (data >> 56) as u8 == generation
+            })
+            .count();
+        (used * 1000 / sample.len()) as u32
+    }
+
+    fn bucket(&self, hash: u64) -> &[Slot] {
+        let buckets = self.slots.len() / BUCKET;
+        let index = ((hash as u128 * buckets as u128) >> 64) as usize;
+        &self.slots[index * BUCKET..(index + 1) * BUCKET]
+    }
+}
+
+// bits 0-7 from, 8-15 to, 16-23 promotion, all zero for no move
+fn pack_move(mv: Option<Move>) -> u64 {
+    let Some(mv) = mv else {
+        return 0;
+    };
+    let promotion = match mv.promotion {
+        None => 0,
+        Some(PieceKind::Knight) => 1,
+        Some(PieceKind::Bishop) => 2,
+        Some(PieceKind::Rook) => 3,
+        Some(PieceKind::Queen) => 4,
+        Some(PieceKind::Fairy(def)) => 5 + pieces::index_of(def).unwrap_or(0) as u64,
+        Some(PieceKind::Pawn | PieceKind::King) => unreachable!("not a promotion"),
+    };
+    mv.from as u64 | (mv.to as u64) << 8 | promotion << 16
+}
+
+fn unpack(data: u64) -> Option<Entry> {
+    let bound = match (data >> 48) & 0xFF {
+        1 => Bound::Exact,
+        2 => Bound::Lower,
+        3 => Bound::Upper,
+        _ => return None,
+    };
+    let from = (data & 0xFF) as u32;
+    let to = ((data >> 8) & 0xFF) as u32;
+    let promotion = match (data >> 16) & 0xFF {
+        0 => None,
+        1 => Some(PieceKind::Knight),
+        2 => Some(PieceKind::Bishop),
+        3 => Some(PieceKind::Rook),
+        4 => Some(PieceKind::Queen),
+        code => Some(PieceKind::Fairy(
+            *pieces::fairy_pieces().get(code as usize - 5)?,
+        )),
+    };
+    Some(Entry {
+        depth: ((data >> 40) & 0xFF) as u32,
+        score: ((data >> 24) & 0xFFFF) as u16 as i16 as i32,
+        bound,
+        best_move: (from != to).then_some(Move {
+            from,
+            to,
+            promotion,
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // hashes this far apart land in the same bucket of a 1 MB table
+    const BASE: u64 = 0xABCD_0000_0000_0000;
+
+    #[test]
+    fn stores_and_probes() {
+        let tt = TranspositionTable::new(1);
+        let mv = Move {
+            from: 52,
+            to: 60,
+            promotion: Some(PieceKind::Knight),
+        };
+        tt.store(BASE, 7, -1234, Bound::Lower, Some(mv));
+        let entry = tt.probe(BASE).unwrap();
+        assert_eq!(entry.depth, 7);
+        assert_eq!(entry.score, -1234);
+        assert_eq!(entry.bound, Bound::Lower);
+        assert_eq!(entry.best_move, Some(mv));
+        assert!(tt.probe(BASE + 1).is_none());
+
+        // a search of the same position without a best move keeps the one we had
+        tt.store(BASE, 8, 50, Bound::Upper, None);
+        let entry = tt.probe(BASE).unwrap();
+        assert_eq!((entry.depth, entry.score), (8, 50));
+        assert_eq!(entry.best_move, Some(mv));
+    }
+
+    #[test]
+    fn replaces_shallow_then_old_entries() {
+        let tt = TranspositionTable::new(1);
+        for depth in 1..=BUCKET as u64 {
+            tt.store(BASE + depth, depth as u32, 0, Bound::Exact, None);
+        }
+        // the bucket is full, the shallowest goes
+        tt.store(BASE + 10, 10, 0, Bound::Exact, None);
+        assert!(tt.probe(BASE + 1).is_none());
+        assert!((2..=BUCKET as u64).all(|depth| tt.probe(BASE + depth).is_some()));
+
+        // a search later, entries of the last one count as shallower than they are
+        tt.new_search();
+        tt.store(BASE + 11, 1, 0, Bound::Exact, None);
+        assert!(tt.probe(BASE + 2).is_none());
+        tt.store(BASE + 12, 1, 0, Bound::Exact, None);
+        assert!(tt.probe(BASE + 3).is_none());
+        assert!(tt.probe(BASE + 11).is_some());
+        assert!(tt.probe(BASE + 10).is_some());
+    }
+
+    #[test]
+    fn clear_forgets_everything() {
+        let tt = TranspositionTable::new(1);
+        for i in 0..1000 {
+            tt.store(
+                0x9E37_79B9_7F4A_7C15u64.wrapping_mul(i),
+                3,
+                0,
+                Bound::Exact,
+                None,
+            );
+        }
+        assert!(tt.hashfull() > 0);
+        tt.clear();
+        assert_eq!(tt.hashfull(), 0);
+        assert!((0..1000).all(|i| tt.probe(0x9E37_79B9_7F4A_7C15u64.wrapping_mul(i)).is_none()));
+    }
+
+    #[test]
+    fn concurrent_writers_never_tear_entries() {
+        let tt = TranspositionTable::new(1);
+        // what each hash is stored with, so a probe can tell whose data it got
+        let expected = |hash: u64| ((hash % 200) as u32, (hash % 20_000) as i32 - 10_000);
+        std::thread::scope(|scope| {
+            for thread in 0..4u64 {
+                let tt = &tt;
+                scope.spawn(move || {
+                    for i in 0..20_000u64 {
+                        // all threads fight over a handful of buckets
+                        let hash = BASE + (i * 4 + thread) % 64;
+                        let (depth, score) = expected(hash);
+                        tt.store(hash, depth, score, Bound::Exact, None);
+                        if let Some(entry) = tt.probe(hash ^ 1) {
+                            assert_eq!((entry.depth, entry.score), expected(hash ^ 1));
+                        }
+                    }
+                });
+            }
+        });
+        for hash in BASE..BASE + 64 {
+            if let Some(entry) = tt.probe(hash) {
+                assert_eq!((entry.depth, entry.score), expected(hash));
+            }
+        }
+    }
+}
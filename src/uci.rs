@@ -0,0 +1,362 @@
+use std::io::BufRead;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::batch_eval::GpuEvaluator;
+use crate::book::Book;
+use crate::logic::{BoardState, STARTING_FEN};
+use crate::nnue::Network;
+use crate::search::{Limits, SearchResult, Searcher};
+use crate::syzygy::Tablebases;
+use crate::tt::{self, TranspositionTable};
+
+// kept back from the clock for the GUI and the pipe
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+
+/// Speaks UCI on stdin/stdout until `quit` or the end of input.
+pub fn run() -> anyhow::Result<()> {
+    let mut uci = Uci::new()?;
+    for line in std::io::stdin().lock().lines() {
+        match uci.command(&line?) {
+            Ok(true) => {}
+            Ok(false) => break,
+            // a GUI has nowhere to show errors, so report and carry on
+            Err(e) => println!("info string {e}"),
+        }
+    }
+    uci.stop();
+    Ok(())
+}
+
+struct Uci {
+    board: BoardState,
+    tt: Arc<TranspositionTable>,
+    search: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+    book: Option<Book>,
+    /// Whether to play from `book` rather than leave the opening to the GUI.
+    own_book: bool,
+    tablebases: Option<Arc<Tablebases>>,
+    /// Evaluates on a GPU device of its own, there is no window to share one with.
+    evaluator: Option<Arc<GpuEvaluator>>,
+    network: Option<Arc<Network>>,
+    threads: usize,
+}
+
+impl Uci {
+    fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            board: BoardState::from_fen(STARTING_FEN)?,
+            tt: Arc::new(TranspositionTable::new(tt::DEFAULT_MB)),
+            search: None,
+            book: None,
+            own_book: false,
+            tablebases: None,
+            evaluator: None,
+            network: None,
+            threads: 1,
+        })
+    }
+
+    /// Handles one line of input, returns false on `quit`.
+    fn command(&mut self, line: &str) -> anyhow::Result<bool> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(true);
+        };
+        match command {
+            "uci" => {
+                println!("id name chess-gpu {}", env!("CARGO_PKG_VERSION"));
+                println!("id author the chess-gpu developers");
+                println!(
+                    "option name Hash type spin default {} min 1 max 65536",
+                    tt::DEFAULT_MB
+                );
+                println!("option name Clear Hash type button");
+                println!("option name Threads type spin default 1 min 1 max 256");
+                println!("option name OwnBook type check default false");
+                println!("option name Book File type string default <empty>");
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name GpuEval type check default false");
+                println!("option name EvalFile type string default <empty>");
+                println!("uciok");
+            }
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                self.stop();
+                self.tt.clear();
+            }
+            "setoption" => self.set_option(args)?,
+            "position" => self.position(args)?,
+            "go" => self.go(args)?,
+            "stop" => self.stop(),
+            "quit" => return Ok(false),
+            // debug, register, ponderhit and anything unknown
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn set_option(&mut self, args: &[&str]) -> anyhow::Result<()> {
+        // setoption name <id, may contain spaces> [value <x>]
+        let value_at = args.iter().position(|&arg| arg == "value");
+        let name = args[1.min(args.len())..value_at.unwrap_or(args.len())].join(" ");
+        let value = value_at.map(|at| args[at + 1..].join(" "));
+        match (name.to_ascii_lowercase().as_str(), value) {
+            ("hash", Some(value)) => {
+                self.stop();
+                self.tt = Arc::new(TranspositionTable::new(value.parse::<usize>()?.max(1)));
+            }
+            ("clear hash", _) => {
+                self.stop();
+                self.tt.clear();
+            }
+            ("threads", Some(value)) => {
+                self.stop();
+                self.threads = value.parse::<usize>()?.max(1);
+            }
+            ("ownbook", Some(value)) => self.own_book = value == "true",
+            ("book file", Some(value)) => {
+                self.book = match value.as_str() {
+                    "" | "<empty>" => None,
+                    path => Some(Book::open(path)?),
+                };
+            }
+            ("syzygypath", Some(value)) => {
+                self.stop();
+                self.tablebases = match value.as_str() {
+                    "" | "<empty>" => None,
+                    paths => Some(Arc::new(Tablebases::open(paths)?)),
+                };
+            }
+            ("gpueval", Some(value)) => {
+                self.stop();
+                self.evaluator = match value.as_str() {
+                    "true" => Some(Arc::new(GpuEvaluator::headless()?)),
+                    _ => None,
+                };
+            }
+            ("evalfile", Some(value)) => {
+                self.stop();
+                self.network = match value.as_str() {
+                    "" | "<empty>" => None,
+                    path => Some(Arc::new(Network::open(path)?)),
+                };
+            }
+            _ => return Err(anyhow::anyhow!("unknown option {name:?}")),
+        }
+        Ok(())
+    }
+
+    fn position(&mut self, args: &[&str]) -> anyhow::Result<()> {
+        let moves_at = args
+            .iter()
+            .position(|&arg| arg == "moves")
+            .unwrap_or(args.len());
+        let mut board = match args.first() {
+            Some(&"startpos") => BoardState::from_fen(STARTING_FEN)?,
+            Some(&"fen") => {
+                let mut fen = args[1..moves_at].to_vec();
+                // some GUIs leave out the move counters
+                if fen.len() == 4 {
+                    fen.extend(["0", "1"]);
+                }
+                BoardState::from_fen(&fen.join(" "))?
+            }
+            _ => return Err(anyhow::anyhow!("expected startpos or fen, got {args:?}")),
+        };
+        for name in args.iter().skip(moves_at + 1) {
+            let mv = board
+                .parse_move(name)
+                .ok_or_else(|| anyhow::anyhow!("illegal move {name}"))?;
+            board.make(mv);
+        }
+        self.board = board;
+        Ok(())
+    }
+
+    fn go(&mut self, args: &[&str]) -> anyhow::Result<()> {
+        self.stop();
+        let (limits, infinite) = self.limits(args)?;
+
+        // an infinite search has to wait for `stop`, so analysis never comes from the book
+        if self.own_book
+            && !infinite
+            && let Some(mv) = self.book.as_ref().and_then(|book| book.choose(&self.board))
+        {
+            println!("bestmove {}", self.board.move_name(mv));
+            return Ok(());
+        }
+
+        let mut searcher = Searcher::with_table(limits, self.tt.clone())
+            .with_tablebases(self.tablebases.clone())
+            .with_network(self.network.clone())
+            .with_evaluator(self.evaluator.clone())
+            .with_threads(self.threads);
+        let stop = searcher.stop_flag();
+        let board = self.board.clone();
+        let tt = self.tt.clone();
+        let handle = std::thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let result =
+                    searcher.search_with_report(&board, |result| info(&board, result, &tt));
+                // bestmove may only follow `stop` when searching infinitely
+                while infinite && !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                match (result.best_move, result.pv.get(1)) {
+                    (Some(best), Some(&ponder)) => println!(
+                        "bestmove {} ponder {}",
+                        board.move_name(best),
+                        board.move_name(ponder)
+                    ),
+                    (Some(best), None) => println!("bestmove {}", board.move_name(best)),
+                    (None, _) => println!("bestmove 0000"),
+                }
+            }
+        });
+        self.search = Some((stop, handle));
+        Ok(())
+    }
+
+    /// What `go` asks for, and whether the search is infinite.
+    fn limits(&self, args: &[&str]) -> anyhow::Result<(Limits, bool)> {
+        let mut limits = Limits::default();
+        let mut infinite = false;
+        let mut time_left = None;
+        let mut increment = 0;
+        let mut moves_to_go = None;
+        let mut args = args.iter();
+        while let Some(&arg) = args.next() {
+            let mut value = || -> anyhow::Result<u64> {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("go {arg} needs a value"))?;
+                Ok(value.parse()?)
+            };
+            let ours = arg.starts_with('w') == self.board.white_to_play;
+            match arg {
+                "depth" => limits.depth = Some(value()? as u32),
+                "nodes" => limits.nodes = Some(value()?),
+                "movetime" => limits.time = Some(Duration::from_millis(value()?)),
+                "wtime" | "btime" => {
+                    let ms = value()?;
+                    if ours {
+                        time_left = Some(ms);
+                    }
+                }
+                "winc" | "binc" => {
+                    let ms = value()?;
+                    if ours {
+                        increment = ms;
+                    }
+                }
+                "movestogo" => moves_to_go = Some(value()?),
+                "infinite" => infinite = true,
+                // ponder, searchmoves and mate are not supported
+                _ => {}
+            }
+        }
+        if let Some(left) = time_left
+            && limits.time.is_none()
+        {
+            let left = Duration::from_millis(left);
+            let share = left / moves_to_go.unwrap_or(30).max(1) as u32
+                + Duration::from_millis(increment) * 3 / 4;
+            limits.time = Some(share.min(left.saturating_sub(MOVE_OVERHEAD)));
+        }
+        Ok((limits, infinite))
+    }
+
+    /// Ends a running search, which still answers with `bestmove`.
+    fn stop(&mut self) {
+        if let Some((stop, handle)) = self.search.take() {
+            stop.store(true, Ordering::Relaxed);
+            let _ = handle.join();
+        }
+    }
+}
+
+fn info(board: &BoardState, result: &SearchResult, tt: &TranspositionTable) {
+    let nps = result.nodes * 1000 / (result.time.as_millis() as u64).max(1);
+    // pv has to come last, and `describe` ends with it
+    println!(
+        "info nps {} hashfull {} {}",
+        nps,
+        tt.hashfull(),
+        result.describe(board)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_up_positions() {
+        let mut uci = Uci::new().unwrap();
+        uci.command("position startpos moves e2e4 e7e5").unwrap();
+        assert_eq!(
+            uci.board.to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2"
+        );
+
+        // the move counters are optional
+        uci.command("position fen 4k3/8/8/8/8/8/8/4K2R w K - moves e1g1")
+            .unwrap();
+        assert_eq!(uci.board.to_fen(), "4k3/8/8/8/8/8/8/5RK1 b - - 1 1");
+    }
+
+    #[test]
+    fn keeps_the_board_on_malformed_positions() {
+        let mut uci = Uci::new().unwrap();
+        uci.command("position startpos moves d2d4").unwrap();
+        let fen = uci.board.to_fen();
+        for line in [
+            "position",
+            "position foo",
+            "position startpos moves e2e5",
+            "position fen 8/8/8/8/8/8/8/X7 w - - 0 1",
+            "position fen 8/8/8",
+        ] {
+            assert!(uci.command(line).is_err(), "{line}");
+            assert_eq!(uci.board.to_fen(), fen, "{line}");
+        }
+    }
+
+    #[test]
+    fn reads_go_limits() {
+        let uci = Uci::new().unwrap();
+        let limits = |line: &str| {
+            let args = line.split_whitespace().collect::<Vec<_>>();
+            uci.limits(&args)
+        };
+
+        let (depth, infinite) = limits("depth 5 nodes 1000").unwrap();
+        assert_eq!(
+            (depth.depth, depth.nodes, depth.time),
+            (Some(5), Some(1000), None)
+        );
+        assert!(!infinite);
+
+        let (movetime, _) = limits("wtime 60000 movetime 300").unwrap();
+        assert_eq!(movetime.time, Some(Duration::from_millis(300)));
+
+        // white's clock: a twentieth of the time left plus most of the increment
+        let (clock, _) = limits("wtime 60000 btime 1000 winc 1000 binc 0 movestogo 20").unwrap();
+        assert_eq!(clock.time, Some(Duration::from_millis(3750)));
+
+        // never more than is left on the clock
+        let (short, _) = limits("wtime 100 winc 1000").unwrap();
+        assert_eq!(short.time, Some(Duration::from_millis(50)));
+
+        let (none, infinite) = limits("infinite").unwrap();
+        assert_eq!((none.depth, none.nodes, none.time), (None, None, None));
+        assert!(infinite);
+
+        assert!(limits("depth").is_err());
+        assert!(limits("movetime soon").is_err());
+    }
+}
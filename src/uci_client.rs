@@ -0,0 +1,265 @@
+use std::time::{Duration, Instant};
+
+use crate::logic::{BoardState, Move};
+use crate::process::{EngineProcess, Interrupt};
+use crate::search::{Limits, MATE, MAX_PLY, SearchResult};
+
+// how long an engine may overrun its time limit before we ask it to stop
+const GRACE: Duration = Duration::from_secs(5);
+
+/// External engine process spoken to over UCI.
+pub struct UciEngine {
+    process: EngineProcess,
+}
+
+impl UciEngine {
+    /// Starts the executable at `path` and waits for it to finish the UCI handshake.
+    pub fn spawn(path: &str) -> anyhow::Result<Self> {
+        let mut engine = Self {
+            process: EngineProcess::spawn(path, "quit")?,
+        };
+        engine.process.send("uci")?;
+        let timeout = Duration::from_secs(10);
+        loop {
+            let line = engine.process.read_line(timeout)?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.process.name = name.trim().to_owned();
+            } else if line.trim() == "uciok" {
+                break;
+            }
+        }
+        engine.wait_ready()?;
+        Ok(engine)
+    }
+
+    /// What the engine calls itself.
+    pub fn name(&self) -> &str {
+        &self.process.name
+    }
+
+    /// Sends `stop` from another thread, ending the search `search` waits for.
+    pub fn interrupt(&self) -> Interrupt {
+        self.process.interrupt("stop")
+    }
+
+    pub fn set_option(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        self.process
+            .send(&format!("setoption name {name} value {value}"))?;
+        self.wait_ready()
+    }
+
+    pub fn new_game(&mut self) -> anyhow::Result<()> {
+        self.process.send("ucinewgame")?;
+        self.wait_ready()
+    }
+
+    /// Sets up `start` followed by `moves`.
+    pub fn position(&mut self, start: &BoardState, moves: &[Move]) -> anyhow::Result<()> {
+        let mut command = format!("position fen {}", start.to_fen());
+        if !moves.is_empty() {
+            command.push_str(" moves");
+            let mut board = start.clone();
+            for &mv in moves {
+                command.push(' ');
+                command.push_str(&board.move_name(mv));
+                board.make(mv);
+            }
+        }
+        self.process.send(&command)
+    }
+
+    /// Searches `start` followed by `moves` and blocks until the engine answers with
+    /// `bestmove`. Scores and lines come from the last `info` that had a `pv`.
+    pub fn search(
+        &mut self,
+        start: &BoardState,
+        moves: &[Move],
+        limits: Limits,
+    ) -> anyhow::Result<SearchResult> {
+        let mut board = start.clone();
+        for &mv in moves {
+            board.make(mv);
+        }
+        self.position(start, moves)?;
+
+        let mut go = String::from("go");
+        if let Some(depth) = limits.depth {
+            go.push_str(&format!(" depth {depth}"));
+        }
+        if let Some(nodes) = limits.nodes {
+            go.push_str(&format!(" nodes {nodes}"));
+        }
+        if let Some(time) = limits.time {
+            go.push_str(&format!(" movetime {}", time.as_millis()));
+        }
+        if go == "go" {
+            go.push_str(&format!(" depth {MAX_PLY}"));
+        }
+        self.process.send(&go)?;
+
+        let start_time = Instant::now();
+        let mut deadline = limits.time.map(|time| time + GRACE);
+        let mut result = SearchResult::default();
+        loop {
+            let line = match deadline {
+                Some(timeout) => {
+                    match self
+                        .process
+                        .read_line(timeout.saturating_sub(start_time.elapsed()))
+                    {
+                        Ok(line) => line,
+                        // overran the clock, the engine still has to answer `stop`
+                        Err(_) if !self.process.exited() => {
+                            self.process.send("stop")?;
+                            deadline = None;
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                None => self.process.read_line(Duration::MAX)?,
+            };
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.split_first() {
+                Some((&"info", args)) => parse_info(&board, args, &mut result),
+                Some((&"bestmove", args)) => {
+                    result.best_move = match args.first() {
+                        Some(&"0000" | &"(none)") | None => None,
+                        Some(name) => Some(board.parse_move(name).ok_or_else(|| {
+                            anyhow::anyhow!("{} played illegal move {name}", self.name())
+                        })?),
+                    };
+                    if result.pv.first() != result.best_move.as_ref() {
+                        result.pv = result.best_move.into_iter().collect();
+                    }
+                    if result.time.is_zero() {
+                        result.time = start_time.elapsed();
+                    }
+                    return Ok(result);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn wait_ready(&mut self) -> anyhow::Result<()> {
+        self.process.send("isready")?;
+        while self.process.read_line(Duration::from_secs(10))?.trim() != "readyok" {}
+        Ok(())
+    }
+}
+
+// info lines without a pv, like currmove updates, only carry partial information
+fn parse_info(board: &BoardState, args: &[&str], result: &mut SearchResult) {
+    let mut info = result.clone();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "depth" => info.depth = number(&mut args).unwrap_or(0) as u32,
+            "nodes" => info.nodes = number(&mut args).unwrap_or(0) as u64,
+            "time" => info.time = Duration::from_millis(number(&mut args).unwrap_or(0) as u64),
+            // only the main line counts
+            "multipv" if number(&mut args) != Some(1) => return,
+            "score" => match (args.next(), number(&mut args)) {
+                (Some(&"cp"), Some(cp)) => info.score = cp as i32,
+                (Some(&"mate"), Some(moves)) if moves > 0 => {
+                    info.score = MATE - (2 * moves as i32 - 1)
+                }
+                (Some(&"mate"), Some(moves)) => info.score = -MATE + 2 * -moves as i32,
+                _ => {}
+            },
+            "pv" => {
+                let mut board = board.clone();
+                info.pv.clear();
+                for name in args.by_ref() {
+                    let Some(mv) = board.parse_move(name) else {
+                        break;
+                    };
+                    info.pv.push(mv);
+                    board.make(mv);
+                }
+                info.best_move = info.pv.first().copied();
+                *result = info;
+                return;
+            }
+            "string" => return,
+            _ => {}
+        }
+    }
+}
+
+fn number(args: &mut std::slice::Iter<&str>) -> Option<i64> {
+    args.next().and_then(|value| value.parse().ok())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::logic::STARTING_FEN;
+    use crate::process::tests::stand_in;
+
+    // answers the first search with a centipawn score and no ponder move, later ones with a mate
+    const ENGINE: &str = r#"case "$line" in
+uci) echo "id name Stand-in 1.0"; echo "option name Hash type spin default 16 min 1 max 1024"; echo uciok ;;
+isready) echo readyok ;;
+go*) if [ -z "$searched" ]; then
+    searched=1
+    echo "info depth 1 score cp 13 nodes 20 pv e2e4"
+    echo "info depth 2 seldepth 3 multipv 1 score cp 34 nodes 400 time 5 pv e2e4 e7e5"
+    echo "info depth 2 currmove d2d4 currmovenumber 2"
+    echo "bestmove e2e4"
+  else
+    echo "info depth 3 score mate 2 nodes 900 pv b8c6 g1f3"
+    echo "bestmove b8c6 ponder g1f3"
+  fi ;;
+quit) exit 0 ;;
+esac"#;
+
+    #[test]
+    fn talks_to_a_uci_engine() {
+        let path = stand_in("uci-stand-in", ENGINE);
+        let start = BoardState::from_fen(STARTING_FEN).unwrap();
+        let e4 = start.parse_move("e2e4").unwrap();
+        {
+            let mut engine = UciEngine::spawn(path.to_str().unwrap()).unwrap();
+            assert_eq!(engine.name(), "Stand-in 1.0");
+            engine.set_option("Hash", "32").unwrap();
+            engine.new_game().unwrap();
+
+            let result = engine.search(&start, &[], Limits::depth(2)).unwrap();
+            assert_eq!(result.best_move, Some(e4));
+            assert_eq!(result.score, 34);
+            assert_eq!((result.depth, result.nodes), (2, 400));
+            assert_eq!(result.time, Duration::from_millis(5));
+            let mut after = start.clone();
+            after.make(e4);
+            assert_eq!(result.pv, vec![e4, after.parse_move("e7e5").unwrap()]);
+
+            let result = engine.search(&start, &[e4], Limits::depth(3)).unwrap();
+            assert_eq!(result.best_move, after.parse_move("b8c6"));
+            assert_eq!(result.mate_in(), Some(2));
+        }
+
+        let log = std::fs::read_to_string(path.with_extension("log")).unwrap();
+        let commands = log.lines().collect::<Vec<_>>();
+        let fen = start.to_fen();
+        assert_eq!(
+            commands,
+            [
+                "uci",
+                "isready",
+                "setoption name Hash value 32",
+                "isready",
+                "ucinewgame",
+                "isready",
+                &format!("position fen {fen}"),
+                "go depth 2",
+                &format!("position fen {fen} moves e2e4"),
+                "go depth 3",
+                "quit",
+            ]
+        );
+        let _ = std::fs::remove_file(path.with_extension("log"));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,435 @@
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+use crate::logic::{self, BoardState, Move, STARTING_FEN, Undo};
+use crate::search::{Limits, SearchResult, Searcher};
+use crate::syzygy::Tablebases;
+use crate::tt::{self, TranspositionTable};
+
+// kept back from the clock for the GUI and the pipe
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+
+enum Event {
+    Line(String),
+    /// A search finished, tagged with the number it was started under.
+    Done(u64, Option<Move>),
+}
+
+/// Speaks CECP (the XBoard/WinBoard protocol) on stdin/stdout until `quit` or the end of input.
+pub fn run() -> anyhow::Result<()> {
+    let (events, receiver) = mpsc::channel();
+    let input = events.clone();
+    // input is read on its own thread so a running search can be interrupted
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            if input.send(Event::Line(line)).is_err() {
+                return;
+            }
+        }
+        let _ = input.send(Event::Line("quit".to_owned()));
+    });
+
+    let mut xboard = Xboard::new(events)?;
+    for event in receiver {
+        match event {
+            Event::Line(line) => match xboard.command(&line) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => println!("Error ({e}): {line}"),
+            },
+            Event::Done(id, best_move) if id == xboard.searches => xboard.engine_moved(best_move),
+            // a search that was called off
+            Event::Done(..) => {}
+        }
+        for reply in xboard.replies.drain(..) {
+            println!("{reply}");
+        }
+    }
+    xboard.stop();
+    Ok(())
+}
+
+struct Xboard {
+    board: BoardState,
+    history: Vec<Undo>,
+    /// Side the engine plays, `None` in force mode.
+    engine_white: Option<bool>,
+    tt: Arc<TranspositionTable>,
+    depth: Option<u32>,
+    /// Fixed time per move from `st`, otherwise the clock decides.
+    move_time: Option<Duration>,
+    moves_per_session: u32,
+    increment: Duration,
+    time_left: Duration,
+    /// The other side's clock from `otim`, if the GUI sends it.
+    opponent_time_left: Option<Duration>,
+    post: bool,
+    search: Option<Arc<AtomicBool>>,
+    searches: u64,
+    events: mpsc::Sender<Event>,
+    tablebases: Option<Arc<Tablebases>>,
+    threads: usize,
+    /// Lines for the GUI, written out once the command or search that produced them is done.
+    replies: Vec<String>,
+}
+
+impl Xboard {
+    fn new(events: mpsc::Sender<Event>) -> anyhow::Result<Self> {
+        Ok(Self {
+            board: BoardState::from_fen(STARTING_FEN)?,
+            history: Vec::new(),
+            engine_white: Some(false),
+            tt: Arc::new(TranspositionTable::new(tt::DEFAULT_MB)),
+            depth: None,
+            move_time: None,
+            moves_per_session: 0,
+            increment: Duration::ZERO,
+            time_left: Duration::from_secs(300),
+            opponent_time_left: None,
+            post: false,
+            search: None,
+            searches: 0,
+            events,
+            tablebases: None,
+            threads: 1,
+            replies: Vec::new(),
+        })
+    }
+
+    /// Handles one line of input, returns false on `quit`.
+    fn command(&mut self, line: &str) -> anyhow::Result<bool> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(true);
+        };
+        match command {
+            "protover" => self.replies.push(format!(
+                "feature myname=\"chess-gpu {}\" usermove=1 setboard=1 ping=1 playother=1 \
+                 sigint=0 sigterm=0 colors=0 analyze=0 variants=\"normal,capablanca,gothic\" \
+                 egt=\"syzygy\" smp=1 done=1",
+                env!("CARGO_PKG_VERSION")
+            )),
+            "new" => {
+                self.stop();
+                self.set_board(BoardState::from_fen(STARTING_FEN)?);
+                self.engine_white = Some(false);
+                self.depth = None;
+                self.move_time = None;
+                self.tt.clear();
+            }
+            "variant" => {
+                let name = match args.first() {
+                    Some(&"normal") => "standard",
+                    Some(name) => name,
+                    None => return Err(anyhow::anyhow!("missing variant")),
+                };
+                let fen = logic::variant_fen(name)
+                    .ok_or_else(|| anyhow::anyhow!("unknown variant {name}"))?;
+                self.stop();
+                self.set_board(BoardState::from_fen(fen)?);
+            }
+            "setboard" => {
+                self.stop();
+                self.set_board(BoardState::from_fen(&args.join(" "))?);
+            }
+            "force" => {
+                self.stop();
+                self.engine_white = None;
+            }
+            "go" => {
+                self.engine_white = Some(self.board.white_to_play);
+                self.think();
+            }
+            "playother" => {
+                self.engine_white = Some(!self.board.white_to_play);
+            }
+            "usermove" => {
+                let name = args.first().copied().unwrap_or_default();
+                let Some(mv) = self
+                    .board
+                    .parse_move(name)
+                    .or_else(|| self.board.parse_san(name))
+                else {
+                    self.replies.push(format!("Illegal move: {name}"));
+                    return Ok(true);
+                };
+                self.stop();
+                self.play(mv);
+                if self.engine_white == Some(self.board.white_to_play) {
+                    self.think();
+                }
+            }
+            "?" => {
+                if let Some(stop) = &self.search {
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+            "undo" => self.take_back(1),
+            "remove" => self.take_back(2),
+            "level" => {
+                let [moves, base, increment] = args[..] else {
+                    return Err(anyhow::anyhow!("level takes three values"));
+                };
+                self.moves_per_session = moves.parse()?;
+                // base is minutes, or minutes:seconds
+                let (minutes, seconds) = base.split_once(':').unwrap_or((base, "0"));
+                self.time_left =
+                    Duration::from_secs(minutes.parse::<u64>()? * 60 + seconds.parse::<u64>()?);
+                self.increment = Duration::from_secs_f64(increment.parse()?);
+                self.move_time = None;
+            }
+            "st" => self.move_time = Some(Duration::from_secs_f64(value(args)?.parse()?)),
+            "sd" => self.depth = Some(value(args)?.parse()?),
+            "time" => {
+                self.time_left = Duration::from_millis(value(args)?.parse::<u64>()? * 10);
+            }
+            "otim" => {
+                self.opponent_time_left =
+                    Some(Duration::from_millis(value(args)?.parse::<u64>()? * 10));
+            }
+            "egtpath" => match args {
+                ["syzygy", paths @ ..] => {
+                    self.stop();
+                    self.tablebases = Some(Arc::new(Tablebases::open(&paths.join(" "))?));
+                }
+                _ => return Err(anyhow::anyhow!("unsupported tablebases")),
+            },
+            "cores" => self.threads = value(args)?.parse()?,
+            "ping" => self.replies.push(format!("pong {}", args.join(" "))),
+            "result" => {
+                self.stop();
+                self.engine_white = None;
+            }
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "quit" => return Ok(false),
+            // xboard, accepted, rejected, hard, easy, random, computer, name, rating, draw
+            // and friends change nothing for us
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn set_board(&mut self, board: BoardState) {
+        self.board = board;
+        self.history.clear();
+    }
+
+    fn play(&mut self, mv: Move) {
+        self.history.push(self.board.make(mv));
+        if let Some(result) = self.result() {
+            self.replies.push(result);
+            self.engine_white = None;
+        }
+    }
+
+    fn take_back(&mut self, moves: usize) {
+        self.stop();
+        for _ in 0..moves {
+            if let Some(undo) = self.history.pop() {
+                self.board.unmake(undo);
+            }
+        }
+    }
+
+    fn think(&mut self) {
+        self.stop();
+        if self.result().is_some() {
+            return;
+        }
+        let limits = Limits {
+            depth: self.depth,
+            nodes: None,
+            time: Some(self.budget()),
+        };
+        let mut searcher = Searcher::with_table(limits, self.tt.clone())
+            .with_tablebases(self.tablebases.clone())
+            .with_threads(self.threads);
+        self.search = Some(searcher.stop_flag());
+        let id = self.searches;
+        let board = self.board.clone();
+        let events = self.events.clone();
+        let post = self.post;
+        std::thread::spawn(move || {
+            let result = searcher.search_with_report(&board, |result| {
+                if post {
+                    thinking(&board, result);
+                }
+            });
+            let _ = events.send(Event::Done(id, result.best_move));
+        });
+    }
+
+    fn engine_moved(&mut self, best_move: Option<Move>) {
+        self.search = None;
+        match best_move {
+            Some(mv) => {
+                self.replies
+                    .push(format!("move {}", self.board.move_name(mv)));
+                self.play(mv);
+            }
+            None => self.replies.push("resign".to_owned()),
+        }
+    }
+
+    /// Calls off a running search, whose move will be ignored.
+    fn stop(&mut self) {
+        if let Some(stop) = self.search.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        self.searches += 1;
+    }
+
+    fn budget(&self) -> Duration {
+        if let Some(time) = self.move_time {
+            return time;
+        }
+        let moves_left = match self.moves_per_session {
+            0 => 30,
+            session => session - (self.board.fullmove_number - 1) % session,
+        };
+        // a tenth of a lead on the opponent's clock is spent as well
+        let lead = self.opponent_time_left.map_or(Duration::ZERO, |opponent| {
+            self.time_left.saturating_sub(opponent)
+        });
+        (self.time_left / moves_left + self.increment * 3 / 4 + lead / 10)
+            .min(self.time_left.saturating_sub(MOVE_OVERHEAD))
+    }
+
+    /// The result line once the game is over.
+    fn result(&self) -> Option<String> {
+        if self.board.fog_of_war && self.board.king_square(self.board.white_to_play).is_none() {
+            return Some(if self.board.white_to_play {
+                "0-1 {Black captures the king}".to_owned()
+            } else {
+                "1-0 {White captures the king}".to_owned()
+            });
+        }
+        if self.board.moves().is_empty() {
+            return Some(match (self.board.in_check(), self.board.white_to_play) {
+                (true, true) => "0-1 {Black mates}".to_owned(),
+                (true, false) => "1-0 {White mates}".to_owned(),
+                (false, _) => "1/2-1/2 {Stalemate}".to_owned(),
+            });
+        }
+        if self.board.halfmove_clock >= 100 {
+            return Some("1/2-1/2 {50 move rule}".to_owned());
+        }
+        None
+    }
+}
+
+fn value<'a>(args: &[&'a str]) -> anyhow::Result<&'a str> {
+    args.first()
+        .copied()
+        .ok_or_else(|| anyhow::anyhow!("missing value"))
+}
+
+// `ply score time nodes pv`, with time in centiseconds and mates as 100000 + moves
+fn thinking(board: &BoardState, result: &SearchResult) {
+    let score = match result.mate_in() {
+        Some(moves) if moves > 0 => 100_000 + moves,
+        Some(moves) => -100_000 + moves,
+        None => result.score,
+    };
+    let pv = result
+        .pv
+        .iter()
+        .map(|&mv| board.move_name(mv))
+        .collect::<Vec<_>>()
+        .join(" ");
+    println!(
+        "{} {} {} {} {}",
+        result.depth,
+        score,
+        result.time.as_millis() / 10,
+        result.nodes,
+        pv
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs `lines` and returns what was replied
+    fn send(xboard: &mut Xboard, lines: &[&str]) -> Vec<String> {
+        for line in lines {
+            assert!(xboard.command(line).unwrap(), "{line}");
+        }
+        xboard.replies.drain(..).collect()
+    }
+
+    #[test]
+    fn plays_a_game() {
+        let (events, receiver) = mpsc::channel();
+        let mut xboard = Xboard::new(events).unwrap();
+        assert_eq!(
+            send(&mut xboard, &["new", "sd 2", "st 5", "ping 1"]),
+            ["pong 1"]
+        );
+        assert_eq!(xboard.depth, Some(2));
+        assert_eq!(xboard.move_time, Some(Duration::from_secs(5)));
+
+        // in force mode moves are only played along
+        assert!(send(&mut xboard, &["force", "usermove e2e4", "usermove e7e5"]).is_empty());
+        assert!(xboard.search.is_none());
+        let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2";
+        assert_eq!(xboard.board.to_fen(), fen);
+
+        assert_eq!(
+            send(&mut xboard, &["usermove e2e4"]),
+            ["Illegal move: e2e4"]
+        );
+        assert_eq!(xboard.board.to_fen(), fen);
+
+        send(&mut xboard, &["go"]);
+        assert_eq!(xboard.engine_white, Some(true));
+        let Event::Done(id, best_move) = receiver.recv().unwrap() else {
+            panic!("expected a search");
+        };
+        assert_eq!(id, xboard.searches);
+        let name = xboard.board.move_name(best_move.unwrap());
+        xboard.engine_moved(best_move);
+        assert_eq!(
+            xboard.replies.drain(..).collect::<Vec<_>>(),
+            [format!("move {name}")]
+        );
+        assert!(!xboard.board.white_to_play);
+
+        // the engine answers the next move, until the game is called
+        send(&mut xboard, &["usermove b8c6"]);
+        assert!(xboard.search.is_some());
+        send(&mut xboard, &["result 1/2-1/2 {Draw agreed}"]);
+        assert!(xboard.search.is_none());
+        assert_eq!(xboard.engine_white, None);
+        let Event::Done(id, _) = receiver.recv().unwrap() else {
+            panic!("expected a search");
+        };
+        assert_ne!(id, xboard.searches);
+
+        send(&mut xboard, &["new"]);
+        assert_eq!(xboard.board.to_fen(), STARTING_FEN);
+        assert_eq!((xboard.depth, xboard.move_time), (None, None));
+        assert_eq!(xboard.engine_white, Some(false));
+    }
+
+    #[test]
+    fn announces_the_result() {
+        let (events, _receiver) = mpsc::channel();
+        let mut xboard = Xboard::new(events).unwrap();
+        let replies = send(
+            &mut xboard,
+            &[
+                "new",
+                "force",
+                "setboard 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+                "usermove a1a8",
+            ],
+        );
+        assert_eq!(replies, ["1-0 {White mates}"]);
+        assert_eq!(xboard.engine_white, None);
+    }
+}
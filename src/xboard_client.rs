@@ -0,0 +1,341 @@
+use std::time::{Duration, Instant};
+
+use crate::logic::{BoardState, Move, STARTING_FEN};
+use crate::process::{EngineProcess, Interrupt};
+use crate::search::{Limits, MATE, MAX_PLY, SearchResult};
+
+// how long an engine may overrun its time limit before we ask it to move now
+const GRACE: Duration = Duration::from_secs(5);
+
+/// External engine process spoken to over CECP, the XBoard/WinBoard protocol.
+pub struct XboardEngine {
+    process: EngineProcess,
+    // features the engine asked for during `protover`
+    usermove: bool,
+    setboard: bool,
+}
+
+impl XboardEngine {
+    /// Starts the executable at `path` and negotiates features. Engines that don't answer
+    /// `protover 2` are taken to speak version 1 after two seconds.
+    pub fn spawn(path: &str) -> anyhow::Result<Self> {
+        let mut engine = Self {
+            process: EngineProcess::spawn(path, "quit")?,
+            usermove: false,
+            setboard: false,
+        };
+        engine.process.send("xboard")?;
+        engine.process.send("protover 2")?;
+
+        let mut timeout = Duration::from_secs(2);
+        let start = Instant::now();
+        while let Ok(line) = engine
+            .process
+            .read_line(timeout.saturating_sub(start.elapsed()))
+        {
+            let Some(features) = line.strip_prefix("feature ") else {
+                continue;
+            };
+            let mut done = false;
+            for (name, value) in parse_features(features) {
+                match (name.as_str(), value.as_str()) {
+                    ("done", "0") => timeout = Duration::from_secs(60 * 60),
+                    ("done", _) => done = true,
+                    ("myname", _) => engine.process.name = value.clone(),
+                    ("usermove", _) => engine.usermove = value == "1",
+                    ("setboard", _) => engine.setboard = value == "1",
+                    _ => {}
+                }
+                // nothing is turned down, features we don't use just go unused
+                engine.process.send(&format!("accepted {name}"))?;
+            }
+            if done {
+                break;
+            }
+        }
+        if engine.process.exited() {
+            return Err(anyhow::anyhow!("{} exited", engine.name()));
+        }
+        // no pondering, it would keep the engine busy between our requests
+        engine.process.send("easy")?;
+        Ok(engine)
+    }
+
+    /// What the engine calls itself.
+    pub fn name(&self) -> &str {
+        &self.process.name
+    }
+
+    /// Sends `?`, asking for the move now from another thread, ending the search `search` waits for.
+    pub fn interrupt(&self) -> Interrupt {
+        self.process.interrupt("?")
+    }
+
+    /// Tells the engine how the game ended, e.g. `1-0 {White mates}`.
+    pub fn result(&mut self, result: &str) -> anyhow::Result<()> {
+        self.process.send(&format!("result {result}"))
+    }
+
+    /// Sets up `start` followed by `moves`, leaving the engine in force mode.
+    pub fn position(&mut self, start: &BoardState, moves: &[Move]) -> anyhow::Result<()> {
+        self.process.send("new")?;
+        if start.width != 8 || start.height != 8 {
+            // gothic chess is capablanca chess from a different start
+            if (start.width, start.height) != (10, 8) {
+                return Err(anyhow::anyhow!(
+                    "CECP has no variant for {}x{} boards",
+                    start.width,
+                    start.height
+                ));
+            }
+            self.process.send("variant capablanca")?;
+        }
+        self.process.send("force")?;
+        let fen = start.to_fen();
+        if fen != STARTING_FEN || start.width != 8 {
+            if !self.setboard {
+                return Err(anyhow::anyhow!(
+                    "{} can only play from the start position",
+                    self.name()
+                ));
+            }
+            self.process.send(&format!("setboard {fen}"))?;
+        }
+        let mut board = start.clone();
+        for &mv in moves {
+            self.user_move(&board, mv)?;
+            board.make(mv);
+        }
+        Ok(())
+    }
+
+    /// Lets the engine move in `start` followed by `moves` and blocks until it does. Scores
+    /// and lines come from the last thinking output before the move.
+    pub fn search(
+        &mut self,
+        start: &BoardState,
+        moves: &[Move],
+        limits: Limits,
+    ) -> anyhow::Result<SearchResult> {
+        let mut board = start.clone();
+        for &mv in moves {
+            board.make(mv);
+        }
+        self.position(start, moves)?;
+        // both limits are sent every time so none carries over from the last search, with
+        // the deepest search and a day standing in for no limit
+        let depth = limits.depth.unwrap_or(MAX_PLY as u32);
+        self.process.send(&format!("sd {depth}"))?;
+        let time = limits.time.unwrap_or(Duration::from_secs(24 * 60 * 60));
+        // st only takes whole seconds, the clock is kept in centiseconds
+        self.process
+            .send(&format!("st {}", time.as_secs().max(1)))?;
+        let centiseconds = time.as_millis() / 10;
+        self.process.send(&format!("time {centiseconds}"))?;
+        self.process.send(&format!("otim {centiseconds}"))?;
+        self.process.send("post")?;
+        self.process.send("go")?;
+
+        let start_time = Instant::now();
+        let mut deadline = limits
+            .time
+            .map(|time| time.max(Duration::from_secs(1)) + GRACE);
+        let mut result = SearchResult::default();
+        loop {
+            let line = match deadline {
+                Some(timeout) => {
+                    match self
+                        .process
+                        .read_line(timeout.saturating_sub(start_time.elapsed()))
+                    {
+                        Ok(line) => line,
+                        Err(_) if !self.process.exited() => {
+                            self.process.send("?")?;
+                            deadline = None;
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                None => self.process.read_line(Duration::MAX)?,
+            };
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                ["move", name] => {
+                    let mv = parse_move(&board, name).ok_or_else(|| {
+                        anyhow::anyhow!("{} played illegal move {name}", self.name())
+                    })?;
+                    // keep it from moving for the other side as well
+                    self.process.send("force")?;
+                    result.best_move = Some(mv);
+                    if result.pv.first() != Some(&mv) {
+                        result.pv = vec![mv];
+                    }
+                    if result.time.is_zero() {
+                        result.time = start_time.elapsed();
+                    }
+                    return Ok(result);
+                }
+                ["resign", ..] | ["1-0" | "0-1" | "1/2-1/2", ..] => {
+                    self.process.send("force")?;
+                    result.best_move = None;
+                    return Ok(result);
+                }
+                [first, ..] if first.starts_with("Illegal") || first.starts_with("Error") => {
+                    return Err(anyhow::anyhow!("{}: {line}", self.name()));
+                }
+                [depth, score, time, nodes, pv @ ..]
+                    if depth.trim_end_matches(['.', '&']).parse::<u32>().is_ok() =>
+                {
+                    parse_thinking(&board, [depth, score, time, nodes], pv, &mut result);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn user_move(&mut self, board: &BoardState, mv: Move) -> anyhow::Result<()> {
+        let name = board.move_name(mv);
+        if self.usermove {
+            self.process.send(&format!("usermove {name}"))
+        } else {
+            self.process.send(&name)
+        }
+    }
+}
+
+// `key=value key="quoted value"`
+fn parse_features(text: &str) -> Vec<(String, String)> {
+    let mut features = Vec::new();
+    let mut rest = text.trim();
+    while let Some((name, after)) = rest.split_once('=') {
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => after.split_once(' ').unwrap_or((after, "")),
+        };
+        features.push((name.trim().to_owned(), value.to_owned()));
+        rest = after.trim_start();
+    }
+    features
+}
+
+// engines may answer in coordinates or in SAN
+fn parse_move(board: &BoardState, name: &str) -> Option<Move> {
+    board.parse_move(name).or_else(|| board.parse_san(name))
+}
+
+// `ply score time nodes pv`, time in centiseconds and mates as 100000 + moves
+fn parse_thinking(board: &BoardState, fields: [&str; 4], pv: &[&str], result: &mut SearchResult) {
+    let [depth, score, time, nodes] = fields.map(|field| {
+        field
+            .trim_end_matches(['.', '&'])
+            .parse::<i64>()
+            .unwrap_or(0)
+    });
+    result.depth = depth as u32;
+    result.score = match score {
+        100_000.. => MATE - (2 * (score - 100_000) as i32 - 1),
+        ..=-100_000 => -MATE + 2 * (-score - 100_000) as i32,
+        _ => score as i32,
+    };
+    result.time = Duration::from_millis(time as u64 * 10);
+    result.nodes = nodes as u64;
+
+    let mut board = board.clone();
+    result.pv.clear();
+    // move numbers like `12.` or `12...` may be mixed in
+    for name in pv.iter().filter(|name| !name.ends_with('.')) {
+        let Some(mv) = parse_move(&board, name) else {
+            break;
+        };
+        result.pv.push(mv);
+        board.make(mv);
+    }
+    result.best_move = result.pv.first().copied();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::process::tests::stand_in;
+
+    // asks for usermove and setboard, thinks once and answers in SAN
+    const ENGINE: &str = r#"case "$line" in
+"protover 2") echo 'feature myname="Stand-in 2.0" usermove=1 setboard=1 done=1' ;;
+go) echo "3 34 5 400 1... e5 2. Nf3"
+    echo "move e5" ;;
+quit) exit 0 ;;
+esac"#;
+
+    #[test]
+    fn talks_to_an_xboard_engine() {
+        let path = stand_in("xboard-stand-in", ENGINE);
+        let start = BoardState::from_fen(STARTING_FEN).unwrap();
+        let e4 = start.parse_move("e2e4").unwrap();
+        let mut after = start.clone();
+        after.make(e4);
+        let e5 = after.parse_move("e7e5").unwrap();
+        {
+            let mut engine = XboardEngine::spawn(path.to_str().unwrap()).unwrap();
+            assert_eq!(engine.name(), "Stand-in 2.0");
+            assert!(engine.usermove && engine.setboard);
+
+            let limits = Limits {
+                time: Some(Duration::from_millis(2500)),
+                ..Limits::depth(3)
+            };
+            let result = engine.search(&start, &[e4], limits).unwrap();
+            assert_eq!(result.best_move, Some(e5));
+            assert_eq!(result.score, 34);
+            assert_eq!((result.depth, result.nodes), (3, 400));
+            assert_eq!(result.time, Duration::from_millis(50));
+            let mut line = after.clone();
+            line.make(e5);
+            assert_eq!(result.pv, vec![e5, line.parse_move("g1f3").unwrap()]);
+
+            // the limits of the first search don't carry over
+            let result = engine.search(&start, &[e4], Limits::default()).unwrap();
+            assert_eq!(result.best_move, Some(e5));
+            engine.result("1-0 {White wins}").unwrap();
+        }
+
+        let log = std::fs::read_to_string(path.with_extension("log")).unwrap();
+        let commands = log.lines().collect::<Vec<_>>();
+        assert_eq!(
+            commands,
+            [
+                "xboard",
+                "protover 2",
+                "accepted myname",
+                "accepted usermove",
+                "accepted setboard",
+                "accepted done",
+                "easy",
+                "new",
+                "force",
+                "usermove e2e4",
+                "sd 3",
+                "st 2",
+                "time 250",
+                "otim 250",
+                "post",
+                "go",
+                "force",
+                "new",
+                "force",
+                "usermove e2e4",
+                "sd 128",
+                "st 86400",
+                "time 8640000",
+                "otim 8640000",
+                "post",
+                "go",
+                "force",
+                "result 1-0 {White wins}",
+                "quit",
+            ]
+        );
+        let _ = std::fs::remove_file(path.with_extension("log"));
+        let _ = std::fs::remove_file(path);
+    }
+}
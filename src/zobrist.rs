@@ -0,0 +1,97 @@
+use crate::logic::{BoardState, MAX_SQUARES, Piece};
+
+// keys are derived on the fly so fairy pieces registered at any time get their own
+fn key(index: u64) -> u64 {
+    // splitmix64
+    let mut z = index.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const SIDE: u64 = 0;
+const CASTLING: u64 = 1;
+const EN_PASSANT: u64 = CASTLING + 4;
+const PIECES: u64 = EN_PASSANT + MAX_SQUARES as u64;
+
+impl BoardState {
+    /// Zobrist hash of everything that decides which moves are legal, i.e. the first four FEN
+    /// fields.
+    pub fn hash(&self) -> u64 {
+        self.key
+    }
+
+    /// Recomputes `key` from scratch, for boards set up or edited by hand.
+    pub fn rehash(&mut self) {
+        self.key = self.state_key();
+        for square in 0..self.pieces.len() as u32 {
+            self.key ^= Self::piece_key(square, self.pieces[square as usize]);
+        }
+    }
+
+    /// The part of the hash that isn't pieces: side to play, castling and en passant.
+    pub fn state_key(&self) -> u64 {
+        let mut hash = if self.white_to_play { 0 } else { key(SIDE) };
+        let castling = [
+            self.white_can_oo,
+            self.white_can_ooo,
+            self.black_can_oo,
+            self.black_can_ooo,
+        ];
+        for (i, _) in castling.iter().enumerate().filter(|(_, allowed)| **allowed) {
+            hash ^= key(CASTLING + i as u64);
+        }
+        if let Some(square) = self.en_passant_square {
+            hash ^= key(EN_PASSANT + square as u64);
+        }
+        hash
+    }
+
+    /// The key of `piece` standing on `square`, nothing for an empty square.
+    pub fn piece_key(square: u32, piece: Option<Piece>) -> u64 {
+        piece.map_or(0, |piece| {
+            key(PIECES + piece.to_idx() as u64 * MAX_SQUARES as u64 + square as u64)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logic::BoardState;
+
+    // every move of the tree below `board`, checking the incremental key against a fresh one
+    fn walk(board: &mut BoardState, depth: u32) {
+        if depth == 0 {
+            return;
+        }
+        for mv in board.moves() {
+            let before = board.hash();
+            let undo = board.make(mv);
+            let mut fresh = board.clone();
+            fresh.rehash();
+            assert_eq!(
+                board.hash(),
+                fresh.hash(),
+                "{} after {mv:?}",
+                board.to_fen()
+            );
+            walk(board, depth - 1);
+            board.unmake(undo);
+            assert_eq!(board.hash(), before);
+        }
+    }
+
+    #[test]
+    fn incremental_key_matches_rehash() {
+        for fen in [
+            // castling both ways, rooks captured in their corners
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            // en passant
+            "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+            // promotions with and without capture
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ] {
+            walk(&mut BoardState::from_fen(fen).unwrap(), 3);
+        }
+    }
+}
@@ -0,0 +1,531 @@
+#!/usr/bin/env python3
+"""Writes the KQvK, KRvK and KPvK Syzygy tables `src/syzygy.rs` is tested against.
+
+Every position is solved from scratch by retrograde analysis, then stored in the .rtbw/.rtbz
+layout with the real index encoding. Values are packed with fixed length codes rather than the
+Huffman pairs of the real generator, which the format allows and keeps this short; the files
+come out larger than the official ones but decode the same way.
+
+    python3 tests/fixtures/syzygy/generate.py
+"""
+
+import os
+import struct
+from collections import defaultdict
+
+WDL_MAGIC = b"\x71\xe8\x23\x5d"
+DTZ_MAGIC = b"\xd7\x66\x0c\xa5"
+STM, WIN_PLIES, LOSS_PLIES, SINGLE_VALUE = 1, 4, 8, 128
+
+PAWN, ROOK, QUEEN, KING, BLACK = 1, 4, 5, 6, 8
+NAMES = {QUEEN: "KQvK", ROOK: "KRvK", PAWN: "KPvK"}
+
+
+def file_of(square):
+    return square & 7
+
+
+def rank_of(square):
+    return square >> 3
+
+
+def adjacent(a, b):
+    return max(abs(file_of(a) - file_of(b)), abs(rank_of(a) - rank_of(b))) <= 1
+
+
+KING_MOVES = [
+    [t for t in range(64) if t != s and adjacent(s, t)] for s in range(64)
+]
+ROOK_DIRECTIONS = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+QUEEN_DIRECTIONS = ROOK_DIRECTIONS + [(1, 1), (1, -1), (-1, 1), (-1, -1)]
+
+
+def rays(square, piece):
+    directions = QUEEN_DIRECTIONS if piece == QUEEN else ROOK_DIRECTIONS
+    for df, dr in directions:
+        f, r = file_of(square) + df, rank_of(square) + dr
+        ray = []
+        while 0 <= f < 8 and 0 <= r < 8:
+            ray.append(r * 8 + f)
+            f, r = f + df, r + dr
+        yield ray
+
+
+def attacks(square, piece, blocker):
+    """Squares the white piece on `square` attacks, with only the white king in the way."""
+    if piece == PAWN:
+        targets = []
+        if rank_of(square) < 7:
+            if file_of(square) > 0:
+                targets.append(square + 7)
+            if file_of(square) < 7:
+                targets.append(square + 9)
+        return targets
+    targets = []
+    for ray in rays(square, piece):
+        for target in ray:
+            targets.append(target)
+            if target == blocker:
+                break
+    return targets
+
+
+def position(wk, wp, bk, white_to_play):
+    return ((wk * 64 + wp) * 64 + bk) * 2 + (0 if white_to_play else 1)
+
+
+def legal(wk, wp, bk, white_to_play, piece):
+    if len({wk, wp, bk}) < 3 or adjacent(wk, bk):
+        return False
+    if piece == PAWN and rank_of(wp) in (0, 7):
+        return False
+    # the side that just moved can't have left its king attacked
+    return not white_to_play or bk not in attacks(wp, piece, wk)
+
+
+def white_moves(wk, wp, bk, piece):
+    """(position after, promotion or None) for every legal white move."""
+    moves = []
+    for target in KING_MOVES[wk]:
+        if target != wp and not adjacent(target, bk):
+            moves.append(((target, wp, bk), None))
+    if piece == PAWN:
+        pushes = [wp + 8]
+        if rank_of(wp) == 1 and wp + 8 not in (wk, bk):
+            pushes.append(wp + 16)
+        for target in pushes:
+            if target in (wk, bk):
+                continue
+            if rank_of(target) == 7:
+                for promotion in (QUEEN, ROOK, 3, 2):
+                    moves.append(((wk, target, bk), promotion))
+            else:
+                moves.append(((wk, target, bk), None))
+    else:
+        for ray in rays(wp, piece):
+            for target in ray:
+                if target in (wk, bk):
+                    break
+                moves.append(((wk, target, bk), None))
+    return moves
+
+
+def black_moves(wk, wp, bk, piece):
+    """Positions after every legal black move, `None` for taking the white piece."""
+    moves = []
+    attacked = attacks(wp, piece, wk)
+    for target in KING_MOVES[bk]:
+        if adjacent(target, wk):
+            continue
+        if target == wp:
+            moves.append(None)
+        elif target not in attacked:
+            moves.append((wk, wp, target))
+    return moves
+
+
+def solve(piece, promotions):
+    """WDL, plies to mate and, for pawns, plies to the next pawn move of every position.
+
+    `promotions` maps a promoted piece to the solved table it leads to, pieces without one
+    only draw.
+    """
+    size = 64 * 64 * 64 * 2
+    wdl = [0] * size
+    dtm = [0] * size
+    predecessors = defaultdict(list)
+    remaining = [0] * size
+    can_draw = [False] * size
+    mated = []
+    # white positions winning by promoting, by ply
+    promotion_wins = defaultdict(list)
+    # black positions a pawn move or promotion from white wins, for DTZ
+    zeroing = defaultdict(list)
+    legal_positions = []
+
+    for wk in range(64):
+        for wp in range(64):
+            for bk in range(64):
+                for white_to_play in (True, False):
+                    if not legal(wk, wp, bk, white_to_play, piece):
+                        continue
+                    here = position(wk, wp, bk, white_to_play)
+                    legal_positions.append((here, wk, wp, bk, white_to_play))
+                    if white_to_play:
+                        for after, promotion in white_moves(wk, wp, bk, piece):
+                            if promotion is None:
+                                after_position = position(*after, False)
+                                predecessors[after_position].append(here)
+                                if piece == PAWN and after[1] != wp:
+                                    zeroing[after_position].append(here)
+                            elif promotion in promotions:
+                                table = promotions[promotion]
+                                value = table["wdl"][position(*after, False)]
+                                if value == -2:
+                                    plies = table["dtm"][position(*after, False)] + 1
+                                    promotion_wins[plies].append((here, True))
+                    else:
+                        moves = black_moves(wk, wp, bk, piece)
+                        remaining[here] = len(moves)
+                        can_draw[here] = None in moves
+                        for after in moves:
+                            if after is not None:
+                                predecessors[position(*after, True)].append(here)
+                        in_check = bk in attacks(wp, piece, wk)
+                        if not moves and in_check:
+                            mated.append(here)
+
+    # plies to mate, losses even and wins odd
+    current = mated
+    for black in mated:
+        wdl[black] = -2
+    plies = 0
+    while current or any(ply > plies for ply in promotion_wins):
+        following = []
+        if plies % 2 == 0:
+            for black in current:
+                for white in predecessors[black]:
+                    if wdl[white] == 0:
+                        wdl[white] = 2
+                        dtm[white] = plies + 1
+                        following.append(white)
+            for white, _ in promotion_wins.get(plies + 1, []):
+                if wdl[white] == 0:
+                    wdl[white] = 2
+                    dtm[white] = plies + 1
+                    following.append(white)
+        else:
+            for white in current:
+                for black in predecessors[white]:
+                    if wdl[black] != 0 or can_draw[black]:
+                        continue
+                    remaining[black] -= 1
+                    if remaining[black] == 0:
+                        wdl[black] = -2
+                        dtm[black] = plies + 1
+                        following.append(black)
+        current = following
+        plies += 1
+
+    table = {"wdl": wdl, "dtm": dtm, "positions": legal_positions}
+    table["dtz"] = dtm if piece != PAWN else distance_to_zeroing(
+        wdl, predecessors, mated, promotion_wins, zeroing, legal_positions, piece
+    )
+    return table
+
+
+def distance_to_zeroing(wdl, predecessors, mated, promotion_wins, zeroing, positions, piece):
+    """Plies to mate or the next winning pawn move, the loser holding out as long as it can."""
+    dtz = [0] * len(wdl)
+    done = [False] * len(wdl)
+    remaining = [0] * len(wdl)
+    current = []
+    for black in mated:
+        done[black] = True
+        current.append(black)
+    winners = []
+    for black, whites in zeroing.items():
+        if wdl[black] == -2:
+            winners.extend(whites)
+    for whites in promotion_wins.values():
+        winners.extend(white for white, _ in whites)
+    for here, wk, wp, bk, white_to_play in positions:
+        if not white_to_play and wdl[here] == -2:
+            remaining[here] = len(black_moves(wk, wp, bk, piece))
+
+    distance = 0
+    pending = [white for white in winners if not done[white]]
+    for white in pending:
+        done[white] = True
+        dtz[white] = 1
+    while current or pending:
+        following = []
+        if distance % 2 == 0:
+            following.extend(pending)
+            pending = []
+            for black in current:
+                for white in predecessors[black]:
+                    if not done[white] and wdl[white] == 2:
+                        done[white] = True
+                        dtz[white] = distance + 1
+                        following.append(white)
+        else:
+            for white in current:
+                for black in predecessors[white]:
+                    if done[black] or wdl[black] != -2:
+                        continue
+                    remaining[black] -= 1
+                    if remaining[black] == 0:
+                        done[black] = True
+                        dtz[black] = distance + 1
+                        following.append(black)
+        current = following
+        distance += 1
+    return dtz
+
+
+# the index encoding, as in `src/syzygy.rs` and Stockfish's tbprobe
+
+
+def off_diagonal(square):
+    return rank_of(square) - file_of(square)
+
+
+def make_maps():
+    binomial = [[0] * 64 for _ in range(6)]
+    binomial[0][0] = 1
+    for n in range(1, 64):
+        for k in range(min(6, n + 1)):
+            binomial[k][n] = (binomial[k - 1][n - 1] if k > 0 else 0) + (
+                binomial[k][n - 1] if k < n else 0
+            )
+
+    pawns = [0] * 64
+    lead_pawn_idx = [[0] * 64 for _ in range(6)]
+    lead_pawns_size = [[0] * 4 for _ in range(6)]
+    available = 48
+    for lead_pawns in range(1, 6):
+        for file in range(4):
+            idx = 0
+            for rank in range(1, 7):
+                square = rank * 8 + file
+                if lead_pawns == 1:
+                    available -= 2
+                    pawns[square] = available + 1
+                    pawns[square ^ 7] = available
+                lead_pawn_idx[lead_pawns][square] = idx
+                idx += binomial[lead_pawns - 1][pawns[square]]
+            lead_pawns_size[lead_pawns][file] = idx
+
+    b1h1h7 = [0] * 64
+    code = 0
+    for square in range(64):
+        if off_diagonal(square) < 0:
+            b1h1h7[square] = code
+            code += 1
+
+    a1d1d4 = [0] * 64
+    code = 0
+    diagonal = []
+    for square in range(28):
+        if file_of(square) > 3:
+            continue
+        if off_diagonal(square) < 0:
+            a1d1d4[square] = code
+            code += 1
+        elif off_diagonal(square) == 0:
+            diagonal.append(square)
+    for square in diagonal:
+        a1d1d4[square] = code
+        code += 1
+
+    return {
+        "binomial": binomial,
+        "pawns": pawns,
+        "lead_pawn_idx": lead_pawn_idx,
+        "lead_pawns_size": lead_pawns_size,
+        "b1h1h7": b1h1h7,
+        "a1d1d4": a1d1d4,
+    }
+
+
+MAPS = make_maps()
+
+
+def groups(piece):
+    """Per leading pawn file, the factor each group's index is multiplied by and the size."""
+    result = []
+    for file in range(4 if piece == PAWN else 1):
+        if piece == PAWN:
+            # the pawn, then each king on its own
+            sizes = [MAPS["lead_pawns_size"][1][file], 63, 62]
+        else:
+            # both kings and the piece together
+            sizes = [31332]
+        factor = 1
+        factors = []
+        for size in sizes:
+            factors.append(factor)
+            factor *= size
+        result.append((factors, factor))
+    return result
+
+
+def index(wk, wp, bk, piece):
+    """(file, index) of a position with white to move or not, which the index doesn't see."""
+    maps = MAPS
+    if piece == PAWN:
+        # pawn, white king, black king
+        squares = [wp, wk, bk]
+        file = file_of(wp)
+        if file > 3:
+            squares = [square ^ 7 for square in squares]
+            file = 7 - file
+        idx = maps["lead_pawn_idx"][1][squares[0]]
+        factors, _ = groups(piece)[file]
+        idx *= factors[0]
+        start = 1
+        for group in range(1, 3):
+            square = squares[start]
+            adjust = sum(square > s for s in squares[:start])
+            idx += maps["binomial"][1][square - adjust] * factors[group]
+            start += 1
+        return file, idx
+
+    # white king, piece, black king
+    squares = [wk, wp, bk]
+    if file_of(squares[0]) > 3:
+        squares = [square ^ 7 for square in squares]
+    if rank_of(squares[0]) > 3:
+        squares = [square ^ 56 for square in squares]
+    for i in range(3):
+        if off_diagonal(squares[i]) == 0:
+            continue
+        if off_diagonal(squares[i]) > 0:
+            squares = squares[:i] + [((s >> 3) | (s << 3)) & 63 for s in squares[i:]]
+        break
+    s0, s1, s2 = squares
+    adjust1 = int(s1 > s0)
+    adjust2 = int(s2 > s0) + int(s2 > s1)
+    if off_diagonal(s0) != 0:
+        idx = (maps["a1d1d4"][s0] * 63 + s1 - adjust1) * 62 + s2 - adjust2
+    elif off_diagonal(s1) != 0:
+        idx = (6 * 63 + rank_of(s0) * 28 + maps["b1h1h7"][s1]) * 62 + s2 - adjust2
+    elif off_diagonal(s2) != 0:
+        idx = (
+            6 * 63 * 62
+            + 4 * 28 * 62
+            + rank_of(s0) * 7 * 28
+            + (rank_of(s1) - adjust1) * 28
+            + maps["b1h1h7"][s2]
+        )
+    else:
+        idx = (
+            6 * 63 * 62
+            + 4 * 28 * 62
+            + 4 * 7 * 28
+            + rank_of(s0) * 7 * 6
+            + (rank_of(s1) - adjust1) * 6
+            + (rank_of(s2) - adjust2)
+        )
+    return 0, idx
+
+
+# the file layout
+
+
+def encode(values, flags, block_exp=10, span_exp=10):
+    """Header, sparse index, block lengths and blocks of one sub-table."""
+    symbols = sorted(set(values))
+    if len(symbols) == 1:
+        return bytes([flags | SINGLE_VALUE, symbols[0]]), b"", b"", b""
+    bits = max(1, (len(symbols) - 1).bit_length())
+    code = {value: i for i, value in enumerate(symbols)}
+    per_block = (8 << block_exp) // bits
+    span = 1 << span_exp
+    sparse_entries = -(-len(values) // span)
+    last = (sparse_entries - 1) * span + span // 2
+    blocks = max(-(-len(values) // per_block), last // per_block + 1)
+
+    header = bytes([flags, block_exp, span_exp, 0]) + struct.pack("<I", blocks)
+    # one code length, so the lowest symbol of it is the first
+    header += bytes([bits, bits]) + struct.pack("<H", 0)
+    header += struct.pack("<H", len(symbols))
+    for value in symbols:
+        # a leaf: the value on the left, 0xFFF on the right
+        header += bytes([value & 0xFF, ((value >> 8) & 0xF) | 0xF0, 0xFF])
+    header += b"\0" * (len(symbols) & 1)
+
+    sparse = b"".join(
+        struct.pack("<IH", middle // per_block, middle % per_block)
+        for middle in (k * span + span // 2 for k in range(sparse_entries))
+    )
+    lengths = struct.pack("<H", per_block - 1) * blocks
+    data = b""
+    for block in range(blocks):
+        chunk = values[block * per_block : (block + 1) * per_block]
+        number = 0
+        for value in chunk:
+            number = (number << bits) | code[value]
+        number <<= (8 << block_exp) - bits * len(chunk)
+        data += number.to_bytes(1 << block_exp, "big")
+    return header, sparse, lengths, data
+
+
+def write(path, magic, piece, items):
+    """`items[file]` holds a list of (flags, values) per side to move stored."""
+    pieces = [PAWN, KING, KING | BLACK] if piece == PAWN else [KING, piece, KING | BLACK]
+    out = bytearray(magic)
+    sides = len(items[0])
+    out.append((2 if piece == PAWN else 0) | (1 if sides == 2 else 0))
+    for _ in items:
+        out.append(0)
+        out.extend(code | code << 4 for code in pieces)
+    out += b"\0" * (len(out) & 1)
+    parts = [encode(values, flags) for file in items for flags, values in file]
+    for header, _, _, _ in parts:
+        out += header
+    if magic == DTZ_MAGIC:
+        out += b"\0" * (len(out) & 1)
+    for _, sparse, _, _ in parts:
+        out += sparse
+    for _, _, lengths, _ in parts:
+        out += lengths
+    for _, _, _, data in parts:
+        out += b"\0" * (-len(out) % 64)
+        out += data
+    with open(path, "wb") as f:
+        f.write(out)
+
+
+def tables(piece, table, dtz_side):
+    files = groups(piece)
+    wdl = [[[2] * size for side in range(2)] for _, size in files]
+    dtz = [[0] * size for _, size in files]
+    seen = {}
+    for here, wk, wp, bk, white_to_play in table["positions"]:
+        file, idx = index(wk, wp, bk, piece)
+        side = 0 if white_to_play else 1
+        value = table["wdl"][here]
+        plies = table["dtz"][here]
+        key = (file, side, idx)
+        # mirror images share an index, and must agree on everything stored there
+        if seen.setdefault(key, (value, plies)) != (value, plies):
+            raise ValueError(f"{NAMES[piece]}: index {key} stores two positions")
+        wdl[file][side][idx] = value + 2
+        if side == dtz_side and value != 0:
+            # mated positions are stored as losing in one ply
+            dtz[file][idx] = max(plies - 1, 0)
+    flags = WIN_PLIES | LOSS_PLIES | (STM if dtz_side == 1 else 0)
+    return (
+        [[(0, values) for values in sides] for sides in wdl],
+        [[(flags, values)] for values in dtz],
+    )
+
+
+def main():
+    directory = os.path.dirname(os.path.abspath(__file__))
+    solved = {}
+    for piece in (QUEEN, ROOK, PAWN):
+        promotions = {p: solved[p] for p in (QUEEN, ROOK) if p in solved}
+        table = solve(piece, promotions if piece == PAWN else {})
+        solved[piece] = table
+        # even positions have white to move
+        longest = max(table["dtm"][::2])
+        print(f"{NAMES[piece]}: longest mate {longest} plies")
+
+        # KPvK stores black to move, to cover losses in DTZ tables too
+        dtz_side = 1 if piece == PAWN else 0
+        wdl, dtz = tables(piece, table, dtz_side)
+        name = os.path.join(directory, NAMES[piece])
+        write(name + ".rtbw", WDL_MAGIC, piece, wdl)
+        write(name + ".rtbz", DTZ_MAGIC, piece, dtz)
+
+    # known lengths of the longest mates: 10 moves with a queen, 16 with a rook, 28 with a pawn
+    assert max(solved[QUEEN]["dtm"][::2]) == 19
+    assert max(solved[ROOK]["dtm"][::2]) == 31
+    assert max(solved[PAWN]["dtm"][::2]) == 55
+
+
+if __name__ == "__main__":
+    main()